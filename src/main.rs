@@ -3,27 +3,44 @@
 //! **How it works (high level):**
 //! 1. We put the terminal in "raw mode" so we get key events instead of line buffering.
 //! 2. We run a loop: read input → update app state → draw UI → repeat until quit.
-//! 3. Ratatui doesn't own the terminal; we just draw into a buffer and then flush it to stdout.
+//! 3. Ratatui doesn't own the terminal; we just draw into a buffer and then flush it to stderr, which
+//!    keeps stdout free for the path printed by "quit and emit" (Q).
 
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs;
-use std::io::{self, Stdout};
+use std::io::{self, BufWriter, IsTerminal, Stderr};
 use std::path::PathBuf;
-use std::sync::OnceLock;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
 
-use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::event::{
+    self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers, MouseButton,
+    MouseEvent, MouseEventKind,
+};
 use crossterm::execute;
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use pulldown_cmark::{Event as MdEvent, Options, Parser, Tag, TagEnd};
 use ratatui::backend::CrosstermBackend;
-use ratatui::layout::{Constraint, Layout};
+use ratatui::layout::{Constraint, Flex, Layout, Margin, Rect};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span, Text};
-use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, Wrap};
+use ratatui::widgets::{
+    Block, Borders, Clear, List, ListItem, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, Wrap,
+};
 use ratatui::Frame;
+use ratatui_image::picker::{Picker, ProtocolType};
+use ratatui_image::protocol::StatefulProtocol;
+use ratatui_image::StatefulImage;
+use serde::{Deserialize, Serialize};
 use syntect::easy::HighlightLines;
-use syntect::highlighting::{FontStyle, ThemeSet};
-use syntect::parsing::SyntaxSet;
+use syntect::highlighting::{FontStyle, Theme, ThemeSet};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
 use syntect::util::LinesWithEndings;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 fn syntax_set() -> &'static SyntaxSet {
     static SET: OnceLock<SyntaxSet> = OnceLock::new();
@@ -35,6 +52,19 @@ fn theme_set() -> &'static ThemeSet {
     SET.get_or_init(ThemeSet::load_defaults)
 }
 
+/// Theme used until the user picks another one.
+const DEFAULT_THEME: &str = "base16-eighties.dark";
+
+/// Look up a highlighting theme by name, falling back to the default (or any) theme if it's unknown.
+fn find_theme(name: &str) -> &'static Theme {
+    let ts = theme_set();
+    ts.themes
+        .get(name)
+        .or_else(|| ts.themes.get(DEFAULT_THEME))
+        .or_else(|| ts.themes.values().next())
+        .expect("theme set has at least one theme")
+}
+
 /// Convert syntect highlighting color to ratatui Color (avoids syntect-tui version mismatch with ratatui 0.30).
 fn syntect_color_to_ratatui(c: syntect::highlighting::Color) -> Option<Color> {
     if c.a == 0 {
@@ -61,320 +91,6262 @@ fn syntect_font_style_to_modifier(f: FontStyle) -> Modifier {
 /// One entry in the current directory (file or directory).
 #[derive(Clone)]
 struct DirEntry {
+    /// Path relative to `cwd`: the file name, or `dir/child` for entries shown beneath an
+    /// expanded directory in the tree.
     name: String,
+    /// Nesting level in the tree; 0 for entries directly in `cwd`.
+    depth: usize,
     is_dir: bool,
+    /// Size in bytes (0 for directories or when metadata is unavailable).
+    size: u64,
+    /// Last modification time, if the platform reports it.
+    modified: Option<SystemTime>,
+    /// Unix permission bits (`st_mode`); None on other platforms or when metadata is unavailable.
+    mode: Option<u32>,
+    /// True if the entry's permissions mark it read-only.
+    readonly: bool,
+    /// True if the platform marks the entry hidden (the Windows hidden attribute; dotfiles go by name).
+    hidden_attribute: bool,
+    /// True if the entry itself is a symbolic link; the other fields then describe its target.
+    is_symlink: bool,
+    /// Where the entry points, if it is a symbolic link.
+    link_target: Option<PathBuf>,
+}
+
+impl DirEntry {
+    /// The last component of `name`, for display and name search.
+    fn file_name(&self) -> &str {
+        std::path::Path::new(&self.name)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(&self.name)
+    }
+
+    /// True for a regular file (not a link) with an execute bit set for anyone, as `ls -F` marks
+    /// with `*`. Always false where there are no mode bits.
+    fn is_executable(&self) -> bool {
+        !self.is_symlink && self.mode.is_some_and(|mode| mode & 0o170000 == 0o100000 && mode & 0o111 != 0)
+    }
 }
 
 /// Preview mode for files.
 #[derive(Clone, Copy, PartialEq)]
 enum PreviewMode {
     Raw,      // Syntax highlighted source
-    Rendered, // Markdown rendered view (only for .md files)
+    Rendered, // Markdown rendered view (.md files), or pretty-printed JSON (.json files)
 }
 
-/// All state the UI needs to render and react to input.
-struct App {
-    /// Current directory we're showing.
-    cwd: PathBuf,
-    /// Entries in `cwd` (directories first, then files). Sorted by name (case-insensitive).
-    entries: Vec<DirEntry>,
-    /// Index into `entries` that is currently selected. 0 when list is empty.
-    selected: usize,
-    /// When false, entries whose name starts with '.' are hidden (except "..").
-    show_hidden: bool,
-    /// When Some, the preview panel is open showing this file's path and cached content.
-    preview_path: Option<PathBuf>,
-    /// Cached preview as styled lines (metadata + content). Set when preview_path is set.
-    preview_content: Option<Vec<Line<'static>>>,
-    /// Vertical scroll offset for the preview (number of lines scrolled down).
-    preview_scroll: usize,
-    /// Maximum scroll value for current preview (updated during rendering).
-    preview_scroll_max: usize,
-    /// True when the preview only shows the first part of the file (file exceeded limit).
-    preview_truncated: bool,
-    /// Current preview mode (raw or rendered).
-    preview_mode: PreviewMode,
+/// Order of entries within the directory and file groups.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SortMode {
+    Name,     // A → Z
+    Size,     // Largest first
+    Modified, // Newest first
 }
 
-impl App {
-    fn new(initial_cwd: PathBuf) -> Self {
-        let mut app = App {
-            cwd: initial_cwd,
-            entries: Vec::new(),
-            selected: 0,
-            show_hidden: false,
-            preview_path: None,
-            preview_content: None,
-            preview_scroll: 0,
-            preview_scroll_max: 0,
-            preview_truncated: false,
-            preview_mode: PreviewMode::Raw,
-        };
-        app.refresh_entries();
-        app
+impl SortMode {
+    /// Next mode in the `s` cycle.
+    fn next(self) -> Self {
+        match self {
+            SortMode::Name => SortMode::Size,
+            SortMode::Size => SortMode::Modified,
+            SortMode::Modified => SortMode::Name,
+        }
     }
 
-    /// Re-read the current directory and set `entries`. Resets selection to 0 and clamps if needed.
-    fn refresh_entries(&mut self) {
-        let mut entries = read_dir_entries(&self.cwd);
-        if !self.show_hidden {
-            entries.retain(|e| e.name == ".." || !e.name.starts_with('.'));
+    /// Short label for the path bar title.
+    fn label(self) -> &'static str {
+        match self {
+            SortMode::Name => "name",
+            SortMode::Size => "size",
+            SortMode::Modified => "modified",
         }
-        self.entries = entries;
-        // Clamp selection so we don't point past the end after refresh (e.g. after going up).
-        self.selected = self.selected.min(self.entries.len().saturating_sub(1));
     }
+}
 
-    /// Move selection up by one, wrapping to bottom if at top.
-    fn selection_up(&mut self) {
-        if self.entries.is_empty() {
-            return;
+/// Where hidden (dot) entries go within the directory and file groups, while they are shown.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum HiddenPlacement {
+    /// In sort order with everything else.
+    Interleave,
+    Top,
+    Bottom,
+}
+
+/// What marks directories, links and files in the list. Plain ASCII by default, since whether the
+/// terminal and font can show emoji or Nerd Font glyphs can't be detected.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum IconSet {
+    /// No icons; directories get a trailing `/` instead.
+    Ascii,
+    Emoji,
+    /// Glyphs from a patched Nerd Font.
+    Nerd,
+}
+
+impl IconSet {
+    /// What goes before an entry's name. All prefixes of a set have the same width.
+    fn prefix(self, is_dir: bool, expanded: bool, is_symlink: bool) -> &'static str {
+        match (self, is_dir, is_symlink) {
+            (IconSet::Ascii, _, _) => " ",
+            (IconSet::Emoji, true, _) if expanded => "📂 ",
+            (IconSet::Emoji, true, _) => "📁 ",
+            (IconSet::Emoji, false, true) => "🔗 ",
+            (IconSet::Emoji, false, false) => "   ",
+            (IconSet::Nerd, true, _) if expanded => "\u{f07c} ",
+            (IconSet::Nerd, true, _) => "\u{f07b} ",
+            (IconSet::Nerd, false, true) => "\u{f0c1} ",
+            (IconSet::Nerd, false, false) => "\u{f15b} ",
         }
-        self.selected = self.selected.saturating_sub(1);
-        if self.selected == 0 && !self.entries.is_empty() {
-            // Optional: wrap to bottom. Alternatively leave at 0.
-            // self.selected = self.entries.len() - 1;
+    }
+
+    /// What goes after a directory's name.
+    fn dir_suffix(self) -> &'static str {
+        if self == IconSet::Ascii { "/" } else { "" }
+    }
+}
+
+/// The listing settings a session starts with, which `App::reset_view` goes back to.
+#[derive(Clone, Copy)]
+struct ViewSettings {
+    sort_mode: SortMode,
+    case_sensitive_sort: bool,
+    group_dirs_first: bool,
+    natural_sort: bool,
+    hidden_placement: HiddenPlacement,
+    show_hidden: bool,
+}
+
+/// How `arrange_entries` orders each group of entries.
+#[derive(Clone, Copy)]
+struct SortOptions {
+    mode: SortMode,
+    /// Reverse the mode's natural order.
+    reverse: bool,
+    /// Compare digit runs in names numerically, so "file2" sorts before "file10".
+    natural: bool,
+    /// Compare names character by character, so uppercase sorts before lowercase ("B" < "a").
+    case_sensitive: bool,
+    /// List directories before files rather than sorting them in together.
+    dirs_first: bool,
+    /// Where hidden entries go within each group.
+    hidden: HiddenPlacement,
+}
+
+/// Whether deleting moves entries to the trash or removes them for good.
+#[derive(Clone, Copy, PartialEq)]
+enum DeleteMode {
+    Trash,
+    Permanent,
+}
+
+/// Whether this platform has a trash for `trash` to move entries into.
+const TRASH_SUPPORTED: bool = cfg!(any(
+    windows,
+    target_os = "macos",
+    all(unix, not(any(target_os = "ios", target_os = "android")))
+));
+
+/// The last file operation that `u` can take back.
+enum UndoOp {
+    /// An entry was renamed from the first path to the second.
+    Rename(PathBuf, PathBuf),
+    /// Several entries were renamed at once, each from the first path to the second.
+    RenameBatch(Vec<(PathBuf, PathBuf)>),
+    /// A file or directory was created here.
+    Create(PathBuf),
+    /// These paths were moved to the trash.
+    Trash(Vec<PathBuf>),
+}
+
+/// What the key handler is currently doing: normal browsing, or waiting on a modal prompt.
+#[derive(Clone, PartialEq)]
+enum Mode {
+    Browse,
+    /// Waiting for y/n before deleting these paths.
+    ConfirmDelete(Vec<PathBuf>, DeleteMode),
+    /// Waiting for y/n before quitting (only when `confirm_quit` is on).
+    ConfirmQuit,
+    /// Pasting would replace these existing paths; waiting to hear whether to overwrite or keep both.
+    ConfirmOverwrite(Vec<PathBuf>),
+    /// Renaming the first path to the second would replace it; waiting for y/n.
+    ConfirmRename(PathBuf, PathBuf),
+    /// Showing the names a batch rename would give; waiting for y/n to carry it out.
+    ConfirmBatchRename(Vec<(PathBuf, PathBuf)>),
+    /// A one-line text prompt is open; the typed text lives in `App::input`.
+    Input(InputKind),
+}
+
+/// What a text prompt's input will be used for when submitted.
+#[derive(Clone, Copy, PartialEq)]
+enum InputKind {
+    /// Jump to a directory (or open a file) by path.
+    GotoPath,
+    /// Create an empty file in `cwd`.
+    NewFile,
+    /// Create a directory in `cwd`.
+    NewDir,
+    /// Give the selected entry a new name.
+    Rename,
+    /// Rename the marked entries after a pattern with a `{n}` counter.
+    BatchRename,
+    /// Run a shell command; `%` stands for the selected entry's path.
+    ShellCommand,
+    /// Search the text of the open preview.
+    PreviewSearch,
+    /// Only list entries matching a pattern.
+    Filter,
+    /// Change the permissions of the selected entry.
+    Chmod,
+    /// Scroll the open preview to a line number.
+    GotoLine,
+}
+
+impl InputKind {
+    /// Title for the prompt bar.
+    fn title(self) -> &'static str {
+        match self {
+            InputKind::GotoPath => " Go to path ",
+            InputKind::NewFile => " New file ",
+            InputKind::NewDir => " New directory ",
+            InputKind::Rename => " Rename ",
+            InputKind::BatchRename => " Rename marked entries ({n} = 1, 2, 3, ...) ",
+            InputKind::ShellCommand => " Shell command (% = selected path, %% = %) ",
+            InputKind::PreviewSearch => " Search preview ",
+            InputKind::Filter => " Filter (substring, or glob with * and ?) ",
+            InputKind::Chmod => " Change mode (octal like 644, or symbolic like +x or go-w) ",
+            InputKind::GotoLine => " Go to line ",
         }
     }
+}
 
-    /// Move selection down by one, wrapping to top if at bottom.
-    fn selection_down(&mut self) {
-        if self.entries.is_empty() {
-            return;
+/// Display options that affect how `load_file_preview` builds its lines.
+#[derive(Clone, Copy, PartialEq)]
+struct PreviewOptions {
+    /// Prefix each line with its line number.
+    line_numbers: bool,
+    /// Draw spaces as "·" and tabs as "→", and give trailing whitespace a background.
+    show_whitespace: bool,
+    /// Show binary files as an `xxd`-style hex dump instead of a one-line summary.
+    hex_view: bool,
+    /// Syntax highlighting theme.
+    theme: &'static Theme,
+    /// Read at most this many bytes of the file.
+    limit: usize,
+    /// Pretty-print JSON files before highlighting them.
+    pretty_json: bool,
+    /// Lay CSV and TSV files out as a table instead of highlighting them.
+    csv_table: bool,
+    /// Read the last `limit` bytes instead of the first, starting at a line boundary. Lines aren't
+    /// numbered then, since where the tail starts in the file isn't known.
+    tail: bool,
+    /// Columns between tab stops; tabs are expanded to spaces up to the next one.
+    tab_width: usize,
+    /// Lines longer than this many characters are cut short with "…" (0: never).
+    max_line_length: usize,
+}
+
+/// A committed search in the open preview. Matches are (line, column) positions in
+/// `App::preview_content`, with the column counted in characters.
+struct PreviewSearch {
+    query: String,
+    case_sensitive: bool,
+    matches: Vec<(usize, usize)>,
+    /// Index into `matches` of the match the preview was last scrolled to.
+    current: usize,
+}
+
+/// Whether pasting the register copies its paths or moves them.
+#[derive(Clone, Copy, PartialEq)]
+enum PasteMode {
+    Copy,
+    Move,
+}
+
+/// Paths yanked (yy) or cut (dd), waiting to be pasted into `cwd` with `p`.
+struct Register {
+    paths: Vec<PathBuf>,
+    mode: PasteMode,
+}
+
+/// Captured output of a `!` shell command, shown in a pager until a key closes it.
+struct CommandOutput {
+    /// The command line as it was run (after `%` expansion).
+    command: String,
+    /// Exit code, or None if the command was killed by a signal or couldn't be started.
+    exit_code: Option<i32>,
+    /// stdout followed by stderr (in red).
+    lines: Vec<Line<'static>>,
+    scroll: usize,
+    /// Largest useful `scroll` for the last rendered pager size.
+    scroll_max: usize,
+}
+
+/// The fuzzy finder stops indexing after this many files, so a walk of `/` can't eat all memory.
+const FINDER_MAX_FILES: usize = 200_000;
+
+/// The fuzzy finder overlay (Ctrl-p): the files under the directory it was opened in, narrowed
+/// down by a fuzzy query as you type.
+struct FuzzyFinder {
+    /// Directory the walk started in; `paths` are relative to it.
+    root: PathBuf,
+    query: String,
+    /// Files found so far.
+    paths: Vec<String>,
+    /// Batches of paths from the walker; None once it is done.
+    rx: Option<Receiver<Vec<String>>>,
+    /// Indices into `paths` of the files matching `query`, best match first.
+    matches: Vec<usize>,
+    /// Index into `matches` of the highlighted result.
+    selected: usize,
+    /// First result shown; moved during rendering to keep `selected` visible.
+    offset: usize,
+}
+
+impl FuzzyFinder {
+    /// Start walking `root` in the background. Hidden files and gitignore rules are treated as
+    /// the listing treats them.
+    fn new(root: PathBuf, show_hidden: bool, respect_gitignore: bool) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let dir = root.clone();
+        std::thread::spawn(move || walk_files(&dir, show_hidden, respect_gitignore, &tx));
+        FuzzyFinder {
+            root,
+            query: String::new(),
+            paths: Vec::new(),
+            rx: Some(rx),
+            matches: Vec::new(),
+            selected: 0,
+            offset: 0,
         }
-        self.selected = (self.selected + 1).min(self.entries.len() - 1);
     }
 
-    /// Enter the selected directory (if it's a dir) or go to parent if selection is "..".
-    fn enter_selected(&mut self) {
-        let Some(entry) = self.entries.get(self.selected) else {
-            return;
+    /// Take in whatever the walker found since the last call. Returns whether anything changed.
+    fn poll(&mut self) -> bool {
+        let Some(rx) = self.rx.as_ref() else {
+            return false;
         };
-        if entry.name == ".." {
-            // Go to parent directory.
-            if let Some(parent) = self.cwd.parent() {
-                self.cwd = parent.to_path_buf();
-                self.selected = 0;
-                self.refresh_entries();
+        let mut changed = false;
+        loop {
+            match rx.try_recv() {
+                Ok(batch) => {
+                    self.paths.extend(batch);
+                    changed = true;
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.rx = None;
+                    changed = true;
+                    break;
+                }
             }
-            return;
         }
-        if entry.is_dir {
-            let next = self.cwd.join(&entry.name);
-            if next.is_dir() {
-                self.cwd = next;
-                self.selected = 0;
-                self.refresh_entries();
-            }
-            return;
+        if changed {
+            self.rank(false);
         }
-        // File: open preview panel on the right.
-        let path = self.cwd.join(&entry.name);
-        if path.is_file() {
-            // Determine if this is a markdown file
-            let is_markdown = path.extension().and_then(|e| e.to_str()) == Some("md");
-            let mode = if is_markdown { PreviewMode::Rendered } else { PreviewMode::Raw };
-            
-            let (content, truncated) = if mode == PreviewMode::Rendered {
-                load_markdown_preview(&path)
-            } else {
-                load_file_preview(&path)
-            };
-            
-            self.preview_content = Some(content);
-            self.preview_path = Some(path);
-            self.preview_scroll = 0;
-            self.preview_scroll_max = 0;
-            self.preview_truncated = truncated;
-            self.preview_mode = mode;
+        changed
+    }
+
+    /// Recompute `matches` for the current query. An empty query lists every file in walk order;
+    /// otherwise the best scores come first, and shorter paths first among equal scores. With
+    /// `narrow`, only the current matches are scored again, which is enough when the query only
+    /// grew.
+    fn rank(&mut self, narrow: bool) {
+        if self.query.is_empty() {
+            self.matches = (0..self.paths.len()).collect();
+        } else {
+            let matcher = SkimMatcherV2::default().smart_case();
+            let candidates = if narrow { std::mem::take(&mut self.matches) } else { (0..self.paths.len()).collect() };
+            let mut scored: Vec<(i64, usize)> = candidates
+                .into_iter()
+                .filter_map(|i| Some((matcher.fuzzy_match(&self.paths[i], &self.query)?, i)))
+                .collect();
+            scored.sort_by_key(|&(score, i)| (std::cmp::Reverse(score), self.paths[i].len()));
+            self.matches = scored.into_iter().map(|(_, i)| i).collect();
         }
+        self.selected = self.selected.min(self.matches.len().saturating_sub(1));
     }
 
-    /// Close the preview panel if open.
-    fn close_preview(&mut self) {
-        self.preview_path = None;
-        self.preview_content = None;
-        self.preview_scroll = 0;
-        self.preview_scroll_max = 0;
-        self.preview_truncated = false;
-        self.preview_mode = PreviewMode::Raw;
+    /// Change the query (typing or Backspace) and go back to the best match.
+    fn edit(&mut self, edit: impl FnOnce(&mut String)) {
+        let previous = self.query.clone();
+        edit(&mut self.query);
+        self.selected = 0;
+        // Anything matching the longer query also matched the shorter one.
+        self.rank(!previous.is_empty() && self.query.starts_with(&previous));
     }
 
-    /// Toggle between raw and rendered preview mode (only for markdown files).
-    fn toggle_preview_mode(&mut self) {
-        // Only toggle if we have a markdown file open
-        let is_markdown = self.preview_path
-            .as_ref()
-            .and_then(|p| p.extension())
-            .and_then(|e| e.to_str()) == Some("md");
-        
-        if !is_markdown {
-            return;
+    /// Move the highlight by `delta` results, stopping at the ends.
+    fn move_selection(&mut self, delta: isize) {
+        let last = self.matches.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// The highlighted file, relative to `root`.
+    fn selected_path(&self) -> Option<&str> {
+        self.matches.get(self.selected).map(|&i| self.paths[i].as_str())
+    }
+}
+
+/// Something a key can be bound to. What an action does can depend on context: with the preview
+/// open, `MoveDown` scrolls the preview and `Parent` pans it left when wrapping is off.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Action {
+    Quit,
+    /// Quit and print the selected file, or else the current directory, to stdout.
+    QuitAndEmit,
+    /// Close the preview, or quit when none is open.
+    Close,
+    MoveUp,
+    MoveDown,
+    /// Move the list selection even while the preview is open (the arrow keys by default).
+    SelectUp,
+    SelectDown,
+    /// Jump to the first entry; needs two presses (the default is `gg`).
+    Top,
+    Bottom,
+    PageDown,
+    PageUp,
+    HalfPageDown,
+    HalfPageUp,
+    HistoryBack,
+    HistoryForward,
+    Open,
+    Parent,
+    ScrollLeft,
+    ScrollRight,
+    Search,
+    GotoPath,
+    ToggleHidden,
+    ToggleWrapSelection,
+    TogglePreviewWrap,
+    /// Show the preview over the whole screen instead of beside the list (or go back).
+    TogglePreviewFullscreen,
+    /// Give the preview less or more of the width beside the list.
+    ShrinkPreview,
+    GrowPreview,
+    SetBookmark,
+    JumpToBookmark,
+    Copy,
+    /// Copy the selected entry's file name rather than its full path.
+    CopyName,
+    /// Hand the current directory to the shell through `$DIRNAV_CWD_FILE` (or the clipboard).
+    SyncCwd,
+    /// Toggle the mark on the selected entry.
+    ToggleMark,
+    /// Mark everything, or clear all marks when everything is marked.
+    ToggleMarkAll,
+    /// Put the marked entries (or the selection) in the register for copying; needs two presses.
+    Yank,
+    /// Put the marked entries (or the selection) in the register for moving; needs two presses.
+    Cut,
+    Paste,
+    /// Expand or collapse the selected directory in place.
+    ToggleExpand,
+    ShellCommand,
+    /// Open an interactive shell in the current directory; dirnav resumes when it exits.
+    OpenShell,
+    NewFile,
+    NewDir,
+    Delete,
+    DeletePermanently,
+    ToggleGitignore,
+    CycleSort,
+    ReverseSort,
+    /// Switch names between case-insensitive and case-sensitive order.
+    ToggleSortCase,
+    /// Sort directories in with the files instead of before them (or go back).
+    ToggleDirsFirst,
+    /// List everything newest first, directories in among the files (or go back to the sort mode).
+    ToggleRecentFirst,
+    /// Clear the filter and search and put sorting and the listing toggles back to the startup settings.
+    ResetView,
+    /// Move the selection to the next directory, skipping files.
+    NextDir,
+    /// Move the selection to the previous directory, skipping files.
+    PrevDir,
+    /// Keep re-reading the end of the previewed file as it grows.
+    FollowPreview,
+    /// Change the selected entry's permissions (Unix).
+    Chmod,
+    /// Rename the selected entry.
+    Rename,
+    /// Rename the marked entries (or the selection) after a pattern such as `photo_{n}.jpg`.
+    BatchRename,
+    /// Reverse the last rename, create or move to the trash.
+    Undo,
+    /// Open the fuzzy finder over the files below `cwd`.
+    FuzzyFind,
+    /// Show how many entries each directory holds (or stop).
+    ToggleDirCounts,
+    /// Show the time in the corner of the key hints (or stop).
+    ToggleClock,
+    /// Show the first lines of the selected text file under the list (or stop).
+    TogglePeek,
+    /// Add up the sizes of everything below the selected directory.
+    DirSize,
+    /// Show a second, independent pane beside the first (or close it).
+    ToggleDualPane,
+    /// Focus the other pane in dual-pane mode.
+    SwitchPane,
+    TogglePreviewMode,
+    CycleTheme,
+    ToggleLineNumbers,
+    /// Show spaces, tabs and trailing whitespace in the preview.
+    ToggleWhitespace,
+    ToggleHexView,
+    /// Switch the details line between `rwxr-xr-x` and octal permissions.
+    TogglePermissionFormat,
+    /// Open the key binding overlay.
+    Help,
+    /// Only list entries matching a pattern; Esc (`Close`) clears it.
+    Filter,
+    /// Open the selected entry in the platform's default application.
+    OpenExternal,
+    /// Double how much of a file the preview reads, and reload it.
+    RaisePreviewLimit,
+}
+
+impl Action {
+    /// Names used for actions in the `[keys]` table of the config file.
+    const NAMES: &[(&str, Action)] = &[
+        ("quit", Action::Quit),
+        ("quit_and_emit", Action::QuitAndEmit),
+        ("close", Action::Close),
+        ("move_up", Action::MoveUp),
+        ("move_down", Action::MoveDown),
+        ("select_up", Action::SelectUp),
+        ("select_down", Action::SelectDown),
+        ("top", Action::Top),
+        ("bottom", Action::Bottom),
+        ("next_dir", Action::NextDir),
+        ("prev_dir", Action::PrevDir),
+        ("follow_preview", Action::FollowPreview),
+        ("page_down", Action::PageDown),
+        ("page_up", Action::PageUp),
+        ("half_page_down", Action::HalfPageDown),
+        ("half_page_up", Action::HalfPageUp),
+        ("history_back", Action::HistoryBack),
+        ("history_forward", Action::HistoryForward),
+        ("open", Action::Open),
+        ("parent", Action::Parent),
+        ("scroll_left", Action::ScrollLeft),
+        ("scroll_right", Action::ScrollRight),
+        ("search", Action::Search),
+        ("goto_path", Action::GotoPath),
+        ("fuzzy_find", Action::FuzzyFind),
+        ("toggle_hidden", Action::ToggleHidden),
+        ("toggle_wrap_selection", Action::ToggleWrapSelection),
+        ("toggle_preview_wrap", Action::TogglePreviewWrap),
+        ("toggle_preview_fullscreen", Action::TogglePreviewFullscreen),
+        ("shrink_preview", Action::ShrinkPreview),
+        ("grow_preview", Action::GrowPreview),
+        ("set_bookmark", Action::SetBookmark),
+        ("jump_to_bookmark", Action::JumpToBookmark),
+        ("copy", Action::Copy),
+        ("copy_name", Action::CopyName),
+        ("sync_cwd", Action::SyncCwd),
+        ("toggle_mark", Action::ToggleMark),
+        ("toggle_mark_all", Action::ToggleMarkAll),
+        ("yank", Action::Yank),
+        ("cut", Action::Cut),
+        ("paste", Action::Paste),
+        ("toggle_expand", Action::ToggleExpand),
+        ("shell_command", Action::ShellCommand),
+        ("open_shell", Action::OpenShell),
+        ("new_file", Action::NewFile),
+        ("new_dir", Action::NewDir),
+        ("delete", Action::Delete),
+        ("delete_permanently", Action::DeletePermanently),
+        ("chmod", Action::Chmod),
+        ("rename", Action::Rename),
+        ("batch_rename", Action::BatchRename),
+        ("undo", Action::Undo),
+        ("toggle_gitignore", Action::ToggleGitignore),
+        ("cycle_sort", Action::CycleSort),
+        ("reverse_sort", Action::ReverseSort),
+        ("toggle_sort_case", Action::ToggleSortCase),
+        ("toggle_dirs_first", Action::ToggleDirsFirst),
+        ("toggle_recent_first", Action::ToggleRecentFirst),
+        ("reset_view", Action::ResetView),
+        ("toggle_dir_counts", Action::ToggleDirCounts),
+        ("toggle_clock", Action::ToggleClock),
+        ("toggle_peek", Action::TogglePeek),
+        ("dir_size", Action::DirSize),
+        ("toggle_dual_pane", Action::ToggleDualPane),
+        ("switch_pane", Action::SwitchPane),
+        ("toggle_preview_mode", Action::TogglePreviewMode),
+        ("cycle_theme", Action::CycleTheme),
+        ("toggle_line_numbers", Action::ToggleLineNumbers),
+        ("toggle_whitespace", Action::ToggleWhitespace),
+        ("toggle_hex_view", Action::ToggleHexView),
+        ("toggle_permission_format", Action::TogglePermissionFormat),
+        ("help", Action::Help),
+        ("filter", Action::Filter),
+        ("open_external", Action::OpenExternal),
+        ("raise_preview_limit", Action::RaisePreviewLimit),
+    ];
+
+    /// Headings of the help overlay, in display order; `help` assigns each action to one.
+    const CATEGORIES: &[&str] = &["Navigation", "Files", "View", "Preview"];
+
+    fn from_name(name: &str) -> Option<Action> {
+        Action::NAMES.iter().find(|(n, _)| *n == name).map(|(_, a)| *a)
+    }
+
+    /// Category and one-line description of the action, for the help overlay.
+    fn help(self) -> (&'static str, &'static str) {
+        match self {
+            Action::MoveUp => ("Navigation", "Move up (scrolls the preview when open)"),
+            Action::MoveDown => ("Navigation", "Move down (scrolls the preview when open)"),
+            Action::SelectUp => ("Navigation", "Move the selection up, even with the preview open"),
+            Action::SelectDown => ("Navigation", "Move the selection down, even with the preview open"),
+            Action::Top => ("Navigation", "Jump to the first entry, or the preview's start (press twice)"),
+            Action::Bottom => ("Navigation", "Jump to the last entry, or the end of the file in the preview"),
+            Action::NextDir => ("Navigation", "Jump to the next directory, skipping files"),
+            Action::PrevDir => ("Navigation", "Jump to the previous directory, skipping files"),
+            Action::FollowPreview => ("Preview", "Follow the end of the previewed file as it grows"),
+            Action::PageDown => ("Navigation", "Page down"),
+            Action::PageUp => ("Navigation", "Page up"),
+            Action::HalfPageDown => ("Navigation", "Half a page down"),
+            Action::HalfPageUp => ("Navigation", "Half a page up"),
+            Action::Open => ("Navigation", "Open the directory or preview the file"),
+            Action::OpenExternal => ("Files", "Open with the default application"),
+            Action::Parent => ("Navigation", "Go to the parent directory"),
+            Action::HistoryBack => ("Navigation", "Go back to the previous directory"),
+            Action::HistoryForward => ("Navigation", "Go forward again"),
+            Action::Search => ("Navigation", "Search entries by name (the preview text when open)"),
+            Action::Filter => ("View", "Only show entries matching a pattern (Esc clears it)"),
+            Action::FuzzyFind => ("Navigation", "Fuzzy-find a file anywhere below the current directory"),
+            Action::GotoPath => ("Navigation", "Go to a path, or to a line of the preview"),
+            Action::SetBookmark => ("Navigation", "Bookmark the current directory under the next letter"),
+            Action::JumpToBookmark => ("Navigation", "Jump to the bookmark under the next letter"),
+            Action::ToggleExpand => ("Navigation", "Expand or collapse the directory in place"),
+            Action::ToggleMark => ("Files", "Mark or unmark the selected entry"),
+            Action::ToggleMarkAll => ("Files", "Mark all entries, or clear the marks"),
+            Action::Copy => ("Files", "Copy the path to the clipboard (the preview text when open)"),
+            Action::CopyName => ("Files", "Copy just the file name to the clipboard"),
+            Action::SyncCwd => ("Files", "Write the current directory to $DIRNAV_CWD_FILE for the shell (or copy it)"),
+            Action::Yank => ("Files", "Yank for copying (press twice); copies to the other pane in dual-pane mode"),
+            Action::Cut => ("Files", "Cut for moving (press twice); moves to the other pane in dual-pane mode"),
+            Action::Paste => ("Files", "Paste yanked or cut entries here"),
+            Action::NewFile => ("Files", "Create a file"),
+            Action::NewDir => ("Files", "Create a directory"),
+            Action::Delete => ("Files", "Move the marked entries or the selection to the trash"),
+            Action::DeletePermanently => ("Files", "Delete the marked entries or the selection permanently"),
+            Action::Chmod => ("Files", "Change the selected entry's permissions (octal or symbolic)"),
+            Action::Rename => ("Files", "Rename the selected entry"),
+            Action::BatchRename => ("Files", "Rename the marked entries after a pattern with a {n} counter"),
+            Action::Undo => ("Files", "Undo the last rename, new file or directory, or move to the trash"),
+            Action::ShellCommand => ("Files", "Run a shell command (% is the selected path, %% a literal %)"),
+            Action::OpenShell => ("Files", "Open a shell here (exit it to come back)"),
+            Action::ToggleHidden => ("View", "Show or hide hidden files"),
+            Action::ToggleGitignore => ("View", "Show or hide gitignored files"),
+            Action::ToggleWrapSelection => ("View", "Wrap the selection around the ends of the list"),
+            Action::CycleSort => ("View", "Cycle the sort mode"),
+            Action::ReverseSort => ("View", "Reverse the sort order"),
+            Action::ToggleSortCase => ("View", "Toggle case-sensitive name order"),
+            Action::ToggleDirsFirst => ("View", "Toggle listing directories before files"),
+            Action::ToggleRecentFirst => ("View", "Toggle listing the most recently modified entries first"),
+            Action::ResetView => ("View", "Reset the filter, sorting and listing toggles to how they started"),
+            Action::ToggleDirCounts => ("View", "Toggle showing how many entries each directory holds"),
+            Action::ToggleClock => ("View", "Toggle the clock"),
+            Action::TogglePeek => ("View", "Toggle a peek at the first lines of the selected file"),
+            Action::DirSize => ("Files", "Add up the total size of the selected directory"),
+            Action::ToggleDualPane => ("View", "Toggle dual-pane mode"),
+            Action::SwitchPane => ("Navigation", "Focus the other pane (dual-pane mode)"),
+            Action::TogglePermissionFormat => ("View", "Show permissions as octal or rwx"),
+            Action::CycleTheme => ("View", "Cycle the syntax highlighting theme"),
+            Action::Help => ("View", "Show this help"),
+            Action::Close => ("View", "Close the preview, or quit"),
+            Action::Quit => ("View", "Quit"),
+            Action::QuitAndEmit => ("View", "Quit and print the selected file or the directory to stdout"),
+            Action::TogglePreviewMode => ("Preview", "Toggle raw and rendered markdown or pretty JSON"),
+            Action::TogglePreviewWrap => ("Preview", "Toggle line wrapping"),
+            Action::TogglePreviewFullscreen => ("Preview", "Toggle the full-screen preview"),
+            Action::ShrinkPreview => ("Preview", "Make the preview narrower"),
+            Action::GrowPreview => ("Preview", "Make the preview wider"),
+            Action::ScrollLeft => ("Preview", "Scroll left (when not wrapping)"),
+            Action::ScrollRight => ("Preview", "Scroll right (when not wrapping)"),
+            Action::ToggleLineNumbers => ("Preview", "Toggle line numbers"),
+            Action::ToggleWhitespace => ("Preview", "Toggle showing spaces, tabs and trailing whitespace"),
+            Action::ToggleHexView => ("Preview", "Toggle the hex view of binary files"),
+            Action::RaisePreviewLimit => ("Preview", "Read twice as much of large files"),
         }
-        
-        // Toggle mode
-        self.preview_mode = match self.preview_mode {
-            PreviewMode::Raw => PreviewMode::Rendered,
-            PreviewMode::Rendered => PreviewMode::Raw,
+    }
+}
+
+/// A key together with the modifiers that matter for bindings.
+type KeyBinding = (KeyCode, KeyModifiers);
+
+/// Normalize a key for keymap lookup: only Ctrl/Alt/Shift count, and Shift is dropped for
+/// characters since it's already part of the character ('G' vs 'g').
+fn key_binding(code: KeyCode, modifiers: KeyModifiers) -> KeyBinding {
+    let mut modifiers = modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+    if let KeyCode::Char(_) = code {
+        modifiers.remove(KeyModifiers::SHIFT);
+    }
+    (code, modifiers)
+}
+
+/// Parse a key as written in the config file: an optional `ctrl-`, `alt-` or `shift-` prefix
+/// (in any combination) followed by a single character or a key name such as `enter`, `esc`,
+/// `space`, `backspace`, `tab`, `up`, `pagedown` or `f5`.
+fn parse_key_binding(text: &str) -> Option<KeyBinding> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = text;
+    loop {
+        let lower = rest.to_ascii_lowercase();
+        let (modifier, len) = if lower.starts_with("ctrl-") {
+            (KeyModifiers::CONTROL, 5)
+        } else if lower.starts_with("alt-") {
+            (KeyModifiers::ALT, 4)
+        } else if lower.starts_with("shift-") {
+            (KeyModifiers::SHIFT, 6)
+        } else {
+            break;
         };
-        
-        // Reload preview with new mode
-        if let Some(ref path) = self.preview_path.clone() {
-            let (content, truncated) = if self.preview_mode == PreviewMode::Rendered {
-                load_markdown_preview(path)
-            } else {
-                load_file_preview(path)
-            };
-            self.preview_content = Some(content);
-            self.preview_truncated = truncated;
-            self.preview_scroll = 0;
-            self.preview_scroll_max = 0;
+        modifiers |= modifier;
+        rest = &rest[len..];
+    }
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match rest.to_ascii_lowercase().as_str() {
+            "enter" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "space" => KeyCode::Char(' '),
+            "backspace" => KeyCode::Backspace,
+            "tab" => KeyCode::Tab,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "delete" => KeyCode::Delete,
+            name => KeyCode::F(name.strip_prefix('f')?.parse().ok()?),
+        },
+    };
+    Some(key_binding(code, modifiers))
+}
+
+/// How a binding is written in the help overlay, e.g. "Ctrl+f", "Shift+Backspace" or "↓".
+fn format_key_binding((code, modifiers): KeyBinding) -> String {
+    let key = match code {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Up => "↑".to_string(),
+        KeyCode::Down => "↓".to_string(),
+        KeyCode::Left => "←".to_string(),
+        KeyCode::Right => "→".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        other => format!("{:?}", other),
+    };
+    let mut out = String::new();
+    for (modifier, name) in [(KeyModifiers::CONTROL, "Ctrl+"), (KeyModifiers::ALT, "Alt+"), (KeyModifiers::SHIFT, "Shift+")] {
+        if modifiers.contains(modifier) {
+            out.push_str(name);
         }
     }
+    out.push_str(&key);
+    out
+}
 
-    /// Scroll preview down (j). No-op if preview closed.
-    fn preview_scroll_down(&mut self) {
-        if self.preview_content.is_some() {
-            self.preview_scroll = (self.preview_scroll.saturating_add(1)).min(self.preview_scroll_max);
+/// The help overlay's content: every bound action with its keys, grouped by category.
+fn help_lines(keymap: &HashMap<KeyBinding, Action>) -> Vec<Line<'static>> {
+    let mut rows: Vec<(&str, String, &str)> = Vec::new();
+    for (_, action) in Action::NAMES {
+        let mut keys: Vec<String> = keymap
+            .iter()
+            .filter(|(_, a)| *a == action)
+            .map(|(binding, _)| format_key_binding(*binding))
+            .collect();
+        if keys.is_empty() {
+            continue;
         }
+        // Plain keys first, then named ones.
+        keys.sort_by_key(|k| (k.chars().count() > 1, k.clone()));
+        // Two-press actions show the key doubled, as they are typed.
+        if matches!(action, Action::Top | Action::Yank | Action::Cut) {
+            keys = keys.into_iter().map(|k| format!("{}{}", k, k)).collect();
+        }
+        let (category, description) = action.help();
+        rows.push((category, keys.join(", "), description));
+    }
+    let key_width = rows.iter().map(|(_, keys, _)| keys.width()).max().unwrap_or(0);
+    let mut lines = Vec::new();
+    for category in Action::CATEGORIES {
+        lines.push(Line::from(Span::styled(
+            format!(" {}", category),
+            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+        )));
+        for (_, keys, description) in rows.iter().filter(|(c, _, _)| c == category) {
+            lines.push(Line::from(vec![
+                Span::styled(format!("   {:<width$}", keys, width = key_width), Style::default().fg(Color::Cyan)),
+                Span::raw(format!("   {}", description)),
+            ]));
+        }
+        lines.push(plain_line(""));
     }
+    lines.pop();
+    lines
+}
 
-    /// Scroll preview up (k). No-op if preview closed.
-    fn preview_scroll_up(&mut self) {
-        if self.preview_content.is_some() {
-            self.preview_scroll = self.preview_scroll.saturating_sub(1);
+/// The built-in bindings, before any `[keys]` overrides from the config file.
+fn default_keymap() -> HashMap<KeyBinding, Action> {
+    let none = KeyModifiers::NONE;
+    let ctrl = KeyModifiers::CONTROL;
+    let alt = KeyModifiers::ALT;
+    [
+        (KeyCode::Char('q'), none, Action::Quit),
+        (KeyCode::Char('Q'), none, Action::QuitAndEmit),
+        (KeyCode::Esc, none, Action::Close),
+        (KeyCode::Up, none, Action::SelectUp),
+        (KeyCode::Char('k'), none, Action::MoveUp),
+        (KeyCode::Down, none, Action::SelectDown),
+        (KeyCode::Char('j'), none, Action::MoveDown),
+        (KeyCode::Char('g'), none, Action::Top),
+        (KeyCode::Char('G'), none, Action::Bottom),
+        (KeyCode::Char('}'), none, Action::NextDir),
+        (KeyCode::Char('{'), none, Action::PrevDir),
+        (KeyCode::Char('F'), none, Action::FollowPreview),
+        // Paging: Ctrl-f/b (and PageDown/PageUp) move a full page, Ctrl-d/u half a page.
+        (KeyCode::Char('f'), ctrl, Action::PageDown),
+        (KeyCode::Char('p'), ctrl, Action::FuzzyFind),
+        (KeyCode::Char('b'), ctrl, Action::PageUp),
+        (KeyCode::PageDown, none, Action::PageDown),
+        (KeyCode::PageUp, none, Action::PageUp),
+        (KeyCode::Char('d'), ctrl, Action::HalfPageDown),
+        (KeyCode::Char('u'), ctrl, Action::HalfPageUp),
+        (KeyCode::Char('t'), ctrl, Action::ToggleClock),
+        // Shift+Backspace isn't reported by every terminal, so Alt+←/→ work as well.
+        (KeyCode::Backspace, none, Action::HistoryBack),
+        (KeyCode::Backspace, KeyModifiers::SHIFT, Action::HistoryForward),
+        (KeyCode::Left, alt, Action::HistoryBack),
+        (KeyCode::Right, alt, Action::HistoryForward),
+        (KeyCode::Enter, none, Action::Open),
+        (KeyCode::Char('l'), none, Action::Open),
+        (KeyCode::Char('h'), none, Action::Parent),
+        (KeyCode::Left, none, Action::ScrollLeft),
+        (KeyCode::Right, none, Action::ScrollRight),
+        (KeyCode::Char('/'), none, Action::Search),
+        (KeyCode::Char(':'), none, Action::GotoPath),
+        (KeyCode::Char('H'), none, Action::ToggleHidden),
+        (KeyCode::Char('W'), none, Action::ToggleWrapSelection),
+        (KeyCode::Char('w'), none, Action::TogglePreviewWrap),
+        (KeyCode::Char('Z'), none, Action::TogglePreviewFullscreen),
+        (KeyCode::Char('<'), none, Action::ShrinkPreview),
+        (KeyCode::Char('>'), none, Action::GrowPreview),
+        (KeyCode::Char('m'), none, Action::SetBookmark),
+        (KeyCode::Char('\''), none, Action::JumpToBookmark),
+        (KeyCode::Char('c'), none, Action::Copy),
+        (KeyCode::Char('C'), none, Action::CopyName),
+        (KeyCode::Char('P'), none, Action::SyncCwd),
+        (KeyCode::Char(' '), none, Action::ToggleMark),
+        (KeyCode::Char('V'), none, Action::ToggleMarkAll),
+        (KeyCode::Char('z'), none, Action::ToggleExpand),
+        (KeyCode::Char('!'), none, Action::ShellCommand),
+        (KeyCode::Char('$'), none, Action::OpenShell),
+        (KeyCode::Char('a'), none, Action::NewFile),
+        (KeyCode::Char('A'), none, Action::NewDir),
+        (KeyCode::Char('y'), none, Action::Yank),
+        (KeyCode::Char('d'), none, Action::Cut),
+        (KeyCode::Char('p'), none, Action::Paste),
+        (KeyCode::Char('D'), none, Action::Delete),
+        (KeyCode::Delete, none, Action::Delete),
+        (KeyCode::Delete, KeyModifiers::SHIFT, Action::DeletePermanently),
+        (KeyCode::Char('M'), none, Action::Chmod),
+        (KeyCode::Char('r'), none, Action::Rename),
+        (KeyCode::Char('R'), none, Action::BatchRename),
+        (KeyCode::Char('u'), none, Action::Undo),
+        (KeyCode::Char('i'), none, Action::ToggleGitignore),
+        (KeyCode::Char('s'), none, Action::CycleSort),
+        (KeyCode::Char('S'), none, Action::ReverseSort),
+        (KeyCode::Char('I'), none, Action::ToggleSortCase),
+        (KeyCode::Char('e'), none, Action::ToggleDirsFirst),
+        (KeyCode::Char('L'), none, Action::ToggleRecentFirst),
+        (KeyCode::Char('\\'), none, Action::ResetView),
+        (KeyCode::Char('K'), none, Action::TogglePeek),
+        (KeyCode::Char('#'), none, Action::ToggleDirCounts),
+        (KeyCode::Char('U'), none, Action::DirSize),
+        (KeyCode::Char('|'), none, Action::ToggleDualPane),
+        (KeyCode::Tab, none, Action::SwitchPane),
+        (KeyCode::Char('t'), none, Action::TogglePreviewMode),
+        (KeyCode::Char('T'), none, Action::CycleTheme),
+        (KeyCode::Char('n'), none, Action::ToggleLineNumbers),
+        (KeyCode::Char('v'), none, Action::ToggleWhitespace),
+        (KeyCode::Char('x'), none, Action::ToggleHexView),
+        (KeyCode::Char('O'), none, Action::TogglePermissionFormat),
+        (KeyCode::Char('?'), none, Action::Help),
+        (KeyCode::Char('f'), none, Action::Filter),
+        (KeyCode::Char('o'), none, Action::OpenExternal),
+        (KeyCode::Char('+'), none, Action::RaisePreviewLimit),
+    ]
+    .into_iter()
+    .map(|(code, modifiers, action)| (key_binding(code, modifiers), action))
+    .collect()
+}
+
+/// The default keymap with the config file's `[keys]` overrides applied. An action of `"none"`
+/// unbinds the key. Returns the keymap and the entries that couldn't be understood.
+fn build_keymap(overrides: &BTreeMap<String, String>) -> (HashMap<KeyBinding, Action>, Vec<String>) {
+    let mut keymap = default_keymap();
+    let mut invalid = Vec::new();
+    for (key, action) in overrides {
+        let Some(binding) = parse_key_binding(key) else {
+            invalid.push(key.clone());
+            continue;
+        };
+        if action == "none" {
+            keymap.remove(&binding);
+        } else if let Some(action) = Action::from_name(action) {
+            keymap.insert(binding, action);
+        } else {
+            invalid.push(format!("{} = {}", key, action));
         }
     }
+    (keymap, invalid)
 }
 
-/// Read directory entries for the given path. Returns dirs first (with ".." at top), then files, sorted by name.
-fn read_dir_entries(path: &std::path::Path) -> Vec<DirEntry> {
-    let read = match fs::read_dir(path) {
-        Ok(r) => r,
-        Err(_) => return Vec::new(),
-    };
+/// Default colours of file names in the list by kind of file, and the extensions of each kind,
+/// in the spirit of `LS_COLORS`. Categories and single extensions can be recoloured in the config.
+const FILE_CATEGORIES: &[(&str, Color, &[&str])] = &[
+    (
+        "code",
+        Color::LightBlue,
+        &[
+            "rs", "c", "h", "cc", "cpp", "hpp", "cs", "go", "java", "kt", "swift", "py", "rb", "php", "js", "jsx",
+            "ts", "tsx", "lua", "hs", "ml", "scala", "zig", "sh", "bash", "zsh", "fish", "sql", "html", "css",
+        ],
+    ),
+    ("archive", Color::Red, &["zip", "tar", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "jar", "deb", "rpm", "iso"]),
+    ("image", Color::LightMagenta, &["png", "jpg", "jpeg", "gif", "bmp", "webp", "svg", "ico", "tif", "tiff", "heic"]),
+    ("media", Color::LightCyan, &["mp3", "flac", "wav", "ogg", "opus", "m4a", "mp4", "mkv", "webm", "avi", "mov"]),
+];
+
+/// The colour of file names by (lowercase) extension: the defaults from `FILE_CATEGORIES` with
+/// the config's `[colors]` on top. A key there is a category or an extension (extensions win), and
+/// a value is a colour name, `#rrggbb`, a 256-colour index, or "none" for no colour. Also returns
+/// the entries that couldn't be used.
+fn build_file_colors(overrides: &BTreeMap<String, String>) -> (HashMap<String, Color>, Vec<String>) {
+    let mut colors: HashMap<String, Color> = FILE_CATEGORIES
+        .iter()
+        .flat_map(|&(_, color, extensions)| extensions.iter().map(move |ext| (ext.to_string(), color)))
+        .collect();
+    let mut invalid = Vec::new();
+    let category = |key: &str| FILE_CATEGORIES.iter().find(|(name, _, _)| *name == key);
+    let (by_category, by_extension): (Vec<_>, Vec<_>) = overrides.iter().partition(|(key, _)| category(key).is_some());
+    for (key, value) in by_category.into_iter().chain(by_extension) {
+        let color = match value.as_str() {
+            "none" => None,
+            value => match value.parse::<Color>() {
+                Ok(color) => Some(color),
+                Err(_) => {
+                    invalid.push(format!("{} = {}", key, value));
+                    continue;
+                }
+            },
+        };
+        let extensions = match category(key) {
+            Some((_, _, extensions)) => extensions.iter().map(|ext| ext.to_string()).collect(),
+            None => vec![key.trim_start_matches('.').to_lowercase()],
+        };
+        for ext in extensions {
+            match color {
+                Some(color) => colors.insert(ext, color),
+                None => colors.remove(&ext),
+            };
+        }
+    }
+    (colors, invalid)
+}
 
-    let mut dirs = Vec::new();
-    let mut files = Vec::new();
+/// User preferences from `config.toml` in the config dir. Missing keys keep their defaults and
+/// unknown keys are ignored, so config files from other versions still load.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Config {
+    /// Directory to start in instead of the working directory.
+    start_dir: Option<PathBuf>,
+    /// Start where the previous session ended, as `--resume` does (takes precedence over `start_dir`).
+    resume_last_dir: bool,
+    /// Syntax highlighting theme name.
+    theme: String,
+    show_hidden: bool,
+    sort_mode: SortMode,
+    /// Order numbered names numerically ("file2" before "file10") rather than character by character.
+    natural_sort: bool,
+    /// Order names by their characters' codes (uppercase first) instead of ignoring case.
+    case_sensitive_sort: bool,
+    /// List directories before files; when false they're sorted in among the files.
+    group_dirs_first: bool,
+    /// Where hidden entries go when shown: in among the others, or gathered at the top or bottom.
+    hidden_placement: HiddenPlacement,
+    /// Ask before quitting.
+    confirm_quit: bool,
+    /// Preview files, and list directories, as the selection lands on them, without pressing Enter.
+    auto_preview: bool,
+    /// Show how many entries each directory holds after its name.
+    show_dir_counts: bool,
+    /// Show the time in the bottom right corner.
+    show_clock: bool,
+    /// Show the first lines of the selected text file under the list.
+    show_peek: bool,
+    /// Entries to keep in view above and below the selection, like vim's option of that name.
+    scrolloff: usize,
+    /// How much of a file the preview reads, in KiB (capped at `MAX_PREVIEW_LIMIT`).
+    preview_limit_kb: usize,
+    /// Percentage of the width beside the list that the preview takes (within `PREVIEW_RATIO_RANGE`).
+    preview_ratio: u16,
+    /// Columns between tab stops in the preview (1 to 16).
+    tab_width: usize,
+    /// Characters of a line the preview shows before cutting it short (0 shows every line whole).
+    max_line_length: usize,
+    /// Markers before entry names: "ascii", "emoji" or "nerd".
+    icons: IconSet,
+    /// Key binding overrides, e.g. `"ctrl-n" = "move_down"`; see `Action::NAMES`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    keys: BTreeMap<String, String>,
+    /// File name colours by category or extension, e.g. `image = "yellow"`; see `build_file_colors`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    colors: BTreeMap<String, String>,
+}
 
-    for entry in read.flatten() {
-        let name = entry.file_name().to_string_lossy().into_owned();
-        let is_dir = entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
-        let de = DirEntry { name, is_dir };
-        if de.is_dir {
-            dirs.push(de);
-        } else {
-            files.push(de);
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            start_dir: None,
+            resume_last_dir: false,
+            theme: DEFAULT_THEME.to_string(),
+            show_hidden: false,
+            sort_mode: SortMode::Name,
+            natural_sort: true,
+            case_sensitive_sort: false,
+            group_dirs_first: true,
+            hidden_placement: HiddenPlacement::Interleave,
+            confirm_quit: false,
+            auto_preview: false,
+            show_dir_counts: false,
+            show_clock: false,
+            show_peek: false,
+            scrolloff: 3,
+            preview_limit_kb: DEFAULT_PREVIEW_LIMIT / 1024,
+            preview_ratio: 50,
+            tab_width: 4,
+            max_line_length: 10_000,
+            icons: IconSet::Ascii,
+            keys: BTreeMap::new(),
+            colors: BTreeMap::new(),
         }
     }
+}
 
-    // Case-insensitive sort so "Apple" comes before "banana".
-    dirs.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-    files.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+/// What the directory-reading worker sends back while listing `cwd`.
+enum DirLoadMsg {
+    /// The entries read so far, in display order.
+    Partial(Vec<DirEntry>),
+    /// The complete listing.
+    Done(DirListing),
+    /// The directory itself couldn't be read.
+    Failed(io::Error),
+}
 
-    let mut out = Vec::new();
-    // Only add ".." if we're not at root (so we can go up).
-    if path.parent().is_some() {
-        out.push(DirEntry {
-            name: "..".to_string(),
-            is_dir: true,
+/// A complete read of `cwd`, with what the worker worked out about it alongside.
+struct DirListing {
+    /// Every entry, in display order.
+    entries: Vec<DirEntry>,
+    /// The names gitignore leaves visible, when that filter is on and `cwd` is in a repo.
+    gitignore_visible: Option<HashSet<String>>,
+    /// Git status of changed paths under `cwd`; empty outside a repo.
+    git_status: HashMap<String, GitStatus>,
+}
+
+/// How git sees a changed path, from least to most notable; a directory shows the most notable
+/// status of anything inside it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum GitStatus {
+    Ignored,
+    Untracked,
+    /// Changes staged in the index, and none beyond them in the worktree.
+    Staged,
+    /// Unstaged changes in the worktree (or a merge conflict).
+    Modified,
+}
+
+impl GitStatus {
+    /// Read the two-letter status code of a `git status --porcelain` line.
+    fn from_porcelain(code: &[u8]) -> Option<Self> {
+        match code {
+            b"!!" => Some(GitStatus::Ignored),
+            b"??" => Some(GitStatus::Untracked),
+            [b'U', _] | [_, b'U'] | b"AA" | b"DD" => Some(GitStatus::Modified),
+            [_, b' '] => Some(GitStatus::Staged),
+            [_, _] => Some(GitStatus::Modified),
+            _ => None,
+        }
+    }
+
+    /// The glyph shown after the entry's name, and its colour.
+    fn glyph(self) -> (char, Color) {
+        match self {
+            GitStatus::Ignored => ('!', Color::DarkGray),
+            GitStatus::Untracked => ('?', Color::LightBlue),
+            GitStatus::Staged => ('A', Color::LightGreen),
+            GitStatus::Modified => ('M', Color::LightRed),
+        }
+    }
+}
+
+/// A listing of `cwd` still being read on a worker thread.
+struct DirLoad {
+    /// Messages from the worker; dropping this cancels the load.
+    rx: Receiver<DirLoadMsg>,
+    /// Show entries as they arrive (a new directory) rather than keeping the old listing until done.
+    stream: bool,
+    /// Entry to select once the listing is complete, and whether to open it then.
+    select: Option<(String, bool)>,
+}
+
+/// Child counts of directories in the listing (`#`). A worker counts one directory at a time, so
+/// only the directories on screen are ever read, and the results are kept per path.
+struct DirCounts {
+    /// Count of each directory asked about so far; None if it couldn't be read.
+    counts: HashMap<PathBuf, Option<usize>>,
+    /// Directories sent to the worker and not answered yet.
+    pending: HashSet<PathBuf>,
+    /// Directories to count; dropping it stops the worker.
+    tx: Sender<PathBuf>,
+    rx: Receiver<(PathBuf, Option<usize>)>,
+}
+
+impl DirCounts {
+    fn new() -> Self {
+        let (tx, worker_rx) = mpsc::channel::<PathBuf>();
+        let (worker_tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for dir in worker_rx {
+                let count = fs::read_dir(&dir).ok().map(|children| children.count());
+                if worker_tx.send((dir, count)).is_err() {
+                    break;
+                }
+            }
         });
+        DirCounts { counts: HashMap::new(), pending: HashSet::new(), tx, rx }
+    }
+
+    /// What to show after a directory's name: its count, "…" while counting, or "?" if unreadable.
+    fn label(&self, dir: &std::path::Path) -> String {
+        match self.counts.get(dir) {
+            Some(Some(count)) => count.to_string(),
+            Some(None) => "?".to_string(),
+            None => "…".to_string(),
+        }
     }
-    out.extend(dirs);
-    out.extend(files);
-    out
 }
 
-/// Build a plain Line from a string (single-style).
-fn plain_line(s: impl Into<String>) -> Line<'static> {
-    Line::from(Span::raw(s.into()))
+/// Totals from adding up everything below a directory (`U`).
+#[derive(Clone, Copy, Default)]
+struct DirSize {
+    bytes: u64,
+    files: u64,
+    /// Entries that couldn't be read (e.g. permission denied) and so aren't in the totals.
+    skipped: u64,
 }
 
-/// Load a short preview of a file: content only, with syntax highlighting when available.
-/// Returns (lines, truncated) where truncated is true if the file was larger than the limit.
-fn load_file_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
-    let mut out: Vec<Line<'static>> = Vec::new();
+impl DirSize {
+    /// "4.2M, 318 files", plus a note about anything skipped.
+    fn describe(&self) -> String {
+        let mut text = format!(
+            "{}, {} file{}",
+            format_size(self.bytes),
+            self.files,
+            if self.files == 1 { "" } else { "s" }
+        );
+        if self.skipped > 0 {
+            text.push_str(&format!(" ({} unreadable skipped)", self.skipped));
+        }
+        text
+    }
+}
+
+/// A preview being read and highlighted on a worker thread, so opening a big file doesn't hold up
+/// the keyboard. Dropping it discards the result.
+struct PreviewLoad {
+    started: Instant,
+    rx: Receiver<LoadedPreview>,
+}
+
+/// What the preview worker sends back: the output of `load_file_preview` (or
+/// `load_markdown_preview`), and the decoded image when one is to be drawn.
+#[derive(Clone)]
+struct LoadedPreview {
+    content: Vec<Line<'static>>,
+    truncated: bool,
+    encoding: Option<&'static str>,
+    image: Option<image::DynamicImage>,
+    /// Some lines were longer than `max_line_length` and are cut short.
+    lines_cut: bool,
+    /// The text without the line-number gutter or whitespace marks, when either is drawn, for
+    /// copying the preview.
+    plain_text: Option<String>,
+}
+
+impl LoadedPreview {
+    /// A preview of just `content`, read whole: a note about the file, or lines built elsewhere.
+    fn plain(content: Vec<Line<'static>>) -> Self {
+        LoadedPreview { content, truncated: false, encoding: None, image: None, lines_cut: false, plain_text: None }
+    }
+}
+
+/// Everything a preview's content depends on: whether markdown is rendered, the display
+/// options, and the file's size and modification time when it was read.
+#[derive(Clone, Copy, PartialEq)]
+struct PreviewKey {
+    markdown: bool,
+    opts: PreviewOptions,
+    stamp: (u64, Option<SystemTime>),
+}
+
+impl PreviewKey {
+    fn of(path: &std::path::Path, markdown: bool, opts: PreviewOptions) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        Some(PreviewKey { markdown, opts, stamp: (meta.len(), meta.modified().ok()) })
+    }
+}
+
+/// Previews of the entries around the selection, loaded ahead of time on a worker thread so
+/// opening one is instant. Holds the `PREFETCH_CAPACITY` most recently used, least recent first.
+struct Prefetch {
+    cache: VecDeque<(PathBuf, PreviewKey, LoadedPreview)>,
+    /// The file the worker is loading, if any; one at a time, so scrolling fast doesn't pile up work.
+    pending: Option<PathBuf>,
+    /// Files to load; dropping it stops the worker.
+    tx: Sender<(PathBuf, PreviewKey)>,
+    rx: Receiver<(PathBuf, PreviewKey, LoadedPreview)>,
+}
+
+/// How often the current directory is checked to still exist, for deletions the watcher misses.
+const CWD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the selection has to rest on an entry before `auto_preview` opens it.
+const AUTO_PREVIEW_DELAY: Duration = Duration::from_millis(150);
+
+/// How many closed previews' scroll positions are remembered.
+const SCROLL_MEMORY_CAPACITY: usize = 64;
+
+/// How many prefetched previews are kept.
+const PREFETCH_CAPACITY: usize = 32;
+/// Files bigger than this aren't prefetched, as loading them takes long enough to show anyway.
+const PREFETCH_MAX_SIZE: u64 = 256 * 1024;
+
+impl Prefetch {
+    fn new() -> Self {
+        let (tx, worker_rx) = mpsc::channel::<(PathBuf, PreviewKey)>();
+        let (worker_tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (path, key) in worker_rx {
+                let loaded = load_preview(&path, key.markdown, key.opts, false);
+                if worker_tx.send((path, key, loaded)).is_err() {
+                    break;
+                }
+            }
+        });
+        Prefetch { cache: VecDeque::new(), pending: None, tx, rx }
+    }
+
+    /// The cached preview of `path` for `key`, marked as most recently used.
+    fn get(&mut self, path: &std::path::Path, key: PreviewKey) -> Option<LoadedPreview> {
+        let index = self.cache.iter().position(|(p, k, _)| p == path && *k == key)?;
+        let entry = self.cache.remove(index)?;
+        let loaded = entry.2.clone();
+        self.cache.push_back(entry);
+        Some(loaded)
+    }
+
+    fn insert(&mut self, path: PathBuf, key: PreviewKey, loaded: LoadedPreview) {
+        self.cache.retain(|(p, _, _)| *p != path);
+        self.cache.push_back((path, key, loaded));
+        if self.cache.len() > PREFETCH_CAPACITY {
+            self.cache.pop_front();
+        }
+    }
+}
+
+/// A directory being measured on a worker thread.
+struct DirSizeJob {
+    dir: PathBuf,
+    /// Directory we were in when it started; leaving it cancels the job.
+    cwd: PathBuf,
+    started: Instant,
+    /// Totals so far, updated as the worker reports in.
+    progress: DirSize,
+    /// Running totals and, last, the final ones; dropping it stops the worker.
+    rx: Receiver<(DirSize, bool)>,
+}
+
+impl DirSizeJob {
+    fn start(dir: PathBuf, cwd: PathBuf) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let root = dir.clone();
+        std::thread::spawn(move || {
+            const REPORT_EVERY: Duration = Duration::from_millis(100);
+            let mut size = DirSize::default();
+            let mut stack = vec![root];
+            let mut last_report = Instant::now();
+            while let Some(dir) = stack.pop() {
+                let Ok(children) = fs::read_dir(&dir) else {
+                    size.skipped += 1;
+                    continue;
+                };
+                for child in children {
+                    // Symlinks aren't followed, so links to directories count as files.
+                    let Ok((path, meta)) = child.and_then(|c| Ok((c.path(), c.metadata()?))) else {
+                        size.skipped += 1;
+                        continue;
+                    };
+                    if meta.is_dir() {
+                        stack.push(path);
+                    } else {
+                        size.bytes += meta.len();
+                        size.files += 1;
+                    }
+                }
+                // A failed send means the job was dropped, so stop walking.
+                if last_report.elapsed() >= REPORT_EVERY {
+                    last_report = Instant::now();
+                    if tx.send((size, false)).is_err() {
+                        return;
+                    }
+                }
+            }
+            let _ = tx.send((size, true));
+        });
+        DirSizeJob { dir, cwd, started: Instant::now(), progress: DirSize::default(), rx }
+    }
+}
+
+/// One directory listing with its own selection and history. The app shows one, or two side by
+/// side in dual-pane mode.
+struct Pane {
+    /// Current directory we're showing.
+    cwd: PathBuf,
+    /// Entries in `cwd` (directories first, then files). Sorted by name (case-insensitive).
+    entries: Vec<DirEntry>,
+    /// Index into `entries` that is currently selected. 0 when list is empty.
+    selected: usize,
+    /// Index of the first entry shown in the list; moved during rendering to keep `selected` visible.
+    list_offset: usize,
+    /// Names of marked entries in `cwd`; batch operations act on these instead of the selection.
+    marked: HashSet<String>,
+    /// Directories (as `DirEntry::name`s) expanded in place in the list; cleared on directory change.
+    expanded: HashSet<String>,
+    /// Why `cwd` couldn't be listed (e.g. "Permission denied"), shown in the Entries panel.
+    list_error: Option<String>,
+    /// How many dotfiles in `cwd` are left out of `entries` while hidden files aren't shown.
+    hidden_count: usize,
+    /// The in-flight read of `cwd`, if it hasn't finished yet.
+    dir_load: Option<DirLoad>,
+    /// The archive `cwd` is inside, when browsing one; its contents are read-only.
+    archive: Option<PathBuf>,
+    /// Git status of changed paths under `cwd` (keyed like `DirEntry::name`), from the last listing.
+    git_status: HashMap<String, GitStatus>,
+    /// (available, total) bytes on the filesystem holding `cwd`; None if the platform can't say.
+    disk_space: Option<(u64, u64)>,
+    /// Previously visited directories with the selection they had, most recent last (Backspace goes back).
+    back_stack: Vec<(PathBuf, usize)>,
+    /// Directories we went back from, most recent last (Shift+Backspace goes forward again).
+    forward_stack: Vec<(PathBuf, usize)>,
+    /// Filesystem watcher on `cwd`. None if the platform watcher couldn't be created.
+    watcher: Option<RecommendedWatcher>,
+    /// Change notifications from `watcher`.
+    fs_events: Option<Receiver<notify::Result<notify::Event>>>,
+    /// When the first not-yet-handled change notification arrived (for debouncing).
+    fs_changed_at: Option<Instant>,
+    /// When `cwd` was last checked to still exist.
+    cwd_checked_at: Instant,
+}
+
+impl Pane {
+    fn new(cwd: PathBuf) -> Self {
+        Pane {
+            cwd,
+            entries: Vec::new(),
+            selected: 0,
+            list_offset: 0,
+            marked: HashSet::new(),
+            expanded: HashSet::new(),
+            list_error: None,
+            hidden_count: 0,
+            dir_load: None,
+            archive: None,
+            git_status: HashMap::new(),
+            disk_space: None,
+            back_stack: Vec::new(),
+            forward_stack: Vec::new(),
+            watcher: None,
+            fs_events: None,
+            fs_changed_at: None,
+            cwd_checked_at: Instant::now(),
+        }
+    }
+}
+
+/// How long a status message stays up. Errors get twice as long, as they tend to be longer and
+/// matter more.
+const STATUS_DURATION: Duration = Duration::from_secs(4);
+
+/// What a status message reports, which sets its color.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StatusKind {
+    /// A mode change, progress or a hint (yellow).
+    Info,
+    /// A completed operation (green).
+    Success,
+    /// Something that failed or couldn't be done (red).
+    Error,
+}
+
+/// A message in the status line, replacing the key hints until it expires.
+struct Status {
+    text: String,
+    kind: StatusKind,
+    shown: Instant,
+}
+
+impl Status {
+    fn color(&self) -> Color {
+        match self.kind {
+            StatusKind::Info => Color::Yellow,
+            StatusKind::Success => Color::Green,
+            StatusKind::Error => Color::Red,
+        }
+    }
+
+    fn expired(&self) -> bool {
+        let duration = if self.kind == StatusKind::Error { STATUS_DURATION * 2 } else { STATUS_DURATION };
+        self.shown.elapsed() >= duration
+    }
+}
+
+/// All state the UI needs to render and react to input.
+struct App {
+    /// The focused pane: its directory, listing and selection.
+    pane: Pane,
+    /// The unfocused pane in dual-pane mode (toggled with `|`); None with a single pane. Tab
+    /// swaps it with `pane`, so everything else only ever deals with the focused one.
+    other_pane: Option<Pane>,
+    /// Whether the focused pane is the right one, for laying the panes out.
+    pane_is_right: bool,
+    /// A submitted `!` command waiting to be run; `run_app` runs it since it needs the terminal.
+    pending_command: Option<String>,
+    /// Set by `$`: `run_app` opens a shell in `cwd`, for the same reason.
+    shell_requested: bool,
+    /// Output of the last `!` command while its pager is open.
+    command_output: Option<CommandOutput>,
+    /// The fuzzy finder overlay, while open.
+    finder: Option<FuzzyFinder>,
+    /// The last rename, create or move to the trash, for `u` to reverse.
+    last_op: Option<UndoOp>,
+    /// Scroll offset of the help overlay (?), which is open while this is Some.
+    help_scroll: Option<usize>,
+    /// Largest useful `help_scroll` for the last rendered overlay size.
+    help_scroll_max: usize,
+    /// What each key does, from `default_keymap` plus the config file's overrides.
+    keymap: HashMap<KeyBinding, Action>,
+    /// Terminal graphics protocol for image previews; None when the terminal has none.
+    image_picker: Option<Picker>,
+    /// The open preview's image, ready to draw, when it is an image and graphics are available.
+    preview_image: Option<StatefulProtocol>,
+    /// A line of the preview picked with `:` (index into `preview_content`), highlighted while the
+    /// scroll offset is still the one it was jumped to (the second value).
+    preview_line_highlight: Option<(usize, usize)>,
+    /// Where reading stopped in recently closed previews, most recent last: the path, its
+    /// modification time then, and the scroll offset. Reopening an unchanged file goes back there.
+    scroll_memory: VecDeque<(PathBuf, Option<SystemTime>, usize)>,
+    /// The open preview's modification time as listed when it was opened, for `scroll_memory`.
+    preview_modified: Option<SystemTime>,
+    /// A remembered scroll offset to apply once the opening preview's content arrives.
+    preview_scroll_restore: Option<usize>,
+    /// The preview still being loaded, if any; a spinner shows in the panel until it arrives.
+    preview_load: Option<PreviewLoad>,
+    /// Previews of the entries around the selection, loaded ahead of opening them.
+    prefetch: Prefetch,
+    /// The search highlighted in the open preview, cycled with n/N.
+    preview_search: Option<PreviewSearch>,
+    /// Whether the next preview search matches case (toggled with Tab in the search prompt).
+    preview_search_case_sensitive: bool,
+    /// What `p` pastes, if anything has been yanked or cut.
+    register: Option<Register>,
+    /// Name of the entry that was selected in each directory visited this session, restored on return.
+    last_selected: HashMap<PathBuf, String>,
+    /// When true, moving up from the first entry goes to the last and vice versa (toggled with `W`).
+    wrap_selection: bool,
+    /// Entries kept in view above and below the selection when the list scrolls (`scrolloff` in the config).
+    scrolloff: usize,
+    /// When false, entries whose name starts with '.' are hidden (except "..").
+    show_hidden: bool,
+    /// Child counts shown after directory names, when turned on (`#`, or `show_dir_counts` in the config).
+    dir_counts: Option<DirCounts>,
+    /// Show the time on the key hints bar (Ctrl+T, or `show_clock` in the config). The bar is
+    /// redrawn every tick anyway, so it keeps up without any timer of its own.
+    show_clock: bool,
+    /// Show the first lines of the selected text file under the list (K, or `show_peek` in the config).
+    show_peek: bool,
+    /// The lines shown by the peek: the file they were read from, its mtime then, and the lines.
+    /// Kept while the selection stays on the file, so it's only read again when the file changes.
+    peek: Option<(PathBuf, Option<SystemTime>, Vec<String>)>,
+    /// The directory size being worked out (`U`), if any.
+    dir_size_job: Option<DirSizeJob>,
+    /// Finished directory sizes, until a change in the directory holding them is noticed.
+    dir_sizes: HashMap<PathBuf, DirSize>,
+    /// When Some, only entries whose name matches this pattern are listed (set with `f`, cleared with Esc).
+    filter: Option<String>,
+    /// When Some, the preview panel is open showing this file's path and cached content.
+    preview_path: Option<PathBuf>,
+    /// Cached preview as styled lines (metadata + content). Set when preview_path is set.
+    preview_content: Option<Vec<Line<'static>>>,
+    /// Vertical scroll offset for the preview (number of lines scrolled down).
+    preview_scroll: usize,
+    /// Maximum scroll value for current preview (updated during rendering).
+    preview_scroll_max: usize,
+    /// True when the preview only shows part of the file (file exceeded limit).
+    preview_truncated: bool,
+    /// Where the previewed file points, if it is a symbolic link (shown in the title).
+    preview_link: Option<PathBuf>,
+    /// The text encoding the preview was decoded from, when it isn't UTF-8 (shown in the title).
+    preview_encoding: Option<&'static str>,
+    /// Lines of the preview longer than `max_line_length` are cut short (shown in the title).
+    preview_lines_cut: bool,
+    /// The preview's text without line numbers or whitespace marks, when those are drawn (what `c`
+    /// copies then).
+    preview_plain_text: Option<String>,
+    /// The preview shows the end of the file rather than its start (G on a truncated preview; gg
+    /// goes back to the start).
+    preview_tail: bool,
+    /// In follow mode (F), the previewed file's size and modification time when it was last read;
+    /// the tail is re-read whenever they change, like `tail -f`.
+    preview_follow: Option<(u64, Option<SystemTime>)>,
+    /// Open the preview of whichever entry is selected once the selection rests on it
+    /// (`auto_preview` in the config).
+    auto_preview: bool,
+    /// The open preview was opened by `auto_preview` and hasn't been entered: keys still move
+    /// the list selection rather than scrolling it.
+    preview_auto: bool,
+    /// The file the selection landed on and when, while waiting out `AUTO_PREVIEW_DELAY`.
+    auto_preview_wait: Option<(PathBuf, Instant)>,
+    /// The preview takes the whole screen, hiding the list (Z); Esc goes back to the split.
+    preview_fullscreen: bool,
+    /// The preview's share of the split with the list, in percent (`<` / `>`, remembered across runs).
+    preview_ratio: u16,
+    /// How many bytes of a file the preview reads (`preview_limit_kb` in the config; doubled with `+`).
+    preview_limit: usize,
+    /// Columns between tab stops in the preview (`tab_width` in the config).
+    tab_width: usize,
+    /// Longest line the preview shows whole (`max_line_length` in the config; 0 for no limit).
+    max_line_length: usize,
+    /// Current preview mode (raw or rendered).
+    preview_mode: PreviewMode,
+    /// When true, long preview lines wrap; when false they are clipped and scroll horizontally (toggled with `w`).
+    preview_wrap: bool,
+    /// Horizontal scroll offset (columns) for the preview in no-wrap mode.
+    preview_hscroll: usize,
+    /// Maximum horizontal scroll for the current preview (updated during rendering).
+    preview_hscroll_max: usize,
+    /// Cached (inner width, visual row count) of the wrapped preview, so the scroll range matches
+    /// what's on screen without re-wrapping the whole file every frame. Cleared when content changes.
+    preview_wrapped_rows: Option<(u16, usize)>,
+    /// First key of a multi-key command (`gg`, `m<letter>`, `'<letter>`) waiting for its second key.
+    pending_key: Option<char>,
+    /// Count typed before a command (the 5 of `5j`), while it's being entered.
+    count: Option<usize>,
+    /// When Some, incremental search is active and this is the query typed so far.
+    search_query: Option<String>,
+    /// Selection before the search started, restored if the search is cancelled.
+    search_origin: usize,
+    /// When true, raw previews get a line-number gutter (toggled with `n`).
+    show_line_numbers: bool,
+    /// When true, raw previews mark spaces, tabs and trailing whitespace (toggled with `v`).
+    show_whitespace: bool,
+    /// When true, binary files are previewed as a hex dump (toggled with `x`).
+    hex_view: bool,
+    /// Show permissions as octal (0755) instead of `rwxr-xr-x` in the details line (toggled with `O`).
+    octal_permissions: bool,
+    /// Name of the syntax highlighting theme (cycled with `T`, remembered across runs).
+    theme_name: String,
+    /// How entries are ordered (cycled with `s`).
+    sort_mode: SortMode,
+    /// Markers drawn before entry names (`icons` in the config).
+    icons: IconSet,
+    /// Colours of file names by lowercase extension, from `build_file_colors`.
+    file_colors: HashMap<String, Color>,
+    /// Reverse the natural order of `sort_mode` (toggled with `S`).
+    sort_reverse: bool,
+    /// List newest first with directories mixed in, whatever the sort settings say (toggled with `L`).
+    recent_first: bool,
+    /// Compare digit runs numerically when ordering names (`natural_sort` in the config).
+    natural_sort: bool,
+    /// Order names case-sensitively, uppercase first (toggled with `I`).
+    case_sensitive_sort: bool,
+    /// List directories before files (toggled with `e`, remembered across runs).
+    group_dirs_first: bool,
+    /// Where hidden entries go within each group (`hidden_placement` in the config).
+    hidden_placement: HiddenPlacement,
+    /// Ask for confirmation before quitting (`confirm_quit` in the config).
+    confirm_quit: bool,
+    /// What to print to stdout once the terminal is restored; only set by quitting with Q.
+    selected_output: Option<PathBuf>,
+    /// When true, entries matched by .gitignore rules are hidden (toggled with `i`).
+    respect_gitignore: bool,
+    /// The listing settings from the config (and command line) at startup, for `\` to go back to.
+    startup_view: ViewSettings,
+    /// Which modal state input goes to.
+    mode: Mode,
+    /// Text typed into the prompt while `mode` is `Mode::Input`.
+    input: String,
+    /// Message from the last operation (e.g. a filesystem error), shown in place of the key hints
+    /// until it expires.
+    status: Option<Status>,
+    /// Saved directories keyed by letter (`m<letter>` to set, `'<letter>` to jump). Persisted to disk.
+    bookmarks: HashMap<char, PathBuf>,
+    /// Screen area of the entry list (including borders) from the last render, for mouse hit-testing.
+    list_area: Rect,
+    /// Screen area of the unfocused pane's list from the last render, in dual-pane mode.
+    other_list_area: Option<Rect>,
+    /// Screen area of the preview panel from the last render, if it was shown.
+    preview_area: Option<Rect>,
+    /// Number of entry rows visible in the list during the last render (for paging).
+    list_viewport_height: usize,
+    /// Number of content rows visible in the preview during the last render (for paging).
+    preview_viewport_height: usize,
+}
+
+impl App {
+    fn new(initial_cwd: PathBuf, config: &Config, bookmarks: HashMap<char, PathBuf>) -> Self {
+        let (keymap, invalid_keys) = build_keymap(&config.keys);
+        let (file_colors, invalid_colors) = build_file_colors(&config.colors);
+        let mut app = App {
+            pane: Pane::new(initial_cwd),
+            other_pane: None,
+            pane_is_right: false,
+            keymap,
+            preview_search: None,
+            preview_search_case_sensitive: false,
+            image_picker: None,
+            preview_line_highlight: None,
+            scroll_memory: VecDeque::new(),
+            preview_modified: None,
+            preview_scroll_restore: None,
+            preview_load: None,
+            prefetch: Prefetch::new(),
+            preview_image: None,
+            register: None,
+            pending_command: None,
+            shell_requested: false,
+            command_output: None,
+            finder: None,
+            last_op: None,
+            help_scroll: None,
+            help_scroll_max: 0,
+            last_selected: HashMap::new(),
+            wrap_selection: false,
+            show_hidden: config.show_hidden,
+            scrolloff: config.scrolloff,
+            dir_counts: config.show_dir_counts.then(DirCounts::new),
+            show_clock: config.show_clock,
+            show_peek: config.show_peek,
+            peek: None,
+            dir_size_job: None,
+            dir_sizes: HashMap::new(),
+            filter: None,
+            preview_path: None,
+            preview_content: None,
+            preview_scroll: 0,
+            preview_scroll_max: 0,
+            preview_truncated: false,
+            preview_link: None,
+            preview_encoding: None,
+            preview_lines_cut: false,
+            preview_plain_text: None,
+            preview_tail: false,
+            preview_fullscreen: false,
+            preview_ratio: config.preview_ratio.clamp(*PREVIEW_RATIO_RANGE.start(), *PREVIEW_RATIO_RANGE.end()),
+            preview_follow: None,
+            preview_limit: config.preview_limit_kb.saturating_mul(1024).clamp(1024, MAX_PREVIEW_LIMIT),
+            tab_width: config.tab_width.clamp(1, 16),
+            max_line_length: config.max_line_length,
+            preview_mode: PreviewMode::Raw,
+            preview_wrap: true,
+            preview_hscroll: 0,
+            preview_hscroll_max: 0,
+            preview_wrapped_rows: None,
+            pending_key: None,
+            count: None,
+            search_query: None,
+            search_origin: 0,
+            show_line_numbers: false,
+            show_whitespace: false,
+            hex_view: false,
+            octal_permissions: false,
+            theme_name: if theme_set().themes.contains_key(&config.theme) {
+                config.theme.clone()
+            } else {
+                DEFAULT_THEME.to_string()
+            },
+            sort_mode: config.sort_mode,
+            icons: config.icons,
+            file_colors,
+            sort_reverse: false,
+            recent_first: false,
+            natural_sort: config.natural_sort,
+            case_sensitive_sort: config.case_sensitive_sort,
+            hidden_placement: config.hidden_placement,
+            group_dirs_first: config.group_dirs_first,
+            confirm_quit: config.confirm_quit,
+            auto_preview: config.auto_preview,
+            preview_auto: false,
+            auto_preview_wait: None,
+            selected_output: None,
+            respect_gitignore: false,
+            startup_view: ViewSettings {
+                sort_mode: config.sort_mode,
+                case_sensitive_sort: config.case_sensitive_sort,
+                group_dirs_first: config.group_dirs_first,
+                natural_sort: config.natural_sort,
+                hidden_placement: config.hidden_placement,
+                show_hidden: config.show_hidden,
+            },
+            mode: Mode::Browse,
+            input: String::new(),
+            status: None,
+            bookmarks,
+            list_area: Rect::default(),
+            other_list_area: None,
+            preview_area: None,
+            list_viewport_height: 0,
+            preview_viewport_height: 0,
+        };
+        app.start_dir_load(true);
+        app.watch_cwd();
+        app.update_disk_space();
+        let mut ignored = Vec::new();
+        if !invalid_keys.is_empty() {
+            ignored.push(format!("unknown key bindings: {}", invalid_keys.join(", ")));
+        }
+        if !invalid_colors.is_empty() {
+            ignored.push(format!("unknown colors: {}", invalid_colors.join(", ")));
+        }
+        if !ignored.is_empty() {
+            app.set_error(format!("Ignoring {}", ignored.join("; ")));
+        }
+        app
+    }
+
+    /// Show `text` in the status line as information (a mode change, progress or a hint).
+    fn set_status(&mut self, text: impl Into<String>) {
+        self.status = Some(Status { text: text.into(), kind: StatusKind::Info, shown: Instant::now() });
+    }
+
+    /// Show `text` in the status line as the result of a completed operation.
+    fn set_success(&mut self, text: impl Into<String>) {
+        self.status = Some(Status { text: text.into(), kind: StatusKind::Success, shown: Instant::now() });
+    }
+
+    /// Show `text` in the status line as an error.
+    fn set_error(&mut self, text: impl Into<String>) {
+        self.status = Some(Status { text: text.into(), kind: StatusKind::Error, shown: Instant::now() });
+    }
+
+    /// Show the outcome of an operation: a success message, or an error.
+    fn report(&mut self, result: Result<String, String>) {
+        match result {
+            Ok(text) => self.set_success(text),
+            Err(text) => self.set_error(text),
+        }
+    }
+
+    /// Read the first lines of the selected file for the peek when the selection moves to another
+    /// file or the file changes. Called every tick of the event loop.
+    fn poll_peek(&mut self) {
+        let target = match self.pane.entries.get(self.pane.selected) {
+            Some(entry) if self.show_peek && !entry.is_dir && entry.name != ".." && self.pane.archive.is_none() => {
+                self.pane.cwd.join(&entry.name)
+            }
+            _ => {
+                self.peek = None;
+                return;
+            }
+        };
+        // Only regular files: reading a FIFO would block until something writes to it.
+        let Some(meta) = fs::metadata(&target).ok().filter(|meta| meta.is_file()) else {
+            self.peek = None;
+            return;
+        };
+        let modified = meta.modified().ok();
+        if self.peek.as_ref().is_some_and(|(path, at, _)| *path == target && *at == modified) {
+            return;
+        }
+        let lines = peek_lines(&target);
+        self.peek = Some((target, modified, lines));
+    }
+
+    /// Clear the status message once it has been up long enough. Called every tick of the event loop.
+    fn poll_status(&mut self) {
+        if self.status.as_ref().is_some_and(Status::expired) {
+            self.status = None;
+        }
+    }
+
+    /// Point the filesystem watcher at the current `cwd`, replacing any previous watch.
+    /// Failure to watch is not fatal: the listing just won't auto-refresh.
+    fn watch_cwd(&mut self) {
+        self.pane.watcher = None;
+        self.pane.fs_events = None;
+        self.pane.fs_changed_at = None;
+        let (tx, rx) = mpsc::channel();
+        let Ok(mut watcher) = notify::recommended_watcher(tx) else {
+            return;
+        };
+        if watcher.watch(&self.pane.cwd, RecursiveMode::NonRecursive).is_ok() {
+            self.pane.watcher = Some(watcher);
+            self.pane.fs_events = Some(rx);
+        }
+    }
+
+    /// Drain pending change notifications and refresh the listing once things settle.
+    /// Events are debounced so a burst of writes (e.g. a build) causes one refresh, not hundreds.
+    fn poll_fs_events(&mut self) {
+        const DEBOUNCE: Duration = Duration::from_millis(250);
+        if let Some(rx) = self.pane.fs_events.as_ref() {
+            while let Ok(event) = rx.try_recv() {
+                let relevant = event.is_ok_and(|e| !e.kind.is_access());
+                if relevant && self.pane.fs_changed_at.is_none() {
+                    self.pane.fs_changed_at = Some(Instant::now());
+                }
+            }
+        }
+        if self.pane.fs_changed_at.is_some_and(|t| t.elapsed() >= DEBOUNCE) {
+            self.pane.fs_changed_at = None;
+            let cwd = &self.pane.cwd;
+            self.dir_sizes.retain(|dir, _| !dir.starts_with(cwd));
+            self.refresh_entries_keep_selection();
+        }
+        // The watcher only hears of `cwd` itself being deleted once nothing holds it open, and a
+        // shell started there (like the one that launched us) does, so look now and then as well.
+        // The refresh then goes up to what's left of the path.
+        if self.pane.cwd_checked_at.elapsed() >= CWD_CHECK_INTERVAL {
+            self.pane.cwd_checked_at = Instant::now();
+            if dir_is_gone(&self.pane.cwd) {
+                self.refresh_entries();
+            }
+        }
+    }
+
+    /// Switch to `dir`, selecting the first entry. Records the directory we left in the back
+    /// history and drops the forward history, like following a link in a browser.
+    fn change_dir(&mut self, dir: PathBuf) {
+        // Stay put rather than showing an unreadable directory (or archive) as if it were empty.
+        let readable = match split_archive_path(&dir) {
+            Some((archive, inner)) => archive_dir_entries(&archive, &inner).map(drop),
+            None => fs::read_dir(&dir).map(drop),
+        };
+        if let Err(e) = readable {
+            self.set_error(format!("Cannot open {}: {}", dir.display(), describe_io_error(&e)));
+            return;
+        }
+        self.visit_dir(dir);
+    }
+
+    /// Go to `dir` and record the move in the history, without checking it can be read first.
+    fn visit_dir(&mut self, dir: PathBuf) {
+        self.pane.back_stack.push((self.pane.cwd.clone(), self.pane.selected));
+        self.pane.forward_stack.clear();
+        self.load_dir(dir.clone(), 0);
+        if let Some(name) = self.last_selected.get(&dir).cloned() {
+            self.select_when_loaded(name, false);
+        }
+    }
+
+    /// Show `dir` with `selected` (clamped) as the selection, and move the watcher along.
+    /// Does not touch history; see `change_dir`.
+    fn load_dir(&mut self, dir: PathBuf, selected: usize) {
+        if let Some(entry) = self.pane.entries.get(self.pane.selected) {
+            self.last_selected.insert(self.pane.cwd.clone(), entry.name.clone());
+        }
+        self.pane.cwd = dir;
+        self.pane.selected = selected;
+        self.pane.list_offset = 0;
+        self.pane.marked.clear();
+        self.pane.expanded.clear();
+        self.pane.entries.clear();
+        self.start_dir_load(true);
+        self.watch_cwd();
+        self.update_disk_space();
+    }
+
+    /// Re-query free space for `cwd`'s filesystem. Done on directory change, not per frame.
+    fn update_disk_space(&mut self) {
+        self.pane.disk_space = fs2::available_space(&self.pane.cwd)
+            .and_then(|available| Ok((available, fs2::total_space(&self.pane.cwd)?)))
+            .ok();
+    }
+
+    /// Return to the previously visited directory (Backspace), restoring its selection.
+    fn history_back(&mut self) {
+        let Some((dir, selected)) = self.pane.back_stack.pop() else {
+            return;
+        };
+        self.pane.forward_stack.push((self.pane.cwd.clone(), self.pane.selected));
+        self.load_dir(dir, selected);
+    }
+
+    /// Redo a `history_back` (Shift+Backspace), restoring that directory's selection.
+    fn history_forward(&mut self) {
+        let Some((dir, selected)) = self.pane.forward_stack.pop() else {
+            return;
+        };
+        self.pane.back_stack.push((self.pane.cwd.clone(), self.pane.selected));
+        self.load_dir(dir, selected);
+    }
+
+    /// Go to the parent of `cwd` (h, or Enter on ".."), selecting the directory we came from.
+    /// No-op at the root. An unreadable parent is entered all the same, showing why it can't be
+    /// listed: staying put would leave no way past an ancestor whose permissions changed.
+    fn go_parent(&mut self) {
+        let Some(parent) = self.pane.cwd.parent().map(|p| p.to_path_buf()) else {
+            return;
+        };
+        let child = self.pane.cwd.file_name().map(|n| n.to_string_lossy().into_owned());
+        if split_archive_path(&parent).is_none()
+            && let Err(e) = fs::read_dir(&parent)
+        {
+            self.visit_dir(parent.clone());
+            self.set_error(format!("Cannot read {}: {}", parent.display(), describe_io_error(&e)));
+            return;
+        }
+        self.change_dir(parent.clone());
+        // change_dir stays put if the parent can't be read.
+        if let Some(child) = child
+            && self.pane.cwd == parent
+        {
+            self.select_when_loaded(child, false);
+        }
+    }
+
+    /// Re-read the current directory and set `entries`, keeping the current listing on screen
+    /// until the new one is complete. Clamps the selection once it is.
+    fn refresh_entries(&mut self) {
+        self.start_dir_load(false);
+    }
+
+    /// Start reading `cwd` on a worker thread, cancelling any read still in flight so its stale
+    /// results never show. Most directories finish within `LOAD_WAIT`, and those are applied
+    /// before returning; bigger ones arrive through `poll_dir_load` while the UI stays live.
+    fn start_dir_load(&mut self, stream: bool) {
+        const LOAD_WAIT: Duration = Duration::from_millis(50);
+        let (tx, rx) = mpsc::channel();
+        self.pane.archive = split_archive_path(&self.pane.cwd).map(|(archive, _)| archive);
+        let (dir, sort, respect_gitignore) = (self.pane.cwd.clone(), self.sort_options(), self.respect_gitignore);
+        std::thread::spawn(move || stream_dir_entries(&dir, sort, respect_gitignore, &tx));
+        // A selection requested of the superseded load still applies to this one.
+        let select = self.pane.dir_load.take().and_then(|load| load.select);
+        self.pane.dir_load = Some(DirLoad { rx, stream, select });
+        self.poll_dir_load(LOAD_WAIT);
+    }
+
+    /// Take in what the directory worker has sent, waiting up to `wait` for it to finish.
+    /// Called every tick of the event loop with no wait.
+    fn poll_dir_load(&mut self, wait: Duration) {
+        let Some(load) = self.pane.dir_load.as_ref() else {
+            return;
+        };
+        let deadline = Instant::now() + wait;
+        let mut partial = None;
+        let outcome = loop {
+            match load.rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                // Each snapshot supersedes the previous one.
+                Ok(DirLoadMsg::Partial(entries)) => partial = Some(entries),
+                Ok(DirLoadMsg::Done(listing)) => break Some(Ok(listing)),
+                Ok(DirLoadMsg::Failed(e)) => break Some(Err(e)),
+                Err(RecvTimeoutError::Timeout) => break None,
+                Err(RecvTimeoutError::Disconnected) => break Some(Err(io::Error::other("listing was interrupted"))),
+            }
+        };
+        match (outcome, partial) {
+            (Some(result), _) => {
+                let select = self.pane.dir_load.take().and_then(|load| load.select);
+                self.finish_dir_load(result, select);
+            }
+            (None, Some(mut entries)) if load.stream => {
+                // Gitignore and the tree wait for the complete listing.
+                self.filter_listing_with(&mut entries, None);
+                self.pane.entries = entries;
+            }
+            (None, _) => {}
+        }
+    }
+
+    /// Set `entries` from a completed read of `cwd`.
+    fn finish_dir_load(
+        &mut self,
+        result: io::Result<DirListing>,
+        select: Option<(String, bool)>,
+    ) {
+        let mut entries = match result {
+            Ok(DirListing { mut entries, gitignore_visible, git_status }) => {
+                self.pane.list_error = None;
+                self.pane.hidden_count = if self.show_hidden {
+                    0
+                } else {
+                    entries.iter().filter(|e| e.name != ".." && e.file_name().starts_with('.')).count()
+                };
+                self.filter_listing_with(&mut entries, gitignore_visible.as_ref());
+                self.pane.git_status = git_status;
+                entries
+            }
+            Err(e) => {
+                // Deleted from under us (by another process, or a shell command): go up to what is
+                // left of the path, as re-reading would only fail again.
+                if dir_is_gone(&self.pane.cwd)
+                    && let Some(ancestor) = self.pane.cwd.ancestors().skip(1).find(|dir| dir.is_dir())
+                {
+                    let (gone, ancestor) = (self.pane.cwd.clone(), ancestor.to_path_buf());
+                    self.load_dir(ancestor.clone(), 0);
+                    self.set_status(format!("{} no longer exists; moved up to {}", gone.display(), ancestor.display()));
+                    return;
+                }
+                // Keep ".." so the way back up stays visible.
+                self.pane.list_error = Some(describe_io_error(&e));
+                self.pane.hidden_count = 0;
+                self.pane.git_status.clear();
+                self.pane.cwd.parent().map(|_| parent_entry()).into_iter().collect()
+            }
+        };
+        if !self.pane.expanded.is_empty() {
+            entries = self.with_expanded_children(entries);
+        }
+        // Files here may have changed (or this is another directory), so prefetch afresh.
+        self.prefetch.cache.clear();
+        // The directories here may have changed too, so count them again as they come into view.
+        if let Some(dir_counts) = self.dir_counts.as_mut() {
+            let cwd = &self.pane.cwd;
+            dir_counts.counts.retain(|dir, _| !dir.starts_with(cwd));
+        }
+        // Forget marks on entries that no longer exist (or are now filtered out).
+        self.pane.marked.retain(|name| entries.iter().any(|e| e.name == *name));
+        self.pane.entries = entries;
+        // Clamp selection so we don't point past the end after refresh (e.g. after going up).
+        self.pane.selected = self.pane.selected.min(self.pane.entries.len().saturating_sub(1));
+        if let Some((name, open)) = select {
+            self.select_entry(&name, open);
+        }
+    }
+
+    /// Drop the entries of `dir`'s listing that the hidden-file and gitignore settings hide.
+    fn filter_listing(&self, dir: &std::path::Path, entries: &mut Vec<DirEntry>) {
+        let visible = if self.respect_gitignore { gitignore_visible_names(dir) } else { None };
+        self.filter_listing_with(entries, visible.as_ref());
+    }
+
+    /// `filter_listing` with the gitignore-visible names already worked out (None: no filter).
+    fn filter_listing_with(&self, entries: &mut Vec<DirEntry>, gitignore_visible: Option<&HashSet<String>>) {
+        if !self.show_hidden {
+            entries.retain(|e| e.name == ".." || !e.file_name().starts_with('.'));
+        }
+        if let Some(pattern) = self.filter.as_deref() {
+            entries.retain(|e| e.name == ".." || filter_matches(pattern, e.file_name()));
+        }
+        if let Some(visible) = gitignore_visible {
+            entries.retain(|e| e.name == ".." || visible.contains(e.file_name()));
+        }
+    }
+
+    /// Insert the children of expanded directories beneath them, recursively. Children are
+    /// only read for directories that are expanded, so large trees cost nothing until opened.
+    fn with_expanded_children(&self, entries: Vec<DirEntry>) -> Vec<DirEntry> {
+        let mut out = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let expand = entry.is_dir && entry.name != ".." && self.pane.expanded.contains(&entry.name);
+            let (name, depth) = (entry.name.clone(), entry.depth);
+            out.push(entry);
+            if !expand {
+                continue;
+            }
+            let dir = self.pane.cwd.join(&name);
+            let Ok(mut children) = read_dir_entries(&dir, self.sort_options()) else {
+                continue;
+            };
+            children.retain(|c| c.name != "..");
+            self.filter_listing(&dir, &mut children);
+            for child in &mut children {
+                child.name = std::path::Path::new(&name).join(&child.name).to_string_lossy().into_owned();
+                child.depth = depth + 1;
+            }
+            out.extend(self.with_expanded_children(children));
+        }
+        out
+    }
+
+    /// Expand the selected directory in place, or collapse it if expanded (z). On a file inside
+    /// an expanded directory, collapses that directory and selects it.
+    fn toggle_expand(&mut self) {
+        let Some(entry) = self.pane.entries.get(self.pane.selected) else {
+            return;
+        };
+        if entry.name == ".." {
+            return;
+        }
+        let dir = if entry.is_dir {
+            entry.name.clone()
+        } else if let Some(parent) = std::path::Path::new(&entry.name).parent().filter(|_| entry.depth > 0) {
+            parent.to_string_lossy().into_owned()
+        } else {
+            return;
+        };
+        if self.pane.expanded.contains(&dir) {
+            // Collapsing also forgets expanded directories below it.
+            self.pane.expanded.retain(|p| !std::path::Path::new(p).starts_with(&dir));
+        } else {
+            self.pane.expanded.insert(dir.clone());
+        }
+        self.refresh_entries();
+        self.select_when_loaded(dir, false);
+    }
+
+    /// Select the entry with the given name, if present. Returns whether it was found.
+    fn select_by_name(&mut self, name: &str) -> bool {
+        match self.pane.entries.iter().position(|e| e.name == name) {
+            Some(i) => {
+                self.pane.selected = i;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Select `name` now, or once the listing in progress completes. Returns whether it was
+    /// found, or None if that is still to be seen.
+    fn select_when_loaded(&mut self, name: String, open: bool) -> Option<bool> {
+        match self.pane.dir_load.as_mut() {
+            Some(load) => {
+                load.select = Some((name, open));
+                None
+            }
+            None => Some(self.select_entry(&name, open)),
+        }
+    }
+
+    /// Select `name` and, if `open`, open it (reporting it when it isn't listed). Returns whether
+    /// it was found.
+    fn select_entry(&mut self, name: &str, open: bool) -> bool {
+        let found = self.select_by_name(name);
+        if open && found {
+            self.enter_selected();
+        } else if open {
+            // e.g. a hidden file while hidden files are filtered out.
+            self.set_error(format!("{} is not shown in the current listing", name));
+        }
+        found
+    }
+
+    /// Run `f` with the unfocused pane standing in as `pane`, so code written for the focused pane
+    /// can load or update the other one. None with a single pane.
+    fn with_other_pane<R>(&mut self, f: impl FnOnce(&mut App) -> R) -> Option<R> {
+        let mut other = self.other_pane.take()?;
+        std::mem::swap(&mut self.pane, &mut other);
+        let result = f(self);
+        std::mem::swap(&mut self.pane, &mut other);
+        self.other_pane = Some(other);
+        Some(result)
+    }
+
+    /// Open a second pane on the current directory beside this one, or close it again (|).
+    fn toggle_dual_pane(&mut self) {
+        if self.other_pane.take().is_some() {
+            self.pane_is_right = false;
+            self.other_list_area = None;
+            return;
+        }
+        self.other_pane = Some(Pane::new(self.pane.cwd.clone()));
+        self.with_other_pane(|app| {
+            app.start_dir_load(true);
+            app.watch_cwd();
+            app.update_disk_space();
+        });
+        self.set_status("Dual-pane mode: Tab switches panes; yy and dd copy and move to the other one");
+    }
+
+    /// Move the focus to the other pane in dual-pane mode (Tab).
+    fn switch_pane(&mut self) {
+        if let Some(other) = self.other_pane.as_mut() {
+            std::mem::swap(&mut self.pane, other);
+            self.pane_is_right = !self.pane_is_right;
+            self.search_query = None;
+        }
+    }
+
+    /// Copy or move the batch targets straight into the other pane's directory (yy and dd in
+    /// dual-pane mode), going through the register so collisions get the usual prompt.
+    fn transfer_to_other_pane(&mut self, mode: PasteMode) {
+        self.fill_register(mode);
+        if self.register.is_some() {
+            self.with_other_pane(|app| app.paste());
+        }
+    }
+
+    /// Answer the overwrite prompt, pasting into whichever pane's directory the collisions are in.
+    fn resolve_overwrite(&mut self, overwrite: bool) {
+        let Mode::ConfirmOverwrite(existing) = &self.mode else {
+            return;
+        };
+        let into_other = existing.first().and_then(|path| path.parent()) != Some(self.pane.cwd.as_path());
+        if !into_other || self.with_other_pane(|app| app.paste_register(overwrite)).is_none() {
+            self.paste_register(overwrite);
+        }
+    }
+
+    /// Re-read entries but keep the cursor on the same entry (by name) if it still exists.
+    fn refresh_entries_keep_selection(&mut self) {
+        let current = self.pane.entries.get(self.pane.selected).map(|e| e.name.clone());
+        self.refresh_entries();
+        if let Some(name) = current {
+            self.select_when_loaded(name, false);
+        }
+    }
+
+    /// Toggle hiding of gitignored entries (i).
+    fn toggle_gitignore(&mut self) {
+        self.respect_gitignore = !self.respect_gitignore;
+        self.refresh_entries_keep_selection();
+    }
+
+    fn sort_options(&self) -> SortOptions {
+        if self.recent_first {
+            return SortOptions {
+                mode: SortMode::Modified,
+                reverse: false,
+                natural: self.natural_sort,
+                case_sensitive: self.case_sensitive_sort,
+                dirs_first: false,
+                hidden: HiddenPlacement::Interleave,
+            };
+        }
+        SortOptions {
+            mode: self.sort_mode,
+            reverse: self.sort_reverse,
+            natural: self.natural_sort,
+            case_sensitive: self.case_sensitive_sort,
+            dirs_first: self.group_dirs_first,
+            hidden: self.hidden_placement,
+        }
+    }
+
+    /// Switch to the next sort mode (s), leaving the newest-first view.
+    fn cycle_sort_mode(&mut self) {
+        self.recent_first = false;
+        self.sort_mode = self.sort_mode.next();
+        self.refresh_entries_keep_selection();
+    }
+
+    /// Reverse the current sort order (S), leaving the newest-first view.
+    fn toggle_sort_reverse(&mut self) {
+        self.recent_first = false;
+        self.sort_reverse = !self.sort_reverse;
+        self.refresh_entries_keep_selection();
+    }
+
+    /// Switch between ignoring case and plain character order when sorting names (I).
+    fn toggle_sort_case(&mut self) {
+        self.case_sensitive_sort = !self.case_sensitive_sort;
+        self.set_status(if self.case_sensitive_sort {
+            "Sorting names case-sensitively"
+        } else {
+            "Sorting names ignoring case"
+        });
+        self.refresh_entries_keep_selection();
+    }
+
+    /// Switch between listing directories before files and sorting them in together (e).
+    fn toggle_dirs_first(&mut self) {
+        self.group_dirs_first = !self.group_dirs_first;
+        self.set_status(if self.group_dirs_first {
+            "Listing directories first"
+        } else {
+            "Sorting directories in with files"
+        });
+        self.refresh_entries_keep_selection();
+    }
+
+    /// Switch between the newest-first view and the usual sort settings (L), which it leaves
+    /// alone so they come back as they were.
+    fn toggle_recent_first(&mut self) {
+        self.recent_first = !self.recent_first;
+        self.set_status(if self.recent_first {
+            "Listing the most recently modified first".to_string()
+        } else {
+            format!("Sorting by {} again", self.sort_mode.label())
+        });
+        self.refresh_entries_keep_selection();
+    }
+
+    /// Go back to a known view of the listing (`\`): no filter or search, gitignored entries
+    /// listed, and sorting and hidden entries as the session started. That is the configured sort
+    /// rather than a fixed name order, so resetting leaves nothing new to save in the config.
+    fn reset_view(&mut self) {
+        let view = self.startup_view;
+        self.filter = None;
+        self.search_query = None;
+        self.sort_mode = view.sort_mode;
+        self.sort_reverse = false;
+        self.recent_first = false;
+        self.case_sensitive_sort = view.case_sensitive_sort;
+        self.group_dirs_first = view.group_dirs_first;
+        self.natural_sort = view.natural_sort;
+        self.hidden_placement = view.hidden_placement;
+        self.show_hidden = view.show_hidden;
+        self.respect_gitignore = false;
+        self.set_status("View reset to the startup settings");
+        self.refresh_entries_keep_selection();
+    }
+
+    /// Show or hide the child counts of directories (#).
+    fn toggle_dir_counts(&mut self) {
+        self.dir_counts = match self.dir_counts.take() {
+            Some(_) => None,
+            None => Some(DirCounts::new()),
+        };
+    }
+
+    /// Work out the total size of the selected directory in the background (U), or show it
+    /// straight away if it's known.
+    fn measure_selected_dir(&mut self) {
+        let Some(entry) = self.pane.entries.get(self.pane.selected) else {
+            return;
+        };
+        if !entry.is_dir || entry.name == ".." {
+            self.set_status("Select a directory to add up its size");
+            return;
+        }
+        if self.pane.archive.is_some() {
+            self.set_error("Sizes can't be added up inside archives");
+            return;
+        }
+        let dir = self.pane.cwd.join(&entry.name);
+        if let Some(size) = self.dir_sizes.get(&dir) {
+            self.set_success(format!("{}: {}", entry.name, size.describe()));
+        } else if self.dir_size_job.as_ref().is_none_or(|job| job.dir != dir) {
+            self.dir_size_job = Some(DirSizeJob::start(dir, self.pane.cwd.clone()));
+        }
+    }
+
+    /// Show the progress of the directory size being worked out, and its result once done.
+    /// Called every tick of the event loop.
+    fn poll_dir_size(&mut self) {
+        let Some(job) = self.dir_size_job.as_mut() else {
+            return;
+        };
+        if job.cwd != self.pane.cwd {
+            self.dir_size_job = None;
+            return;
+        }
+        let mut done = false;
+        while let Ok((size, finished)) = job.rx.try_recv() {
+            job.progress = size;
+            done |= finished;
+        }
+        let name = display_name(&job.dir);
+        if done {
+            let text = format!("{}: {}", name, job.progress.describe());
+            self.dir_sizes.insert(job.dir.clone(), job.progress);
+            self.dir_size_job = None;
+            self.set_success(text);
+        } else {
+            let text = format!("{} Adding up {}: {} so far", spinner_frame(job.started), name, job.progress.describe());
+            self.set_status(text);
+        }
+    }
+
+    /// Collect finished child counts and send the directories now on screen that haven't been
+    /// counted to the worker. Called every tick of the event loop, for each pane.
+    fn poll_dir_counts(&mut self) {
+        let Some(dir_counts) = self.dir_counts.as_mut() else {
+            return;
+        };
+        while let Ok((dir, count)) = dir_counts.rx.try_recv() {
+            dir_counts.pending.remove(&dir);
+            dir_counts.counts.insert(dir, count);
+        }
+        // Directories inside archives are made up from the archive's index, not on disk.
+        if self.pane.archive.is_some() {
+            return;
+        }
+        let visible = self.pane.entries.iter().skip(self.pane.list_offset).take(self.list_viewport_height);
+        for entry in visible.filter(|e| e.is_dir && e.name != "..") {
+            let dir = self.pane.cwd.join(&entry.name);
+            if !dir_counts.counts.contains_key(&dir) && dir_counts.pending.insert(dir.clone()) {
+                let _ = dir_counts.tx.send(dir);
+            }
+        }
+    }
+
+    /// Move selection up by one. At the top, wraps to the bottom if `wrap_selection` is set.
+    fn selection_up(&mut self) {
+        if self.pane.entries.is_empty() {
+            return;
+        }
+        if self.pane.selected == 0 && self.wrap_selection {
+            self.pane.selected = self.pane.entries.len() - 1;
+        } else {
+            self.pane.selected = self.pane.selected.saturating_sub(1);
+        }
+    }
+
+    /// Move selection down by one. At the bottom, wraps to the top if `wrap_selection` is set.
+    fn selection_down(&mut self) {
+        if self.pane.entries.is_empty() {
+            return;
+        }
+        if self.pane.selected + 1 >= self.pane.entries.len() && self.wrap_selection {
+            self.pane.selected = 0;
+        } else {
+            self.pane.selected = (self.pane.selected + 1).min(self.pane.entries.len() - 1);
+        }
+    }
+
+    /// Move the selection to the next (or previous) directory, skipping files and "..". Past the
+    /// last one, wraps around if `wrap_selection` is set and stays put otherwise.
+    fn select_next_dir(&mut self, forward: bool) {
+        let len = self.pane.entries.len();
+        let selected = self.pane.selected;
+        // Every other entry in the direction of travel, continuing from the other end.
+        let found = (1..len)
+            .map(|step| if forward { (selected + step) % len } else { (selected + len - step) % len })
+            .filter(|&i| self.wrap_selection || (i > selected) == forward)
+            .find(|&i| self.pane.entries[i].is_dir && self.pane.entries[i].name != "..");
+        if let Some(i) = found {
+            self.pane.selected = i;
+        }
+    }
+
+    /// Toggle whether moving past either end of the list wraps around (W).
+    fn toggle_wrap_selection(&mut self) {
+        self.wrap_selection = !self.wrap_selection;
+        self.set_status(if self.wrap_selection {
+            "Selection wraps around"
+        } else {
+            "Selection stops at the ends"
+        });
+    }
+
+    /// Move the selection by `delta` rows, stopping at either end (no wrapping for page jumps).
+    fn move_selection_by(&mut self, delta: isize) {
+        if self.pane.entries.is_empty() {
+            return;
+        }
+        let last = self.pane.entries.len() - 1;
+        self.pane.selected = self.pane.selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// Page the selection, or the preview when it's open, by `pages` viewports (negative = up).
+    /// Half pages (Ctrl-d/u) pass ±0.5.
+    fn page(&mut self, pages: f32) {
+        let (height, preview) = if self.preview_content.is_some() {
+            (self.preview_viewport_height, true)
+        } else {
+            (self.list_viewport_height, false)
+        };
+        let rows = ((height.max(1) as f32) * pages.abs()).max(1.0) as isize;
+        let delta = if pages < 0.0 { -rows } else { rows };
+        if preview {
+            self.preview_scroll = self.preview_scroll.saturating_add_signed(delta).min(self.preview_scroll_max);
+        } else {
+            self.move_selection_by(delta);
+        }
+    }
+
+    /// Jump selection to the first entry (gg).
+    fn select_first(&mut self) {
+        self.pane.selected = 0;
+    }
+
+    /// Jump selection to the last entry (G).
+    fn select_last(&mut self) {
+        self.pane.selected = self.pane.entries.len().saturating_sub(1);
+    }
+
+    /// Begin incremental search (/), remembering the current selection so Esc can restore it.
+    fn start_search(&mut self) {
+        self.search_query = Some(String::new());
+        self.search_origin = self.pane.selected;
+    }
+
+    /// Append a character to the search query and jump to the first match.
+    fn push_search_char(&mut self, c: char) {
+        if let Some(query) = self.search_query.as_mut() {
+            query.push(c);
+        }
+        self.update_search_selection();
+    }
+
+    /// Remove the last character of the search query and re-run the match.
+    fn pop_search_char(&mut self) {
+        if let Some(query) = self.search_query.as_mut() {
+            query.pop();
+        }
+        self.update_search_selection();
+    }
+
+    /// Finish searching and keep the selection where the search left it (Enter).
+    fn commit_search(&mut self) {
+        self.search_query = None;
+    }
+
+    /// Abort searching and put the selection back where it was (Esc).
+    fn cancel_search(&mut self) {
+        self.search_query = None;
+        self.pane.selected = self.search_origin.min(self.pane.entries.len().saturating_sub(1));
+    }
+
+    /// Select the first entry whose name contains the query (case-insensitive).
+    /// An empty query goes back to the original selection; no match leaves the selection alone.
+    fn update_search_selection(&mut self) {
+        let Some(query) = self.search_query.as_ref() else {
+            return;
+        };
+        if query.is_empty() {
+            self.pane.selected = self.search_origin.min(self.pane.entries.len().saturating_sub(1));
+            return;
+        }
+        let needle = query.to_lowercase();
+        if let Some(i) = self
+            .pane
+            .entries
+            .iter()
+            .position(|e| e.file_name().to_lowercase().contains(&needle))
+        {
+            self.pane.selected = i;
+        }
+    }
+
+    /// Enter the selected directory (if it's a dir) or go to parent if selection is "..".
+    fn enter_selected(&mut self) {
+        let Some(entry) = self.pane.entries.get(self.pane.selected) else {
+            return;
+        };
+        if entry.name == ".." {
+            self.go_parent();
+            return;
+        }
+        if entry.is_dir {
+            let next = self.pane.cwd.join(&entry.name);
+            if entry.is_symlink {
+                // Resolve the link so `cwd` stays a real path; this also fails on broken or looping links.
+                match fs::canonicalize(&next) {
+                    Ok(resolved) if resolved.is_dir() => self.change_dir(resolved),
+                    _ => self.set_error(format!("Cannot follow link {}", entry.name)),
+                }
+            } else if next.is_dir() || self.pane.archive.is_some() {
+                self.change_dir(next);
+            }
+            return;
+        }
+        // An archive opens like a directory (but not one inside another archive).
+        let path = self.pane.cwd.join(&entry.name);
+        if path.is_file() && ArchiveKind::of(&path).is_some() {
+            self.change_dir(path);
+            return;
+        }
+        // File: open preview panel on the right. A link that leads nowhere opens too, to say why.
+        let dangling = entry.is_symlink && !path.exists();
+        let modified = entry.modified;
+        if path.is_file() || dangling || self.pane.archive.is_some() {
+            self.preview_auto = false;
+            self.remember_preview_scroll();
+            self.preview_scroll_restore = self.recall_preview_scroll(&path, modified);
+            self.preview_modified = modified;
+            // Determine if this is a markdown file
+            let is_markdown = path.extension().and_then(|e| e.to_str()) == Some("md");
+            let rendered = is_markdown || is_json_path(&path) || csv_delimiter(&path).is_some();
+            let mode = if rendered { PreviewMode::Rendered } else { PreviewMode::Raw };
+            
+            self.preview_path = Some(path);
+            self.preview_mode = mode;
+            self.preview_tail = false;
+            self.preview_follow = None;
+            self.reload_preview();
+        }
+    }
+
+    /// (Re)load the content of the open preview using the current mode and display options, on a
+    /// worker thread. Resets the scroll position; a caller may set it again straight after, as
+    /// the result doesn't touch it. Most files finish within `LOAD_WAIT` and are in place on
+    /// return; bigger ones arrive through `poll_preview_load`. No-op if the preview is closed.
+    fn reload_preview(&mut self) {
+        const LOAD_WAIT: Duration = Duration::from_millis(50);
+        let Some(path) = self.preview_path.clone() else {
+            return;
+        };
+        let is_markdown = path.extension().and_then(|e| e.to_str()) == Some("md");
+        let markdown = self.preview_mode == PreviewMode::Rendered && is_markdown;
+        let opts = self.preview_options();
+        // With a graphics-capable terminal, images are drawn instead of their text summary.
+        let decode_image = self.image_picker.is_some() && is_image_path(&path) && !self.hex_view;
+        self.preview_link = fs::read_link(&path).ok();
+        self.preview_scroll = 0;
+        self.preview_scroll_max = 0;
+        self.preview_hscroll = 0;
+        self.preview_hscroll_max = 0;
+        // A directory (only ever opened by `auto_preview`) shows what it holds, as it would be listed.
+        let listing = path.is_dir().then(|| (self.sort_options(), self.show_hidden, self.icons));
+        if let Some(loaded) = PreviewKey::of(&path, markdown, opts)
+            .filter(|_| !decode_image && listing.is_none())
+            .and_then(|key| self.prefetch.get(&path, key))
+        {
+            self.preview_load = None;
+            self.show_loaded_preview(loaded);
+            return;
+        }
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let loaded = match listing {
+                Some((sort, show_hidden, icons)) => load_dir_preview(&path, sort, show_hidden, icons),
+                None => load_preview(&path, markdown, opts, decode_image),
+            };
+            // The receiver is gone if another preview replaced this one.
+            let _ = tx.send(loaded);
+        });
+        self.preview_load = Some(PreviewLoad { started: Instant::now(), rx });
+        self.poll_preview_load(LOAD_WAIT);
+    }
+
+    /// Start loading the previews of the selected entry and its neighbours that the prefetch
+    /// cache doesn't have, one at a time, as `enter_selected` would open them; and collect what
+    /// the worker has finished. Called every tick of the event loop.
+    fn poll_prefetch(&mut self) {
+        while let Ok((path, key, loaded)) = self.prefetch.rx.try_recv() {
+            self.prefetch.pending = None;
+            self.prefetch.insert(path, key, loaded);
+        }
+        if self.prefetch.pending.is_some() {
+            return;
+        }
+        let around = self.pane.selected.saturating_sub(1)..=self.pane.selected + 1;
+        let candidates: Vec<PathBuf> = around
+            .filter_map(|i| self.pane.entries.get(i))
+            .filter(|e| !e.is_dir && !e.is_symlink && e.size <= PREFETCH_MAX_SIZE && self.pane.archive.is_none())
+            .map(|e| self.pane.cwd.join(&e.name))
+            // Images are decoded for drawing when they're opened, which isn't cached.
+            .filter(|path| !(self.image_picker.is_some() && is_image_path(path)) && ArchiveKind::of(path).is_none())
+            .collect();
+        for path in candidates {
+            let markdown = path.extension().and_then(|e| e.to_str()) == Some("md");
+            let rendered = markdown || is_json_path(&path) || csv_delimiter(&path).is_some();
+            let opts = PreviewOptions { pretty_json: rendered, csv_table: rendered, tail: false, ..self.preview_options() };
+            let Some(key) = PreviewKey::of(&path, markdown, opts) else {
+                continue;
+            };
+            if !self.prefetch.cache.iter().any(|(p, k, _)| *p == path && *k == key) {
+                self.prefetch.pending = Some(path.clone());
+                let _ = self.prefetch.tx.send((path, key));
+                return;
+            }
+        }
+    }
+
+    /// Preview the selected entry once the selection has stayed on it for `AUTO_PREVIEW_DELAY`, so
+    /// scrolling past entries doesn't load each one. A directory is previewed as a listing of what
+    /// it holds, without going into it. Only previews it opened itself are touched. Called every
+    /// tick of the event loop.
+    fn poll_auto_preview(&mut self) {
+        let browsing = self.mode == Mode::Browse && self.finder.is_none();
+        if !self.auto_preview || !browsing || (self.preview_path.is_some() && !self.preview_auto) {
+            self.auto_preview_wait = None;
+            return;
+        }
+        let target = self
+            .pane
+            .entries
+            .get(self.pane.selected)
+            .filter(|e| e.name != ".." && !(e.is_dir && self.pane.archive.is_some()))
+            .map(|e| self.pane.cwd.join(&e.name))
+            // Archives open as directories rather than previews.
+            .filter(|path| self.pane.archive.is_some() || ArchiveKind::of(path).is_none());
+        if target == self.preview_path {
+            self.auto_preview_wait = None;
+            return;
+        }
+        let Some(target) = target else {
+            if self.preview_path.is_some() {
+                self.close_preview();
+            }
+            return;
+        };
+        match &self.auto_preview_wait {
+            Some((path, since)) if *path == target => {
+                if since.elapsed() >= AUTO_PREVIEW_DELAY {
+                    self.auto_preview_wait = None;
+                    if target.is_dir() {
+                        self.preview_dir(target);
+                    } else {
+                        self.enter_selected();
+                    }
+                    self.preview_auto = self.preview_path.is_some();
+                }
+            }
+            _ => self.auto_preview_wait = Some((target, Instant::now())),
+        }
+    }
+
+    /// Show what the directory at `path` holds in the preview, leaving `cwd` where it is.
+    fn preview_dir(&mut self, path: PathBuf) {
+        self.remember_preview_scroll();
+        self.preview_modified = None;
+        self.preview_scroll_restore = None;
+        self.preview_path = Some(path);
+        self.preview_mode = PreviewMode::Raw;
+        self.preview_tail = false;
+        self.preview_follow = None;
+        self.reload_preview();
+    }
+
+    /// Put the loaded preview in place once the worker has sent it, waiting up to `wait` for it.
+    /// Called every tick of the event loop with no wait.
+    fn poll_preview_load(&mut self, wait: Duration) {
+        let Some(load) = self.preview_load.as_ref() else {
+            return;
+        };
+        let loaded = match load.rx.recv_timeout(wait) {
+            Ok(loaded) => loaded,
+            Err(RecvTimeoutError::Timeout) => return,
+            Err(RecvTimeoutError::Disconnected) => LoadedPreview::plain(vec![plain_line("(the preview couldn't be loaded)")]),
+        };
+        self.preview_load = None;
+        self.show_loaded_preview(loaded);
+    }
+
+    /// Put a loaded preview's content in place of the old one.
+    fn show_loaded_preview(&mut self, loaded: LoadedPreview) {
+        if let Some(scroll) = self.preview_scroll_restore.take() {
+            // Clamped to the content when drawn.
+            self.preview_scroll = scroll;
+        }
+        self.preview_content = Some(loaded.content);
+        self.preview_truncated = loaded.truncated;
+        self.preview_encoding = loaded.encoding;
+        self.preview_lines_cut = loaded.lines_cut;
+        self.preview_plain_text = loaded.plain_text;
+        self.preview_image = match (self.image_picker.as_ref(), loaded.image) {
+            (Some(picker), Some(image)) => Some(picker.new_resize_protocol(image)),
+            _ => None,
+        };
+        self.preview_wrapped_rows = None;
+        // Line numbers or a different view change the text, so find the matches again.
+        if let Some(search) = self.preview_search.as_mut()
+            && let Some(content) = self.preview_content.as_ref()
+        {
+            search.matches = find_matches(content, &search.query, search.case_sensitive);
+            search.current = 0;
+        }
+    }
+
+    /// Note where the open preview is scrolled to, so reopening the file goes back there. The
+    /// oldest position is forgotten once `SCROLL_MEMORY_CAPACITY` are kept. The end of a large
+    /// file isn't remembered, as its offsets don't apply to the start.
+    fn remember_preview_scroll(&mut self) {
+        let Some(path) = self.preview_path.clone() else {
+            return;
+        };
+        self.scroll_memory.retain(|(p, _, _)| *p != path);
+        if self.preview_scroll > 0 && !self.preview_tail {
+            if self.scroll_memory.len() >= SCROLL_MEMORY_CAPACITY {
+                self.scroll_memory.pop_front();
+            }
+            self.scroll_memory.push_back((path, self.preview_modified, self.preview_scroll));
+        }
+    }
+
+    /// The scroll offset `path` was left at, if it hasn't been modified since. A changed file
+    /// starts from the top, and its old position is forgotten.
+    fn recall_preview_scroll(&mut self, path: &std::path::Path, modified: Option<SystemTime>) -> Option<usize> {
+        let i = self.scroll_memory.iter().position(|(p, _, _)| p == path)?;
+        let (_, then, scroll) = self.scroll_memory.remove(i)?;
+        (then == modified).then_some(scroll)
+    }
+
+    /// Close the preview panel if open.
+    fn close_preview(&mut self) {
+        self.remember_preview_scroll();
+        self.preview_path = None;
+        self.preview_auto = false;
+        self.preview_search = None;
+        self.preview_content = None;
+        self.preview_image = None;
+        self.preview_load = None;
+        self.preview_line_highlight = None;
+        self.preview_scroll = 0;
+        self.preview_scroll_max = 0;
+        self.preview_scroll_restore = None;
+        self.preview_truncated = false;
+        self.preview_link = None;
+        self.preview_encoding = None;
+        self.preview_lines_cut = false;
+        self.preview_plain_text = None;
+        self.preview_tail = false;
+        self.preview_follow = None;
+        self.preview_fullscreen = false;
+        self.preview_mode = PreviewMode::Raw;
+        self.preview_hscroll = 0;
+        self.preview_hscroll_max = 0;
+    }
+
+    /// Switch the preview between the side panel and the whole screen (Z). With no preview open,
+    /// opens the selected file straight into full screen.
+    fn toggle_preview_fullscreen(&mut self) {
+        if self.preview_path.is_none() {
+            // Only a file opens; entering a directory (or an archive) would just move there.
+            let opens_preview = self.pane.entries.get(self.pane.selected).is_some_and(|e| {
+                !e.is_dir
+                    && e.name != ".."
+                    && (self.pane.archive.is_some() || ArchiveKind::of(std::path::Path::new(&e.name)).is_none())
+            });
+            if !opens_preview {
+                self.set_status("Nothing to show fullscreen");
+                return;
+            }
+            self.enter_selected();
+            self.preview_fullscreen = self.preview_path.is_some();
+        } else {
+            self.preview_fullscreen = !self.preview_fullscreen;
+            // With the list hidden, keys go to the preview.
+            self.preview_auto = false;
+        }
+    }
+
+    /// Widen (positive `delta`) or narrow the preview's share of the split (`>` / `<`).
+    fn resize_preview(&mut self, delta: i16) {
+        let (min, max) = (*PREVIEW_RATIO_RANGE.start(), *PREVIEW_RATIO_RANGE.end());
+        self.preview_ratio = self.preview_ratio.saturating_add_signed(delta).clamp(min, max);
+        self.set_status(format!("Preview width {}%", self.preview_ratio));
+    }
+
+    /// Whether the open preview can show the end of its file: raw text (or pretty JSON) only, as
+    /// rendered markdown and hex dumps are built from the start.
+    fn preview_can_tail(&self) -> bool {
+        let is_markdown = self.preview_path.as_ref().and_then(|p| p.extension()).and_then(|e| e.to_str()) == Some("md");
+        self.preview_content.is_some() && !self.hex_view && !(is_markdown && self.preview_mode == PreviewMode::Rendered)
+    }
+
+    /// Scroll the preview to its end (G). A preview cut off by the limit is re-read from the end of
+    /// the file first.
+    fn preview_jump_to_end(&mut self) {
+        if self.preview_truncated && !self.preview_tail && self.preview_can_tail() {
+            self.preview_tail = true;
+            self.reload_preview();
+        }
+        // Clamped to the real maximum when drawn.
+        self.preview_scroll = usize::MAX;
+    }
+
+    /// Scroll the preview to its start (gg), going back to the start of the file after G or F.
+    fn preview_jump_to_start(&mut self) {
+        self.preview_follow = None;
+        if self.preview_tail {
+            self.preview_tail = false;
+            self.reload_preview();
+        }
+        self.preview_scroll = 0;
+    }
+
+    /// Start or stop following the previewed file's end as it grows (F).
+    fn toggle_preview_follow(&mut self) {
+        if self.preview_follow.take().is_some() {
+            self.set_status("Stopped following");
+            return;
+        }
+        let Some(path) = self.preview_path.as_ref() else {
+            return;
+        };
+        if !self.preview_can_tail() {
+            self.set_error("Only text previews can be followed");
+            return;
+        }
+        let Ok(meta) = fs::metadata(path) else {
+            self.set_error("Only files on disk can be followed");
+            return;
+        };
+        self.preview_follow = Some((meta.len(), meta.modified().ok()));
+        self.preview_tail = true;
+        self.reload_preview();
+        self.preview_scroll = usize::MAX;
+    }
+
+    /// In follow mode, re-read the previewed file's tail if it changed since the last read.
+    /// Called every tick of the event loop.
+    fn poll_preview_follow(&mut self) {
+        let (Some(stamp), Some(path)) = (self.preview_follow, self.preview_path.as_ref()) else {
+            return;
+        };
+        let Ok(meta) = fs::metadata(path) else {
+            return;
+        };
+        let current = (meta.len(), meta.modified().ok());
+        if current != stamp {
+            self.preview_follow = Some(current);
+            self.reload_preview();
+            self.preview_scroll = usize::MAX;
+        }
+    }
+
+    /// Toggle between raw and rendered preview mode (only for markdown, JSON and CSV files).
+    fn toggle_preview_mode(&mut self) {
+        // Only toggle if we have a markdown, JSON or CSV file open
+        let is_markdown = self.preview_path
+            .as_ref()
+            .and_then(|p| p.extension())
+            .and_then(|e| e.to_str()) == Some("md");
+        let reformatted = self.preview_path.as_deref().is_some_and(|p| is_json_path(p) || csv_delimiter(p).is_some());
+        if !is_markdown && !reformatted {
+            return;
+        }
+        
+        // Toggle mode
+        self.preview_mode = match self.preview_mode {
+            PreviewMode::Raw => PreviewMode::Rendered,
+            PreviewMode::Rendered => PreviewMode::Raw,
+        };
+        
+        // Reload preview with new mode
+        self.reload_preview();
+    }
+
+    /// Current display options for raw previews.
+    fn preview_options(&self) -> PreviewOptions {
+        PreviewOptions {
+            line_numbers: self.show_line_numbers,
+            show_whitespace: self.show_whitespace,
+            hex_view: self.hex_view,
+            theme: find_theme(&self.theme_name),
+            limit: self.preview_limit,
+            pretty_json: self.preview_mode == PreviewMode::Rendered,
+            csv_table: self.preview_mode == PreviewMode::Rendered,
+            tail: self.preview_tail,
+            tab_width: self.tab_width,
+            max_line_length: self.max_line_length,
+        }
+    }
+
+    /// Switch to the next available highlighting theme (T) and re-render the preview.
+    fn cycle_theme(&mut self) {
+        let names: Vec<&String> = theme_set().themes.keys().collect();
+        let next = names
+            .iter()
+            .position(|n| **n == self.theme_name)
+            .map(|i| (i + 1) % names.len())
+            .unwrap_or(0);
+        // Reloading resets the scroll position, so carry it over to keep the reader's place.
+        let scroll = self.preview_scroll;
+        self.theme_name = names[next].clone();
+        self.reload_preview();
+        self.preview_scroll = scroll;
+        self.set_status(format!("Theme: {}", self.theme_name));
+    }
+
+    /// Double the preview limit, up to `MAX_PREVIEW_LIMIT`, and reload the open preview so a
+    /// truncated file shows more (+). The scroll position is kept.
+    fn raise_preview_limit(&mut self) {
+        if self.preview_limit >= MAX_PREVIEW_LIMIT {
+            self.set_status(format!("Preview limit is already at its maximum of {}", format_preview_limit(MAX_PREVIEW_LIMIT)));
+            return;
+        }
+        self.preview_limit = (self.preview_limit * 2).min(MAX_PREVIEW_LIMIT);
+        let scroll = self.preview_scroll;
+        self.reload_preview();
+        self.preview_scroll = scroll;
+        self.set_status(format!("Preview limit: {}", format_preview_limit(self.preview_limit)));
+    }
+
+    /// Toggle between the "(binary file)" summary and a hex dump. No-op if preview closed.
+    fn toggle_hex_view(&mut self) {
+        if self.preview_path.is_none() {
+            return;
+        }
+        self.hex_view = !self.hex_view;
+        self.reload_preview();
+    }
+
+    /// Toggle the line-number gutter and reload the preview so it takes effect. No-op if preview closed.
+    fn toggle_line_numbers(&mut self) {
+        if self.preview_path.is_none() {
+            return;
+        }
+        self.show_line_numbers = !self.show_line_numbers;
+        self.reload_preview();
+    }
+
+    /// Toggle marking whitespace and reload the preview so it takes effect. No-op if preview closed.
+    fn toggle_whitespace(&mut self) {
+        if self.preview_path.is_none() {
+            return;
+        }
+        self.show_whitespace = !self.show_whitespace;
+        self.reload_preview();
+    }
+
+    /// Ask for confirmation before deleting the batch targets (D, or Shift+Delete for
+    /// `DeleteMode::Permanent`). Never offers to delete "..". Without a trash on this platform,
+    /// the prompt says the entries will be deleted permanently.
+    fn request_delete(&mut self, mode: DeleteMode) {
+        let targets = self.targets();
+        if !targets.is_empty() {
+            let mode = if TRASH_SUPPORTED { mode } else { DeleteMode::Permanent };
+            self.mode = Mode::ConfirmDelete(targets, mode);
+        }
+    }
+
+    /// Delete the paths awaiting confirmation, report the outcome in the status line, and refresh.
+    /// Entries the trash refuses (e.g. on a filesystem without one) are offered for permanent
+    /// deletion in a new prompt rather than being removed behind the user's back.
+    fn confirm_delete(&mut self) {
+        let Mode::ConfirmDelete(paths, mode) = std::mem::replace(&mut self.mode, Mode::Browse) else {
+            return;
+        };
+        let mut deleted = Vec::new();
+        let mut failures = Vec::new();
+        for path in &paths {
+            let result = match mode {
+                DeleteMode::Trash => trash::delete(path).map_err(|e| e.to_string()),
+                DeleteMode::Permanent => remove_path(path).map_err(|e| e.to_string()),
+            };
+            match result {
+                Ok(()) => deleted.push(path.clone()),
+                Err(e) => failures.push((path.clone(), e)),
+            }
+            if self.preview_path.as_deref() == Some(path.as_path()) && !failures.iter().any(|(p, _)| p == path) {
+                self.close_preview();
+            }
+        }
+        let (done, verb) = match mode {
+            DeleteMode::Trash => ("Moved", "move to the trash"),
+            DeleteMode::Permanent => ("Deleted", "delete"),
+        };
+        let suffix = if mode == DeleteMode::Trash { " to the trash" } else { "" };
+        self.report(match (paths.as_slice(), failures.as_slice()) {
+            ([path], []) => Ok(format!("{} {}{}", done, display_name(path), suffix)),
+            (_, []) => Ok(format!("{} {} entries{}", done, deleted.len(), suffix)),
+            ([_], [(path, e)]) => Err(format!("Could not {} {}: {}", verb, display_name(path), e)),
+            (_, [(path, e), ..]) => Err(format!(
+                "{} {} of {} entries{}; could not {} {}: {}",
+                done,
+                deleted.len(),
+                paths.len(),
+                suffix,
+                verb,
+                display_name(path),
+                e
+            )),
+        });
+        if mode == DeleteMode::Trash && !deleted.is_empty() {
+            self.last_op = Some(UndoOp::Trash(deleted));
+        }
+        if mode == DeleteMode::Trash && !failures.is_empty() {
+            let refused = failures.into_iter().map(|(path, _)| path).collect();
+            self.mode = Mode::ConfirmDelete(refused, DeleteMode::Permanent);
+        }
+        self.refresh_entries();
+    }
+
+    /// Put the batch targets in the register to be pasted with `p` (yy copies, dd moves). Clears
+    /// the marks, like a completed batch operation.
+    fn fill_register(&mut self, mode: PasteMode) {
+        let paths = self.targets();
+        if paths.is_empty() {
+            return;
+        }
+        let what = match paths.as_slice() {
+            [path] => display_name(path),
+            _ => format!("{} entries", paths.len()),
+        };
+        let verb = if mode == PasteMode::Copy { "Yanked" } else { "Cut" };
+        self.set_success(format!("{} {} (p to paste)", verb, what));
+        self.register = Some(Register { paths, mode });
+        self.pane.marked.clear();
+    }
+
+    /// Paste the register into `cwd` (p), asking first if that would replace existing entries.
+    fn paste(&mut self) {
+        let Some(register) = self.register.as_ref() else {
+            self.set_status("Nothing to paste (yank with yy or cut with dd first)");
+            return;
+        };
+        // An entry pasted onto itself isn't a collision: copies get a new name, moves are no-ops.
+        let existing: Vec<PathBuf> = register
+            .paths
+            .iter()
+            .filter_map(|src| src.file_name().map(|name| (src, self.pane.cwd.join(name))))
+            .filter(|(src, dest)| dest != *src && fs::symlink_metadata(dest).is_ok())
+            .map(|(_, dest)| dest)
+            .collect();
+        if existing.is_empty() {
+            self.paste_register(false);
+        } else {
+            self.mode = Mode::ConfirmOverwrite(existing);
+        }
+    }
+
+    /// Copy or move the register's paths into `cwd`. Existing entries in the way are replaced if
+    /// `overwrite`, otherwise the pasted entry gets a free " (n)" name. Moves empty the register.
+    fn paste_register(&mut self, overwrite: bool) {
+        self.mode = Mode::Browse;
+        let Some(register) = self.register.take() else {
+            return;
+        };
+        let mut pasted = Vec::new();
+        let mut failures = Vec::new();
+        for src in &register.paths {
+            let Some(name) = src.file_name() else {
+                continue;
+            };
+            let mut dest = self.pane.cwd.join(name);
+            if dest == *src {
+                if register.mode == PasteMode::Move {
+                    continue;
+                }
+                dest = unused_path(&dest);
+            } else if self.pane.cwd.starts_with(src) {
+                failures.push((display_name(src), io::Error::other("cannot paste a directory into itself")));
+                continue;
+            }
+            let mut result = Ok(());
+            if fs::symlink_metadata(&dest).is_ok() {
+                if !overwrite {
+                    dest = unused_path(&dest);
+                } else if src.starts_with(&dest) {
+                    failures.push((display_name(src), io::Error::other("it is inside the entry it would replace")));
+                    continue;
+                } else {
+                    result = remove_path(&dest);
+                }
+            }
+            let result = result.and_then(|()| match register.mode {
+                PasteMode::Copy => copy_path(src, &dest),
+                PasteMode::Move => move_path(src, &dest),
+            });
+            // The previewed file may have just been moved away or overwritten.
+            if let Some(preview) = self.preview_path.as_deref()
+                && ((preview == src.as_path() && register.mode == PasteMode::Move) || preview == dest.as_path())
+            {
+                self.close_preview();
+            }
+            match result {
+                Ok(()) => pasted.push(display_name(&dest)),
+                Err(e) => failures.push((display_name(src), e)),
+            }
+        }
+        let verb = if register.mode == PasteMode::Copy { "Copied" } else { "Moved" };
+        self.report(match (pasted.as_slice(), failures.as_slice()) {
+            ([], []) => Ok("Nothing to paste: already here".to_string()),
+            ([name], []) => Ok(format!("{} {}", verb, name)),
+            (_, []) => Ok(format!("{} {} entries", verb, pasted.len())),
+            ([], [(name, e)]) => Err(format!("Could not paste {}: {}", name, e)),
+            (_, [(name, e), ..]) => Err(format!(
+                "{} {} of {} entries; could not paste {}: {}",
+                verb,
+                pasted.len(),
+                pasted.len() + failures.len(),
+                name,
+                e
+            )),
+        });
+        // A copy can be pasted again; the sources of a move are gone (unless it failed).
+        if register.mode == PasteMode::Copy {
+            self.register = Some(register);
+        }
+        self.refresh_entries();
+        if let Some(first) = pasted.first() {
+            self.select_when_loaded(first.clone(), false);
+        }
+    }
+
+    /// What batch operations act on: the marked entries in list order, or else the selected
+    /// entry. ".." is never included.
+    fn targets(&self) -> Vec<PathBuf> {
+        if self.pane.marked.is_empty() {
+            self.pane.entries
+                .get(self.pane.selected)
+                .filter(|e| e.name != "..")
+                .map(|e| self.pane.cwd.join(&e.name))
+                .into_iter()
+                .collect()
+        } else {
+            self.pane.entries
+                .iter()
+                .filter(|e| self.pane.marked.contains(&e.name))
+                .map(|e| self.pane.cwd.join(&e.name))
+                .collect()
+        }
+    }
+
+    /// Toggle the mark on the selected entry and move to the next one (Space).
+    fn toggle_mark(&mut self) {
+        let Some(entry) = self.pane.entries.get(self.pane.selected) else {
+            return;
+        };
+        if entry.name != ".." && !self.pane.marked.remove(&entry.name) {
+            self.pane.marked.insert(entry.name.clone());
+        }
+        if self.pane.selected + 1 < self.pane.entries.len() {
+            self.pane.selected += 1;
+        }
+    }
+
+    /// Mark every entry, or clear all marks if everything is already marked (V).
+    fn toggle_mark_all(&mut self) {
+        let names: Vec<&String> = self.pane.entries.iter().map(|e| &e.name).filter(|n| *n != "..").collect();
+        if !names.is_empty() && names.iter().all(|n| self.pane.marked.contains(*n)) {
+            self.pane.marked.clear();
+        } else {
+            self.pane.marked = names.into_iter().cloned().collect();
+        }
+    }
+
+    /// React to a mouse event: clicks select list rows, the wheel moves the selection over the list
+    /// and scrolls over the preview.
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        let over = |r: Rect| r.contains(ratatui::layout::Position::new(mouse.column, mouse.row));
+        let over_list = over(self.list_area);
+        let over_other = self.other_list_area.filter(|&area| over(area));
+        let over_preview = self.preview_area.is_some_and(over);
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) if over_list || over_other.is_some() => {
+                // A click in the other pane focuses it first.
+                let area = match over_other {
+                    Some(area) => {
+                        self.switch_pane();
+                        area
+                    }
+                    None => self.list_area,
+                };
+                // Skip the top border; rows below map onto entries from the scroll offset on.
+                let Some(row) = mouse.row.checked_sub(area.y + 1) else {
+                    return;
+                };
+                let index = self.pane.list_offset + row as usize;
+                if index < self.pane.entries.len() {
+                    self.pane.selected = index;
+                }
+            }
+            MouseEventKind::ScrollDown if over_preview => self.preview_scroll_down(1),
+            MouseEventKind::ScrollUp if over_preview => self.preview_scroll_up(1),
+            MouseEventKind::ScrollDown if over_list => self.selection_down(),
+            MouseEventKind::ScrollUp if over_list => self.selection_up(),
+            _ => {}
+        }
+    }
+
+    /// Remember `cwd` under `letter` and save the bookmarks file (m<letter>).
+    fn set_bookmark(&mut self, letter: char) {
+        self.bookmarks.insert(letter, self.pane.cwd.clone());
+        self.report(match save_bookmarks(&self.bookmarks) {
+            Ok(()) => Ok(format!("Bookmarked {} as '{}'", self.pane.cwd.display(), letter)),
+            Err(e) => Err(format!("Bookmarked as '{}' (not saved: {})", letter, e)),
+        });
+    }
+
+    /// Go to the directory bookmarked under `letter` ('<letter>). Stays put if it's unset or gone.
+    fn jump_to_bookmark(&mut self, letter: char) {
+        let Some(dir) = self.bookmarks.get(&letter).cloned() else {
+            self.set_error(format!("No bookmark '{}'", letter));
+            return;
+        };
+        if !dir.is_dir() {
+            self.set_error(format!("Bookmark '{}' no longer exists: {}", letter, dir.display()));
+            return;
+        }
+        self.change_dir(dir);
+    }
+
+    /// Copy the full path of the selected entry to the clipboard (c in the list). With marked
+    /// entries, copies all their paths, one per line.
+    fn copy_selected_path(&mut self) {
+        let paths = if self.pane.marked.is_empty() {
+            self.selected_path().into_iter().collect()
+        } else {
+            self.targets()
+        };
+        if paths.is_empty() {
+            return;
+        }
+        let text = paths
+            .iter()
+            .map(|p| p.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let what = match paths.as_slice() {
+            [path] => path.to_string_lossy().into_owned(),
+            _ => format!("{} paths", paths.len()),
+        };
+        self.report(match copy_to_clipboard(&text) {
+            Ok(how) => Ok(how.describe(&what)),
+            Err(e) => Err(format!("Could not copy to clipboard: {}", e)),
+        });
+    }
+
+    /// Copy only the file name of the selected entry to the clipboard (C), for commands run
+    /// relative to `cwd`. With marked entries, copies all their names, one per line.
+    fn copy_selected_name(&mut self) {
+        let names: Vec<&str> = if self.pane.marked.is_empty() {
+            match self.pane.entries.get(self.pane.selected) {
+                Some(entry) if entry.name == ".." => {
+                    self.set_error("\"..\" has no name to copy");
+                    return;
+                }
+                Some(entry) => vec![entry.file_name()],
+                None => return,
+            }
+        } else {
+            self.pane.entries
+                .iter()
+                .filter(|e| self.pane.marked.contains(&e.name))
+                .map(|e| e.file_name())
+                .collect()
+        };
+        let what = match names.as_slice() {
+            [name] => name.to_string(),
+            _ => format!("{} names", names.len()),
+        };
+        self.report(match copy_to_clipboard(&names.join("\n")) {
+            Ok(how) => Ok(how.describe(&what)),
+            Err(e) => Err(format!("Could not copy to clipboard: {}", e)),
+        });
+    }
+
+    /// The directory on disk being browsed: `cwd`, or inside an archive the directory holding it.
+    fn disk_dir(&self) -> &std::path::Path {
+        match self.pane.archive.as_ref() {
+            Some(archive) => archive.parent().unwrap_or(archive),
+            None => self.pane.cwd.as_path(),
+        }
+    }
+
+    /// Write the current directory to the file named by `$DIRNAV_CWD_FILE` (P), where a shell
+    /// function can pick it up and `cd` there while dirnav keeps running. Without that variable
+    /// the path goes to the clipboard instead. Inside an archive, the directory holding it is used.
+    fn sync_cwd(&mut self) {
+        let text = self.disk_dir().display().to_string();
+        self.report(match std::env::var_os("DIRNAV_CWD_FILE").filter(|file| !file.is_empty()) {
+            Some(file) => match write_atomically(std::path::Path::new(&file), &format!("{}\n", text)) {
+                Ok(()) => Ok(format!("Wrote {} to {}", text, file.to_string_lossy())),
+                Err(e) => Err(format!("Could not write {}: {}", file.to_string_lossy(), describe_io_error(&e))),
+            },
+            None => match copy_to_clipboard(&text) {
+                Ok(how) => Ok(format!("{} (set DIRNAV_CWD_FILE to write it to a file instead)", how.describe(&text))),
+                Err(e) => Err(format!("Could not copy to clipboard: {}", e)),
+            },
+        });
+    }
+
+    /// Copy the text of the open preview to the clipboard, without styling (c in the preview).
+    /// Only what the preview holds is copied, so a truncated preview copies just its first part.
+    /// Line numbers and whitespace marks are left out.
+    fn copy_preview_content(&mut self) {
+        let Some(content) = self.preview_content.as_ref() else {
+            return;
+        };
+        let text = self.preview_plain_text.clone().unwrap_or_else(|| lines_to_plain_text(content));
+        let what = format!("{} lines", content.len());
+        self.report(match copy_to_clipboard(&text) {
+            Ok(how) if self.preview_truncated => Ok(format!(
+                "{} (only the {} {} of the file)",
+                how.describe(&what),
+                if self.preview_tail { "last" } else { "first" },
+                format_preview_limit(self.preview_limit)
+            )),
+            Ok(how) => Ok(how.describe(&what)),
+            Err(e) => Err(format!("Could not copy to clipboard: {}", e)),
+        });
+    }
+
+    /// Open a text prompt of the given kind with empty input.
+    fn start_input(&mut self, kind: InputKind) {
+        self.input.clear();
+        self.mode = Mode::Input(kind);
+    }
+
+    /// Close the prompt without doing anything (Esc).
+    fn cancel_input(&mut self) {
+        self.input.clear();
+        self.mode = Mode::Browse;
+    }
+
+    /// Close the prompt and act on what was typed (Enter).
+    fn submit_input(&mut self) {
+        let Mode::Input(kind) = std::mem::replace(&mut self.mode, Mode::Browse) else {
+            return;
+        };
+        let text = std::mem::take(&mut self.input);
+        match kind {
+            InputKind::GotoPath => self.goto_path(&text),
+            InputKind::NewFile => self.create_entry(&text, false),
+            InputKind::NewDir => self.create_entry(&text, true),
+            InputKind::Rename => self.rename_selected(&text),
+            InputKind::BatchRename => self.plan_batch_rename(&text),
+            InputKind::PreviewSearch => self.search_preview(text),
+            InputKind::Filter => self.set_filter(text),
+            InputKind::Chmod => self.chmod_selected(&text),
+            InputKind::GotoLine => self.goto_preview_line(&text),
+            InputKind::ShellCommand => {
+                if !text.trim().is_empty() {
+                    self.pending_command = Some(text);
+                }
+            }
+        }
+    }
+
+    /// Open the chmod prompt (M) with the selected entry's current mode in octal, ready to edit.
+    fn start_chmod(&mut self) {
+        let Some(entry) = self.pane.entries.get(self.pane.selected).filter(|e| e.name != "..") else {
+            return;
+        };
+        let Some(mode) = entry.mode else {
+            self.set_error("Permissions can only be changed on Unix");
+            return;
+        };
+        self.start_input(InputKind::Chmod);
+        self.input = format!("{:o}", mode & 0o7777);
+    }
+
+    /// Apply the mode change `text` (see `parse_mode_change`) to the selected entry and report the
+    /// new mode, or why it couldn't be set.
+    fn chmod_selected(&mut self, text: &str) {
+        let Some(entry) = self.pane.entries.get(self.pane.selected).filter(|e| e.name != "..") else {
+            return;
+        };
+        let (Some(mode), name) = (entry.mode, entry.name.clone()) else {
+            return;
+        };
+        let Some(new_mode) = parse_mode_change(text, mode, entry.is_dir) else {
+            self.set_error(format!("Not a mode: {} (try 644, +x or u=rw,go=r)", text.trim()));
+            return;
+        };
+        self.report(match set_permission_mode(&self.pane.cwd.join(&name), new_mode) {
+            Ok(()) => Ok(format!(
+                "{} is now {:04o} ({})",
+                name,
+                new_mode & 0o7777,
+                &symbolic_permissions(new_mode)[1..]
+            )),
+            Err(e) => Err(format!("Could not change the mode of {}: {}", name, e)),
+        });
+        self.refresh_entries_keep_selection();
+    }
+
+    /// Open the fuzzy finder over the files below `cwd` (Ctrl-p).
+    fn open_finder(&mut self) {
+        if self.pane.archive.is_some() {
+            self.set_error("The finder doesn't search inside archives");
+            return;
+        }
+        self.finder = Some(FuzzyFinder::new(self.pane.cwd.clone(), self.show_hidden, self.respect_gitignore));
+    }
+
+    /// Close the finder and go to the highlighted file: its directory becomes `cwd` with the file
+    /// selected, and, if `open`, shown in the preview.
+    fn accept_finder(&mut self, open: bool) {
+        let Some(finder) = self.finder.take() else {
+            return;
+        };
+        let Some(path) = finder.selected_path().map(|rel| finder.root.join(rel)) else {
+            return;
+        };
+        if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+            let name = name.to_string_lossy().into_owned();
+            if parent != self.pane.cwd {
+                self.change_dir(parent.to_path_buf());
+            }
+            self.select_when_loaded(name, open);
+        }
+    }
+
+    /// Only list entries matching `pattern` from now on; an empty pattern clears the filter.
+    fn set_filter(&mut self, pattern: String) {
+        self.filter = Some(pattern).filter(|p| !p.is_empty());
+        self.refresh_entries_keep_selection();
+    }
+
+    /// Find `query` in the open preview, highlight the matches and scroll to the first one.
+    fn search_preview(&mut self, query: String) {
+        let Some(content) = self.preview_content.as_ref() else {
+            return;
+        };
+        if query.is_empty() {
+            self.preview_search = None;
+            return;
+        }
+        let case_sensitive = self.preview_search_case_sensitive;
+        let matches = find_matches(content, &query, case_sensitive);
+        if matches.is_empty() {
+            self.set_error(format!("No matches for \"{}\"", query));
+            self.preview_search = None;
+            return;
+        }
+        self.preview_search = Some(PreviewSearch { query, case_sensitive, matches, current: 0 });
+        self.show_current_match();
+    }
+
+    /// Move to the next (or with `forward` false, the previous) preview match, wrapping around.
+    fn cycle_preview_match(&mut self, forward: bool) {
+        let Some(search) = self.preview_search.as_mut() else {
+            return;
+        };
+        let count = search.matches.len();
+        if count == 0 {
+            return;
+        }
+        search.current = if forward { (search.current + 1) % count } else { (search.current + count - 1) % count };
+        self.show_current_match();
+    }
+
+    /// Scroll the preview so the current match is near the top, and report its position.
+    fn show_current_match(&mut self) {
+        let (Some(search), Some(content)) = (self.preview_search.as_ref(), self.preview_content.as_ref()) else {
+            return;
+        };
+        let Some(&(line, _)) = search.matches.get(search.current) else {
+            return;
+        };
+        // With wrapping, lines above the match may span several rows each.
+        let row = match self.preview_wrapped_rows {
+            Some((width, _)) if self.preview_wrap => Paragraph::new(Text::from(content[..line].to_vec()))
+                .wrap(Wrap { trim: false })
+                .line_count(width),
+            _ => line,
+        };
+        // Keep a couple of lines of context above the match.
+        self.preview_scroll = row.saturating_sub(2).min(self.preview_scroll_max);
+        self.set_status(format!("Match {}/{}", search.current + 1, search.matches.len()));
+    }
+
+    /// Full path of the selected entry (the parent directory for ".."), if there is one.
+    fn selected_path(&self) -> Option<PathBuf> {
+        let entry = self.pane.entries.get(self.pane.selected)?;
+        Some(if entry.name == ".." {
+            self.pane.cwd.parent().map(|p| p.to_path_buf()).unwrap_or_else(|| self.pane.cwd.clone())
+        } else {
+            self.pane.cwd.join(&entry.name)
+        })
+    }
+
+    /// The path "quit and emit" prints: the previewed or selected file, or else the directory
+    /// we're in. Inside an archive that is the archive itself, as nothing in it exists on disk.
+    fn output_path(&self) -> PathBuf {
+        if let Some(archive) = &self.pane.archive {
+            return archive.clone();
+        }
+        if let Some(path) = &self.preview_path {
+            return path.clone();
+        }
+        match self.pane.entries.get(self.pane.selected) {
+            Some(entry) if !entry.is_dir && entry.name != ".." => self.pane.cwd.join(&entry.name),
+            _ => self.pane.cwd.clone(),
+        }
+    }
+
+    /// Hand the selected entry to the platform opener (o), without leaving the TUI.
+    fn open_selected_externally(&mut self) {
+        let Some(path) = self.selected_path() else {
+            return;
+        };
+        self.report(match open_with_default_app(&path) {
+            Ok(()) => Ok(format!("Opened {}", display_name(&path))),
+            Err(e) => Err(format!("Could not open {}: {}", display_name(&path), e)),
+        });
+    }
+
+    /// Scroll the command output pager by `delta` lines, within the rendered range.
+    fn scroll_command_output(&mut self, delta: isize) {
+        if let Some(output) = self.command_output.as_mut() {
+            output.scroll = output.scroll.saturating_add_signed(delta).min(output.scroll_max);
+        }
+    }
+
+    /// Scroll the help overlay by `delta` lines, within what the last render allowed.
+    fn scroll_help(&mut self, delta: isize) {
+        if let Some(scroll) = self.help_scroll.as_mut() {
+            *scroll = scroll.saturating_add_signed(delta).min(self.help_scroll_max);
+        }
+    }
+
+    /// Open the rename prompt (r) with the selected entry's name, ready to edit.
+    fn start_rename(&mut self) {
+        let Some(entry) = self.pane.entries.get(self.pane.selected).filter(|e| e.name != "..") else {
+            return;
+        };
+        let name = entry.file_name().to_string();
+        self.start_input(InputKind::Rename);
+        self.input = name;
+    }
+
+    /// Where renaming the selected entry to `name` would move it, and whether that replaces
+    /// something else. None when `name` is empty, unchanged or not a plain name.
+    fn rename_target(&self, name: &str) -> Option<(PathBuf, PathBuf, bool)> {
+        let entry = self.pane.entries.get(self.pane.selected).filter(|e| e.name != "..")?;
+        let name = name.trim();
+        if name.is_empty() || name == "." || name == ".." || name == entry.file_name() {
+            return None;
+        }
+        let from = self.pane.cwd.join(&entry.name);
+        let to = from.with_file_name(name);
+        // On a case-insensitive filesystem, "a" -> "A" finds the entry itself, which is fine.
+        let replaces = fs::symlink_metadata(&to).is_ok()
+            && fs::canonicalize(&to).ok() != fs::canonicalize(&from).ok();
+        Some((from, to, replaces))
+    }
+
+    /// Rename the selected entry to `name` (same directory), asking first if that would replace
+    /// an existing entry.
+    fn rename_selected(&mut self, name: &str) {
+        if name.chars().any(std::path::is_separator) {
+            self.set_error(format!("Name must not contain a path separator: {}", name.trim()));
+            return;
+        }
+        match self.rename_target(name) {
+            Some((from, to, true)) => self.mode = Mode::ConfirmRename(from, to),
+            Some((from, to, false)) => self.rename_path(from, to),
+            None => {}
+        }
+    }
+
+    /// Rename `from` to `to`, remember it for undo, and select the entry under its new name.
+    fn rename_path(&mut self, from: PathBuf, to: PathBuf) {
+        if let Err(e) = fs::rename(&from, &to) {
+            self.set_error(format!("Could not rename {}: {}", display_name(&from), e));
+            return;
+        }
+        if self.preview_path.as_deref() == Some(from.as_path()) {
+            self.preview_path = Some(to.clone());
+        }
+        self.set_success(format!("Renamed {} to {}", display_name(&from), display_name(&to)));
+        let name = to.strip_prefix(&self.pane.cwd).map(|rel| rel.to_string_lossy().into_owned()).ok();
+        self.last_op = Some(UndoOp::Rename(from, to));
+        self.refresh_entries();
+        if let Some(name) = name {
+            self.select_when_loaded(name, false);
+        }
+    }
+
+    /// Work out what the batch rename pattern `template` makes of the targets, numbering them
+    /// from 1 in list order, and show the result for confirmation. Nothing is renamed if a new
+    /// name is taken by an entry that isn't itself being renamed, or two entries would share one.
+    fn plan_batch_rename(&mut self, template: &str) {
+        let template = template.trim();
+        let targets = self.targets();
+        if template.is_empty() || targets.is_empty() {
+            return;
+        }
+        if template.chars().any(std::path::is_separator) {
+            self.set_error(format!("Name must not contain a path separator: {}", template));
+            return;
+        }
+        if targets.len() > 1 && !template.contains("{n}") {
+            self.set_error("The pattern needs {n} to give each entry its own name");
+            return;
+        }
+        let names = batch_rename_names(template, targets.len());
+        if let Some(name) = names.iter().find(|name| *name == "." || *name == "..") {
+            self.set_error(format!("Not a usable name: {}", name));
+            return;
+        }
+        let mut pairs = Vec::new();
+        let mut seen = HashSet::new();
+        for (from, name) in targets.iter().zip(names) {
+            let to = from.with_file_name(&name);
+            if !seen.insert(to.clone()) {
+                self.set_error(format!("{} would be given to more than one entry", name));
+                return;
+            }
+            if to == *from {
+                continue;
+            }
+            // A name held by another target is freed by the rename; anything else is in the way.
+            // On a case-insensitive filesystem, "a" -> "A" finds the entry itself, which is fine.
+            let in_the_way = fs::symlink_metadata(&to).is_ok()
+                && !targets.contains(&to)
+                && fs::canonicalize(&to).ok() != fs::canonicalize(from).ok();
+            if in_the_way {
+                self.set_error(format!("{} already exists; nothing was renamed", name));
+                return;
+            }
+            pairs.push((from.clone(), to));
+        }
+        if pairs.is_empty() {
+            self.set_status("The names are unchanged");
+            return;
+        }
+        self.mode = Mode::ConfirmBatchRename(pairs);
+    }
+
+    /// Carry out the confirmed batch rename, remember it for undo and clear the marks.
+    fn confirm_batch_rename(&mut self) {
+        let Mode::ConfirmBatchRename(pairs) = std::mem::replace(&mut self.mode, Mode::Browse) else {
+            return;
+        };
+        if let Err(e) = rename_batch(&pairs) {
+            self.set_error(format!("{}; nothing was renamed", e));
+            self.refresh_entries_keep_selection();
+            return;
+        }
+        if let Some((_, to)) = pairs.iter().find(|(from, _)| self.preview_path.as_ref() == Some(from)) {
+            self.preview_path = Some(to.clone());
+        }
+        self.set_success(match pairs.as_slice() {
+            [(from, to)] => format!("Renamed {} to {}", display_name(from), display_name(to)),
+            _ => format!("Renamed {} entries", pairs.len()),
+        });
+        self.pane.marked.clear();
+        self.last_op = Some(UndoOp::RenameBatch(pairs));
+        self.refresh_entries_keep_selection();
+    }
+
+    /// Reverse the last rename, create or move to the trash (u), where that is still possible:
+    /// nothing has taken the old name, the created entry is still empty, the trash still holds
+    /// what was deleted. Undoing is one level deep; the operation is forgotten once undone.
+    fn undo_last_op(&mut self) {
+        let Some(op) = self.last_op.take() else {
+            self.set_status("Nothing to undo");
+            return;
+        };
+        let result = match &op {
+            UndoOp::Rename(from, _) if fs::symlink_metadata(from).is_ok() => {
+                Err(format!("{} exists again", display_name(from)))
+            }
+            UndoOp::Rename(from, to) => fs::rename(to, from)
+                .map(|()| format!("Renamed {} back to {}", display_name(to), display_name(from)))
+                .map_err(|e| e.to_string()),
+            UndoOp::RenameBatch(pairs) => {
+                let news: Vec<&PathBuf> = pairs.iter().map(|(_, to)| to).collect();
+                match pairs.iter().find(|(from, _)| fs::symlink_metadata(from).is_ok() && !news.contains(&from)) {
+                    Some((from, _)) => Err(format!("{} exists again", display_name(from))),
+                    None => {
+                        let back: Vec<(PathBuf, PathBuf)> =
+                            pairs.iter().map(|(from, to)| (to.clone(), from.clone())).collect();
+                        rename_batch(&back).map(|()| format!("Renamed {} entries back", pairs.len()))
+                    }
+                }
+            }
+            UndoOp::Create(path) => match fs::symlink_metadata(path) {
+                Ok(meta) if meta.is_dir() => fs::remove_dir(path).map_err(|e| match e.kind() {
+                    io::ErrorKind::DirectoryNotEmpty => "it isn't empty any more".to_string(),
+                    _ => e.to_string(),
+                }),
+                Ok(meta) if meta.len() > 0 => Err("it has content now".to_string()),
+                Ok(_) => fs::remove_file(path).map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+            .map(|()| format!("Removed {}", display_name(path))),
+            UndoOp::Trash(paths) => restore_from_trash(paths).map(|()| match paths.as_slice() {
+                [path] => format!("Restored {} from the trash", display_name(path)),
+                _ => format!("Restored {} entries from the trash", paths.len()),
+            }),
+        };
+        self.report(result.map_err(|e| {
+            let what = match &op {
+                UndoOp::Rename(..) | UndoOp::RenameBatch(..) => "the rename",
+                UndoOp::Create(..) => "creating it",
+                UndoOp::Trash(..) => "the delete",
+            };
+            format!("Could not undo {}: {}", what, e)
+        }));
+        if let UndoOp::Rename(from, to) = &op
+            && self.preview_path.as_deref() == Some(to.as_path())
+        {
+            self.preview_path = Some(from.clone());
+        }
+        if let UndoOp::RenameBatch(pairs) = &op
+            && let Some((from, _)) = pairs.iter().find(|(_, to)| self.preview_path.as_ref() == Some(to))
+        {
+            self.preview_path = Some(from.clone());
+        }
+        self.refresh_entries_keep_selection();
+    }
+
+    /// Create a file or directory called `name` in `cwd`, then select it. Names with path
+    /// separators are rejected so creation can't escape the current directory.
+    fn create_entry(&mut self, name: &str, is_dir: bool) {
+        let name = name.trim();
+        if name.is_empty() || name == "." || name == ".." {
+            return;
+        }
+        if name.chars().any(std::path::is_separator) {
+            self.set_error(format!("Name must not contain a path separator: {}", name));
+            return;
+        }
+        let path = self.pane.cwd.join(name);
+        let result = if is_dir {
+            fs::create_dir(&path)
+        } else {
+            // create_new so an existing file is reported rather than truncated.
+            fs::OpenOptions::new().write(true).create_new(true).open(&path).map(|_| ())
+        };
+        match result {
+            Ok(()) => {
+                self.last_op = Some(UndoOp::Create(path));
+                self.refresh_entries();
+                if self.select_when_loaded(name.to_string(), false) == Some(false) {
+                    self.set_success(format!("Created {} (hidden by current filters)", name));
+                }
+            }
+            Err(e) => self.set_error(format!("Could not create {}: {}", name, e)),
+        }
+    }
+
+    /// Jump to `text` (absolute, relative to `cwd`, or starting with `~`). Directories become the new
+    /// `cwd`; files open in the preview with their parent as `cwd`. Anything else is reported as an error.
+    fn goto_path(&mut self, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let target = self.pane.cwd.join(expand_tilde(text));
+        // Canonicalize so "../x" or symlinked paths don't pile up in `cwd`.
+        let Ok(target) = fs::canonicalize(&target) else {
+            self.set_error(format!("No such file or directory: {}", text));
+            return;
+        };
+        if target.is_dir() {
+            self.change_dir(target);
+        } else if let (Some(parent), Some(name)) = (target.parent(), target.file_name()) {
+            let name = name.to_string_lossy().into_owned();
+            self.change_dir(parent.to_path_buf());
+            self.select_when_loaded(name, true);
+        }
+    }
+
+    /// Complete the last path component of the goto-path input (Tab). Completes fully when there's a
+    /// single candidate (adding "/" for directories) and to the longest common prefix otherwise.
+    fn complete_path_input(&mut self) {
+        let (dir_part, prefix) = match self.input.rfind('/') {
+            Some(i) => (&self.input[..=i], &self.input[i + 1..]),
+            None => ("", self.input.as_str()),
+        };
+        let dir = self.pane.cwd.join(expand_tilde(dir_part));
+        let Ok(read) = fs::read_dir(&dir) else {
+            return;
+        };
+        let mut candidates: Vec<(String, bool)> = read
+            .flatten()
+            .map(|e| {
+                let name = e.file_name().to_string_lossy().into_owned();
+                (name, e.path().is_dir())
+            })
+            .filter(|(name, _)| name.starts_with(prefix) && (prefix.starts_with('.') || !name.starts_with('.')))
+            .collect();
+        candidates.sort();
+        let completion = match candidates.as_slice() {
+            [] => return,
+            [(name, is_dir)] => {
+                let mut full = name.clone();
+                if *is_dir {
+                    full.push('/');
+                }
+                full
+            }
+            [(first, _), rest @ ..] => {
+                let mut common = first.clone();
+                for (name, _) in rest {
+                    let shared = common
+                        .char_indices()
+                        .zip(name.chars())
+                        .take_while(|((_, a), b)| a == b)
+                        .last()
+                        .map(|((i, a), _)| i + a.len_utf8())
+                        .unwrap_or(0);
+                    common.truncate(shared);
+                }
+                common
+            }
+        };
+        if let Some(rest) = completion.strip_prefix(prefix) {
+            self.input.push_str(rest);
+        }
+    }
+
+    /// Scroll preview down (j). No-op if preview closed.
+    fn preview_scroll_down(&mut self, rows: usize) {
+        if self.preview_content.is_some() {
+            self.preview_scroll = (self.preview_scroll.saturating_add(rows)).min(self.preview_scroll_max);
+        }
+    }
+
+    /// Scroll preview up (k). No-op if preview closed.
+    fn preview_scroll_up(&mut self, rows: usize) {
+        if self.preview_content.is_some() {
+            self.preview_scroll = self.preview_scroll.saturating_sub(rows);
+        }
+    }
+
+    /// Scroll the preview so line `line` (1-based) is at the top (`10G`), or in the middle when
+    /// `centered`, as far as it will go.
+    fn preview_goto_line(&mut self, line: usize, centered: bool) {
+        let Some(content) = self.preview_content.as_ref() else {
+            return;
+        };
+        let before = &content[..line.saturating_sub(1).min(content.len())];
+        // Wrapped lines take several rows, so count the rows above the target line.
+        let rows = match self.preview_area {
+            Some(rect) if self.preview_wrap => Paragraph::new(Text::from(before.to_vec()))
+                .wrap(Wrap { trim: false })
+                .line_count(rect.width.saturating_sub(2)),
+            _ => before.len(),
+        };
+        let rows = if centered { rows.saturating_sub(self.preview_viewport_height / 2) } else { rows };
+        self.preview_follow = None;
+        self.preview_scroll = rows.min(self.preview_scroll_max);
+    }
+
+    /// Centre the preview on the line numbered `text` (`:` with the preview open) and highlight
+    /// it until the next scroll. Numbers past the end go to the last line.
+    fn goto_preview_line(&mut self, text: &str) {
+        let Some(len) = self.preview_content.as_ref().map(Vec::len).filter(|&len| len > 0) else {
+            return;
+        };
+        let Ok(line) = text.trim().parse::<usize>() else {
+            self.set_error(format!("Not a line number: {}", text.trim()));
+            return;
+        };
+        let line = line.clamp(1, len);
+        self.preview_goto_line(line, true);
+        self.preview_line_highlight = Some((line - 1, self.preview_scroll));
+    }
+
+    /// Select entry `n` (1-based), or the last one if there are fewer (`10G`).
+    fn select_nth(&mut self, n: usize) {
+        self.pane.selected = n.saturating_sub(1).min(self.pane.entries.len().saturating_sub(1));
+    }
+
+    /// Toggle between wrapping long preview lines and clipping them with horizontal scroll (w).
+    fn toggle_preview_wrap(&mut self) {
+        if self.preview_content.is_none() {
+            return;
+        }
+        self.preview_wrap = !self.preview_wrap;
+        self.preview_hscroll = 0;
+        self.preview_scroll = self.preview_scroll.min(self.preview_scroll_max);
+    }
+
+    /// Scroll the preview right (l / →) in no-wrap mode.
+    fn preview_scroll_right(&mut self) {
+        if self.preview_content.is_some() && !self.preview_wrap {
+            self.preview_hscroll = (self.preview_hscroll + 4).min(self.preview_hscroll_max);
+        }
+    }
+
+    /// Scroll the preview left (h / ←) in no-wrap mode.
+    fn preview_scroll_left(&mut self) {
+        if self.preview_content.is_some() && !self.preview_wrap {
+            self.preview_hscroll = self.preview_hscroll.saturating_sub(4);
+        }
+    }
+
+    /// Carry out a bound action, resolving it against the current context (preview open or
+    /// not, wrapping on or off). `pending` is the first key of a two-key command, if any, and
+    /// `count` the number typed before it: movement repeats that many times, and gg/G go to
+    /// that entry or line. Returns false when the app should quit.
+    fn perform(&mut self, action: Action, pending: Option<char>, count: Option<usize>) -> bool {
+        // A preview opened by `auto_preview` is only looked at; keys act on the list until it's entered.
+        let previewing = self.preview_path.is_some() && !self.preview_auto;
+        let times = count.unwrap_or(1);
+        match action {
+            Action::Close if self.preview_fullscreen => self.preview_fullscreen = false,
+            Action::Close if previewing => self.close_preview(),
+            Action::Close if self.filter.is_some() => self.set_filter(String::new()),
+            Action::Quit | Action::Close if self.confirm_quit => self.mode = Mode::ConfirmQuit,
+            Action::Quit | Action::Close => return false,
+            Action::QuitAndEmit => {
+                self.selected_output = Some(self.output_path());
+                return false;
+            }
+            Action::MoveUp if previewing => self.preview_scroll_up(times),
+            Action::MoveDown if previewing => self.preview_scroll_down(times),
+            // A count moves like a page jump, stopping at the ends instead of wrapping.
+            Action::MoveUp | Action::SelectUp if count.is_some() => self.move_selection_by(-(times as isize)),
+            Action::MoveDown | Action::SelectDown if count.is_some() => self.move_selection_by(times as isize),
+            Action::MoveUp | Action::SelectUp => self.selection_up(),
+            Action::MoveDown | Action::SelectDown => self.selection_down(),
+            Action::Top if pending != Some('g') => {
+                self.pending_key = Some('g');
+                // Keep the count for the second g.
+                self.count = count;
+            }
+            Action::Top | Action::Bottom if count.is_some() && previewing => self.preview_goto_line(times, false),
+            Action::Top | Action::Bottom if count.is_some() => self.select_nth(times),
+            Action::Top if previewing => self.preview_jump_to_start(),
+            Action::Top => self.select_first(),
+            Action::Bottom if !previewing => self.select_last(),
+            Action::NextDir | Action::PrevDir if !previewing => {
+                for _ in 0..times.min(self.pane.entries.len().max(1)) {
+                    self.select_next_dir(action == Action::NextDir);
+                }
+            }
+            Action::Bottom => self.preview_jump_to_end(),
+            Action::FollowPreview => self.toggle_preview_follow(),
+            Action::PageDown => self.page(times as f32),
+            Action::PageUp => self.page(-(times as f32)),
+            Action::HalfPageDown => self.page(0.5 * times as f32),
+            Action::HalfPageUp => self.page(-0.5 * times as f32),
+            Action::HistoryBack => self.history_back(),
+            Action::HistoryForward => self.history_forward(),
+            // With an unwrapped preview open, open/parent scroll it sideways instead of navigating.
+            Action::Open | Action::ScrollRight if previewing && !self.preview_wrap => self.preview_scroll_right(),
+            Action::Parent | Action::ScrollLeft if previewing && !self.preview_wrap => self.preview_scroll_left(),
+            Action::Open => self.enter_selected(),
+            Action::Parent => self.go_parent(),
+            Action::Search if previewing => self.start_input(InputKind::PreviewSearch),
+            Action::Search => self.start_search(),
+            Action::GotoPath if previewing => self.start_input(InputKind::GotoLine),
+            Action::GotoPath => self.start_input(InputKind::GotoPath),
+            Action::ToggleHidden => {
+                self.show_hidden = !self.show_hidden;
+                self.refresh_entries();
+            }
+            Action::ToggleWrapSelection => self.toggle_wrap_selection(),
+            Action::TogglePreviewWrap => self.toggle_preview_wrap(),
+            Action::TogglePreviewFullscreen => self.toggle_preview_fullscreen(),
+            Action::ShrinkPreview => self.resize_preview(-PREVIEW_RATIO_STEP),
+            Action::GrowPreview => self.resize_preview(PREVIEW_RATIO_STEP),
+            Action::SetBookmark => self.pending_key = Some('m'),
+            Action::JumpToBookmark => self.pending_key = Some('\''),
+            Action::Copy if previewing => self.copy_preview_content(),
+            Action::Copy => self.copy_selected_path(),
+            Action::CopyName => self.copy_selected_name(),
+            Action::SyncCwd => self.sync_cwd(),
+            Action::ToggleMark => self.toggle_mark(),
+            Action::ToggleMarkAll => self.toggle_mark_all(),
+            Action::ToggleExpand => self.toggle_expand(),
+            Action::ShellCommand => self.start_input(InputKind::ShellCommand),
+            Action::OpenShell => self.shell_requested = true,
+            Action::NewFile
+            | Action::NewDir
+            | Action::Delete
+            | Action::DeletePermanently
+            | Action::Yank
+            | Action::Cut
+            | Action::Paste
+            | Action::Chmod
+            | Action::Rename
+            | Action::BatchRename
+                if self.pane.archive.is_some() =>
+            {
+                self.set_error("Archives are read-only");
+            }
+            Action::NewFile => self.start_input(InputKind::NewFile),
+            Action::NewDir => self.start_input(InputKind::NewDir),
+            Action::Delete => self.request_delete(DeleteMode::Trash),
+            Action::DeletePermanently => self.request_delete(DeleteMode::Permanent),
+            Action::Chmod => self.start_chmod(),
+            Action::Rename => self.start_rename(),
+            Action::BatchRename => self.start_input(InputKind::BatchRename),
+            Action::Undo => self.undo_last_op(),
+            Action::Yank | Action::Cut => {
+                let (key, mode) = if action == Action::Yank { ('y', PasteMode::Copy) } else { ('d', PasteMode::Move) };
+                if pending == Some(key) && self.other_pane.is_some() {
+                    self.transfer_to_other_pane(mode);
+                } else if pending == Some(key) {
+                    self.fill_register(mode);
+                } else {
+                    self.pending_key = Some(key);
+                }
+            }
+            Action::Paste => self.paste(),
+            Action::Help => self.help_scroll = Some(0),
+            Action::FuzzyFind => self.open_finder(),
+            Action::OpenExternal => self.open_selected_externally(),
+            Action::RaisePreviewLimit => self.raise_preview_limit(),
+            Action::Filter if !previewing => {
+                self.start_input(InputKind::Filter);
+                self.input = self.filter.clone().unwrap_or_default();
+            }
+            Action::ToggleGitignore => self.toggle_gitignore(),
+            Action::CycleSort => self.cycle_sort_mode(),
+            Action::ReverseSort => self.toggle_sort_reverse(),
+            Action::ToggleSortCase => self.toggle_sort_case(),
+            Action::ToggleDirsFirst => self.toggle_dirs_first(),
+            Action::ToggleRecentFirst => self.toggle_recent_first(),
+            Action::ResetView => self.reset_view(),
+            Action::ToggleDirCounts => self.toggle_dir_counts(),
+            Action::ToggleClock => self.show_clock = !self.show_clock,
+            Action::TogglePeek => self.show_peek = !self.show_peek,
+            Action::DirSize => self.measure_selected_dir(),
+            Action::ToggleDualPane => self.toggle_dual_pane(),
+            Action::SwitchPane => self.switch_pane(),
+            Action::TogglePreviewMode => self.toggle_preview_mode(),
+            Action::CycleTheme => self.cycle_theme(),
+            Action::ToggleLineNumbers => self.toggle_line_numbers(),
+            Action::ToggleWhitespace => self.toggle_whitespace(),
+            Action::ToggleHexView => self.toggle_hex_view(),
+            Action::TogglePermissionFormat => self.octal_permissions = !self.octal_permissions,
+            // List-only actions do nothing while the preview is open.
+            Action::NextDir
+            | Action::PrevDir
+            | Action::Filter
+            | Action::ScrollLeft
+            | Action::ScrollRight => {}
+        }
+        true
+    }
+}
+
+/// Worker side of the fuzzy finder: send the files below `root`, relative to it, in batches.
+/// Hidden files and gitignored ones are skipped unless the listing shows them, and `.git` is never
+/// entered. Stops after `FINDER_MAX_FILES` files, or as soon as a send fails (the finder closed).
+fn walk_files(root: &std::path::Path, show_hidden: bool, respect_gitignore: bool, tx: &Sender<Vec<String>>) {
+    let walker = ignore::WalkBuilder::new(root)
+        .hidden(!show_hidden)
+        .git_ignore(respect_gitignore)
+        .git_global(respect_gitignore)
+        .git_exclude(respect_gitignore)
+        .ignore(false)
+        .parents(respect_gitignore)
+        .filter_entry(|e| e.file_name() != ".git")
+        .build();
+    let mut batch = Vec::new();
+    let mut found = 0;
+    for entry in walker.flatten() {
+        if entry.file_type().is_none_or(|t| t.is_dir()) {
+            continue;
+        }
+        let Ok(rel) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        batch.push(rel.to_string_lossy().into_owned());
+        found += 1;
+        if found == FINDER_MAX_FILES {
+            break;
+        }
+        if batch.len() == 1000 && tx.send(std::mem::take(&mut batch)).is_err() {
+            return;
+        }
+    }
+    let _ = tx.send(batch);
+}
+
+/// Read directory entries for the given path. Returns dirs first (with ".." at top), then files,
+/// each group ordered according to `sort`. Fails if the directory itself can't be read; entries
+/// that vanish or can't be inspected mid-listing are skipped.
+fn read_dir_entries(path: &std::path::Path, sort: SortOptions) -> io::Result<Vec<DirEntry>> {
+    let entries = match split_archive_path(path) {
+        Some((archive, inner)) => archive_dir_entries(&archive, &inner)?,
+        None => fs::read_dir(path)?.flatten().map(|entry| dir_entry(&entry)).collect(),
+    };
+    Ok(arrange_entries(path, entries, sort))
+}
+
+/// Describe one entry of a directory listing (name only, at depth 0).
+fn dir_entry(entry: &fs::DirEntry) -> DirEntry {
+    let name = entry.file_name().to_string_lossy().into_owned();
+    let is_symlink = entry.file_type().is_ok_and(|ft| ft.is_symlink());
+    // DirEntry::metadata doesn't follow links; describe a link by its target where it resolves.
+    let meta = if is_symlink { fs::metadata(entry.path()).ok() } else { None }
+        .or_else(|| entry.metadata().ok());
+    let is_dir = meta.as_ref().is_some_and(|m| m.is_dir());
+    let size = meta.as_ref().filter(|_| !is_dir).map(|m| m.len()).unwrap_or(0);
+    let modified = meta.as_ref().and_then(|m| m.modified().ok());
+    let mode = meta.as_ref().and_then(permission_mode);
+    let readonly = meta.as_ref().is_some_and(|m| m.permissions().readonly());
+    let hidden_attribute = meta.as_ref().is_some_and(has_hidden_attribute);
+    let link_target = if is_symlink { fs::read_link(entry.path()).ok() } else { None };
+    DirEntry { name, depth: 0, is_dir, size, modified, mode, readonly, hidden_attribute, is_symlink, link_target }
+}
+
+/// Order the entries of the directory at `path` for display: "..", then directories, then files
+/// (or directories and files together, without `sort.dirs_first`).
+fn arrange_entries(path: &std::path::Path, entries: Vec<DirEntry>, sort: SortOptions) -> Vec<DirEntry> {
+    let (mut dirs, mut files): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.is_dir && sort.dirs_first);
+    sort_entries(&mut dirs, sort);
+    sort_entries(&mut files, sort);
+
+    let mut out = Vec::with_capacity(dirs.len() + files.len() + 1);
+    // Only add ".." if we're not at root (so we can go up).
+    if path.parent().is_some() {
+        out.push(parent_entry());
+    }
+    out.extend(dirs);
+    out.extend(files);
+    out
+}
+
+/// Worker side of `App::refresh_entries`: list `dir` in display order, sending what has been read
+/// so far now and then, and then the whole listing along with the gitignore filter and git status
+/// (computed here too, since walking a big repo is as slow as listing it). Sorting happens here
+/// as well so the UI thread never stalls on a huge directory. Stops as soon as a send fails,
+/// i.e. once the load has been superseded.
+fn stream_dir_entries(dir: &std::path::Path, sort: SortOptions, respect_gitignore: bool, tx: &Sender<DirLoadMsg>) {
+    const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(100);
+    if let Some((archive, inner)) = split_archive_path(dir) {
+        let _ = tx.send(match archive_dir_entries(&archive, &inner) {
+            Ok(entries) => DirLoadMsg::Done(DirListing {
+                entries: arrange_entries(dir, entries, sort),
+                gitignore_visible: None,
+                git_status: HashMap::new(),
+            }),
+            Err(e) => DirLoadMsg::Failed(e),
+        });
+        return;
+    }
+    let read = match fs::read_dir(dir) {
+        Ok(read) => read,
+        Err(e) => {
+            let _ = tx.send(DirLoadMsg::Failed(e));
+            return;
+        }
+    };
+    let mut entries = Vec::new();
+    let (mut last_snapshot, mut snapshot_len) = (Instant::now(), 0);
+    for entry in read.flatten() {
+        entries.push(dir_entry(&entry));
+        // Each snapshot is sorted from scratch, so they're spaced out as the listing doubles to keep
+        // the total sorting within a small multiple of one sort of the full listing.
+        if last_snapshot.elapsed() >= SNAPSHOT_INTERVAL && entries.len() >= snapshot_len * 2 {
+            if tx.send(DirLoadMsg::Partial(arrange_entries(dir, entries.clone(), sort))).is_err() {
+                return;
+            }
+            (last_snapshot, snapshot_len) = (Instant::now(), entries.len());
+        }
+    }
+    let gitignore_visible = if respect_gitignore { gitignore_visible_names(dir) } else { None };
+    let _ = tx.send(DirLoadMsg::Done(DirListing {
+        entries: arrange_entries(dir, entries, sort),
+        gitignore_visible,
+        git_status: git_status(dir),
+    }));
+}
+
+/// The ".." entry at the top of every non-root listing.
+fn parent_entry() -> DirEntry {
+    DirEntry {
+        name: "..".to_string(),
+        depth: 0,
+        is_dir: true,
+        size: 0,
+        modified: None,
+        mode: None,
+        readonly: false,
+        hidden_attribute: false,
+        is_symlink: false,
+        link_target: None,
+    }
+}
+
+/// Archive formats that can be browsed like directories.
+#[derive(Clone, Copy, PartialEq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    fn of(path: &std::path::Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// One file or directory stored in an archive.
+struct ArchiveEntry {
+    /// Path inside the archive, `/`-separated, without a leading `./` or trailing slash.
+    path: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Split a path inside an archive (`/tmp/src.zip/docs`) into the archive file and the
+/// `/`-separated path within it (`docs`, or empty for the archive's top level). None for paths
+/// that exist on disk outside any archive.
+fn split_archive_path(path: &std::path::Path) -> Option<(PathBuf, String)> {
+    for archive in path.ancestors() {
+        if archive.is_dir() {
+            return None;
+        }
+        if archive.is_file() {
+            ArchiveKind::of(archive)?;
+            let inner = path.strip_prefix(archive).ok()?;
+            let inner: Vec<_> = inner.iter().map(|c| c.to_string_lossy()).collect();
+            return Some((archive.to_path_buf(), inner.join("/")));
+        }
+    }
+    None
+}
+
+/// An archive's entries, shared between the listings and the cache that keeps them.
+type ArchiveIndex = Arc<Vec<ArchiveEntry>>;
+
+/// The entries of `archive`, read once and kept until the archive changes on disk (browsing a
+/// compressed tarball means decompressing all of it).
+fn archive_index(archive: &std::path::Path) -> io::Result<ArchiveIndex> {
+    static CACHE: Mutex<Option<(PathBuf, Option<SystemTime>, ArchiveIndex)>> = Mutex::new(None);
+    let modified = fs::metadata(archive)?.modified().ok();
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((path, time, index)) = cache.as_ref()
+        && path == archive
+        && *time == modified
+    {
+        return Ok(Arc::clone(index));
+    }
+    let index = Arc::new(read_archive_index(archive)?);
+    *cache = Some((archive.to_path_buf(), modified, Arc::clone(&index)));
+    Ok(index)
+}
+
+/// Iterate over the members of a tar or compressed tar archive, calling `visit` with each until
+/// it returns true.
+fn visit_tar_entries(
+    archive: &std::path::Path,
+    kind: ArchiveKind,
+    mut visit: impl FnMut(&mut tar::Entry<'_, Box<dyn io::Read>>, String) -> io::Result<bool>,
+) -> io::Result<()> {
+    let file = io::BufReader::new(fs::File::open(archive)?);
+    let reader: Box<dyn io::Read> =
+        if kind == ArchiveKind::TarGz { Box::new(flate2::read::GzDecoder::new(file)) } else { Box::new(file) };
+    for entry in tar::Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let path = path.trim_start_matches("./").trim_end_matches('/').to_string();
+        if !path.is_empty() && visit(&mut entry, path)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_archive_index(archive: &std::path::Path) -> io::Result<Vec<ArchiveEntry>> {
+    let Some(kind) = ArchiveKind::of(archive) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an archive"));
+    };
+    let mut entries = Vec::new();
+    if kind == ArchiveKind::Zip {
+        let mut zip = zip::ZipArchive::new(io::BufReader::new(fs::File::open(archive)?))?;
+        for i in 0..zip.len() {
+            let file = zip.by_index_raw(i)?;
+            // Zip timestamps are in local time.
+            let modified = file
+                .last_modified()
+                .and_then(|t| chrono::NaiveDateTime::try_from(t).ok())
+                .and_then(|t| t.and_local_timezone(chrono::Local).earliest())
+                .map(SystemTime::from);
+            let path = file.name()?.trim_end_matches('/').to_string();
+            entries.push(ArchiveEntry { path, is_dir: file.is_dir(), size: file.size(), modified });
+        }
+    } else {
+        visit_tar_entries(archive, kind, |entry, path| {
+            let header = entry.header();
+            entries.push(ArchiveEntry {
+                path,
+                is_dir: header.entry_type().is_dir(),
+                size: header.size()?,
+                modified: header.mtime().ok().map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+            });
+            Ok(false)
+        })?;
+    }
+    Ok(entries)
+}
+
+/// List the directory `inner` of `archive` (empty for its top level). Archives needn't store their
+/// directories, so those are also made up from the paths of the files inside them.
+fn archive_dir_entries(archive: &std::path::Path, inner: &str) -> io::Result<Vec<DirEntry>> {
+    let index = archive_index(archive)?;
+    let prefix = if inner.is_empty() { String::new() } else { format!("{}/", inner) };
+    let mut listing: HashMap<&str, DirEntry> = HashMap::new();
+    let mut found = inner.is_empty();
+    for entry in index.iter() {
+        found |= entry.is_dir && entry.path == inner;
+        let Some(rest) = entry.path.strip_prefix(prefix.as_str()).filter(|rest| !rest.is_empty()) else {
+            continue;
+        };
+        found = true;
+        let (name, explicit) = match rest.split_once('/') {
+            Some((dir, _)) => (dir, None),
+            None => (rest, Some(entry)),
+        };
+        let item = listing.entry(name).or_insert_with(|| DirEntry {
+            name: name.to_string(),
+            depth: 0,
+            is_dir: true,
+            size: 0,
+            modified: None,
+            mode: None,
+            readonly: true,
+            hidden_attribute: false,
+            is_symlink: false,
+            link_target: None,
+        });
+        if let Some(entry) = explicit {
+            item.is_dir = entry.is_dir;
+            item.size = if entry.is_dir { 0 } else { entry.size };
+            item.modified = entry.modified;
+        }
+    }
+    if !found {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no such directory in the archive"));
+    }
+    Ok(listing.into_values().collect())
+}
+
+/// Read up to `limit` bytes of the file `inner` stored in `archive`, like `read_prefix`.
+fn read_archive_member(archive: &std::path::Path, inner: &str, limit: usize) -> io::Result<(Vec<u8>, bool)> {
+    use std::io::Read;
+
+    let mut content = Vec::new();
+    match ArchiveKind::of(archive) {
+        Some(ArchiveKind::Zip) => {
+            let mut zip = zip::ZipArchive::new(io::BufReader::new(fs::File::open(archive)?))?;
+            zip.by_name(inner)?.take(limit as u64 + 1).read_to_end(&mut content)?;
+        }
+        Some(kind) => {
+            let mut found = false;
+            visit_tar_entries(archive, kind, |entry, path| {
+                found = path == inner;
+                if found {
+                    entry.take(limit as u64 + 1).read_to_end(&mut content)?;
+                }
+                Ok(found)
+            })?;
+            if !found {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no such file in the archive"));
+            }
+        }
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an archive")),
+    }
+    let truncated = content.len() > limit;
+    content.truncate(limit);
+    Ok((content, truncated))
+}
+
+/// Whether `name` passes the list filter `pattern`: a case-insensitive glob match when the pattern
+/// has `*` or `?`, otherwise a case-insensitive substring match.
+fn filter_matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.to_lowercase(), name.to_lowercase());
+    if pattern.contains(['*', '?']) {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        glob_match(&pattern, &name)
+    } else {
+        name.contains(&pattern)
+    }
+}
+
+/// Match all of `name` against a glob where `*` is any run of characters and `?` any one.
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    // Backtrack to just after the last `*` when the rest fails to match.
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// File name of `path` for messages, or the whole path if it has none.
+fn display_name(path: &std::path::Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string_lossy().into_owned())
+}
+
+/// The names batch renaming `count` entries after `template` gives them: each `{n}` becomes the
+/// entry's number from 1, zero-padded to as many digits as the last one ("01" to "12").
+fn batch_rename_names(template: &str, count: usize) -> Vec<String> {
+    let width = count.to_string().len();
+    (1..=count).map(|n| template.replace("{n}", &format!("{:0width$}", n))).collect()
+}
+
+/// Rename each first path to its second in two passes, through a temporary name beside it, so
+/// one entry can take a name another is giving up (swaps and shifts included). If a rename
+/// fails, the ones already done are put back, as far as that goes.
+fn rename_batch(pairs: &[(PathBuf, PathBuf)]) -> Result<(), String> {
+    let temps: Vec<PathBuf> = pairs
+        .iter()
+        .enumerate()
+        .map(|(i, (from, _))| from.with_file_name(format!(".dirnav-rename-{}-{}", std::process::id(), i)))
+        .collect();
+    for (i, ((from, _), temp)) in pairs.iter().zip(&temps).enumerate() {
+        if let Err(e) = fs::rename(from, temp) {
+            for ((from, _), temp) in pairs[..i].iter().zip(&temps) {
+                let _ = fs::rename(temp, from);
+            }
+            return Err(format!("Could not rename {}: {}", display_name(from), e));
+        }
+    }
+    for (i, ((from, to), temp)) in pairs.iter().zip(&temps).enumerate() {
+        if let Err(e) = fs::rename(temp, to) {
+            for ((_, to), temp) in pairs[..i].iter().zip(&temps) {
+                let _ = fs::rename(to, temp);
+            }
+            for ((from, _), temp) in pairs.iter().zip(&temps) {
+                let _ = fs::rename(temp, from);
+            }
+            return Err(format!("Could not rename {}: {}", display_name(from), e));
+        }
+    }
+    Ok(())
+}
+
+/// Put `paths` back where they were before they were moved to the trash. Where several trashed
+/// items came from the same path, the most recent one is restored.
+#[cfg(any(windows, all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android")))))]
+fn restore_from_trash(paths: &[PathBuf]) -> Result<(), String> {
+    let mut trashed = trash::os_limited::list().map_err(|e| e.to_string())?;
+    trashed.sort_by_key(|item| std::cmp::Reverse(item.time_deleted));
+    let mut items = Vec::new();
+    for path in paths {
+        let i = trashed
+            .iter()
+            .position(|item| item.original_path() == *path)
+            .ok_or_else(|| format!("{} is no longer in the trash", display_name(path)))?;
+        items.push(trashed.remove(i));
+    }
+    trash::os_limited::restore_all(items).map_err(|e| e.to_string())
+}
+
+#[cfg(not(any(windows, all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android"))))))]
+fn restore_from_trash(_paths: &[PathBuf]) -> Result<(), String> {
+    Err("restoring from the trash isn't supported here".to_string())
+}
+
+/// Delete `path`, recursively if it is a directory. A symlink to a directory removes the link,
+/// not the target's contents.
+fn remove_path(path: &std::path::Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Copy `src` to `dest`, recursing into directories. Symlinks are copied as links (on Unix).
+fn copy_path(src: &std::path::Path, dest: &std::path::Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(src)?;
+    #[cfg(unix)]
+    if meta.file_type().is_symlink() {
+        return std::os::unix::fs::symlink(fs::read_link(src)?, dest);
+    }
+    if meta.is_dir() || (meta.file_type().is_symlink() && src.is_dir()) {
+        fs::create_dir(dest)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(src, dest).map(|_| ())
+    }
+}
+
+/// Move `src` to `dest`. `fs::rename` can't cross filesystems, so then copy and delete instead.
+fn move_path(src: &std::path::Path, dest: &std::path::Path) -> io::Result<()> {
+    match fs::rename(src, dest) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_path(src, dest)?;
+            remove_path(src)
+        }
+        result => result,
+    }
+}
+
+/// `path`, or the first of "name (1).ext", "name (2).ext", ... that doesn't exist yet.
+fn unused_path(path: &std::path::Path) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let mut candidate = path.to_path_buf();
+    let mut n = 1;
+    while fs::symlink_metadata(&candidate).is_ok() {
+        candidate = path.with_file_name(format!("{} ({}){}", stem, n, ext));
+        n += 1;
+    }
+    candidate
+}
+
+/// Short, user-facing reason for a filesystem error, without the OS error code.
+fn describe_io_error(e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        io::ErrorKind::NotFound => "No such file or directory".to_string(),
+        _ => e.to_string(),
+    }
+}
+
+/// Whether `dir` is gone from disk (deleted, or replaced by a file), as opposed to being there but
+/// unreadable. Inside an archive, whether the archive itself is gone.
+fn dir_is_gone(dir: &std::path::Path) -> bool {
+    let (path, is_archive) = match split_archive_path(dir) {
+        Some((archive, _)) => (archive, true),
+        None => (dir.to_path_buf(), false),
+    };
+    match fs::metadata(path) {
+        Ok(meta) => !is_archive && !meta.is_dir(),
+        Err(e) => e.kind() == io::ErrorKind::NotFound,
+    }
+}
+
+/// Replace each `%` in `template` with `path`, quoted for the shell so spaces and quotes survive,
+/// and each `%%` with a literal `%` (for `date +%%s`). Without a path, a lone `%` is left as is.
+fn expand_command(template: &str, path: Option<&std::path::Path>) -> String {
+    let quoted = path.map(|path| {
+        let path = path.to_string_lossy();
+        if cfg!(windows) {
+            format!("\"{}\"", path)
+        } else {
+            format!("'{}'", path.replace('\'', "'\\''"))
+        }
+    });
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match quoted.as_deref() {
+            _ if c == '%' && chars.next_if_eq(&'%').is_some() => out.push('%'),
+            Some(quoted) if c == '%' => out.push_str(quoted),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Hand the terminal back to the normal screen, for a program run in the foreground.
+fn suspend_tui() -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture)
+}
+
+/// Take the terminal back after `suspend_tui` and redraw everything.
+fn resume_tui(terminal: &mut ratatui::Terminal<CrosstermBackend<BufWriter<Stderr>>>) -> io::Result<()> {
+    enable_raw_mode()?;
+    execute!(io::stderr(), EnterAlternateScreen, EnableMouseCapture)?;
+    terminal.clear()
+}
+
+/// Open an interactive shell (`$SHELL`, or `cmd` on Windows) in `dir` with the TUI suspended, and
+/// wait for it to exit. The inner result says why the shell couldn't be started, if it couldn't.
+fn open_shell(
+    terminal: &mut ratatui::Terminal<CrosstermBackend<BufWriter<Stderr>>>,
+    dir: &std::path::Path,
+) -> io::Result<Result<(), String>> {
+    let shell = if cfg!(windows) {
+        std::env::var("COMSPEC").unwrap_or_else(|_| "cmd".to_string())
+    } else {
+        std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string())
+    };
+    suspend_tui()?;
+    eprintln!("Type exit to return to dirnav.");
+    let mut command = std::process::Command::new(&shell);
+    command.current_dir(dir);
+    // When stdout is being captured (`cd "$(dirnav)"`), the shell writes to the terminal instead.
+    if !io::stdout().is_terminal() {
+        command.stdout(io::stderr());
+    }
+    let result = command.status().map(drop).map_err(|e| format!("Could not start {}: {}", shell, describe_io_error(&e)));
+    resume_tui(terminal)?;
+    Ok(result)
+}
+
+/// Run `command` through the user's shell in `cwd` with the TUI suspended, capturing its output.
+/// Its input still comes from the terminal, so it can ask for a password or a confirmation, but
+/// its output goes to the pager afterwards, so full-screen programs don't work (`$` opens a shell
+/// for those). The TUI is restored and fully redrawn when it exits.
+fn run_shell_command(
+    terminal: &mut ratatui::Terminal<CrosstermBackend<BufWriter<Stderr>>>,
+    cwd: &std::path::Path,
+    command: &str,
+) -> io::Result<CommandOutput> {
+    use std::process::{Command, Stdio};
+
+    suspend_tui()?;
+    eprintln!("$ {}", command);
+
+    let result = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(command).current_dir(cwd).stdin(Stdio::inherit()).output()
+    } else {
+        let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+        Command::new(shell).arg("-c").arg(command).current_dir(cwd).stdin(Stdio::inherit()).output()
+    };
+
+    resume_tui(terminal)?;
+
+    let (exit_code, lines) = match result {
+        Ok(output) => {
+            let mut lines: Vec<Line<'static>> =
+                String::from_utf8_lossy(&output.stdout).lines().map(plain_line).collect();
+            lines.extend(
+                String::from_utf8_lossy(&output.stderr)
+                    .lines()
+                    .map(|l| Line::from(Span::styled(l.to_string(), Style::default().fg(Color::Red)))),
+            );
+            (output.status.code(), lines)
+        }
+        Err(e) => (None, vec![Line::from(Span::styled(
+            format!("Could not run command: {}", e),
+            Style::default().fg(Color::Red),
+        ))]),
+    };
+    Ok(CommandOutput { command: command.to_string(), exit_code, lines, scroll: 0, scroll_max: 0 })
+}
+
+/// Positions of every occurrence of `query` in `lines`, as (line, column) with the column counted
+/// in characters. Matches don't overlap and don't span lines.
+fn find_matches(lines: &[Line<'_>], query: &str, case_sensitive: bool) -> Vec<(usize, usize)> {
+    let fold = |c: char| if case_sensitive { c } else { c.to_lowercase().next().unwrap_or(c) };
+    let needle: Vec<char> = query.chars().map(fold).collect();
+    if needle.is_empty() {
+        return Vec::new();
+    }
+    let mut matches = Vec::new();
+    for (line_idx, line) in lines.iter().enumerate() {
+        let hay: Vec<char> = line.spans.iter().flat_map(|s| s.content.chars()).map(fold).collect();
+        let mut col = 0;
+        while col + needle.len() <= hay.len() {
+            if hay[col..col + needle.len()] == needle[..] {
+                matches.push((line_idx, col));
+                col += needle.len();
+            } else {
+                col += 1;
+            }
+        }
+    }
+    matches
+}
+
+/// Give the characters `start..end` of `line` the background `bg`, splitting spans as needed.
+fn highlight_range(line: &mut Line<'static>, start: usize, end: usize, bg: Color) {
+    let mut spans = Vec::with_capacity(line.spans.len() + 2);
+    let mut pos = 0;
+    for span in line.spans.drain(..) {
+        let len = span.content.chars().count();
+        let (from, to) = (start.clamp(pos, pos + len) - pos, end.clamp(pos, pos + len) - pos);
+        if from == to {
+            spans.push(span);
+        } else {
+            let chars: Vec<char> = span.content.chars().collect();
+            let piece = |a: usize, b: usize| chars[a..b].iter().collect::<String>();
+            if from > 0 {
+                spans.push(Span::styled(piece(0, from), span.style));
+            }
+            spans.push(Span::styled(piece(from, to), span.style.bg(bg).fg(Color::Black)));
+            if to < len {
+                spans.push(Span::styled(piece(to, len), span.style));
+            }
+        }
+        pos += len;
+    }
+    line.spans = spans;
+}
+
+/// Flatten styled lines into plain text, one line per `Line`. Highlighted source lines keep their
+/// original line endings inside the spans, so those are stripped before joining.
+fn lines_to_plain_text(lines: &[Line<'_>]) -> String {
+    lines
+        .iter()
+        .map(|line| {
+            let text: String = line.spans.iter().map(|s| s.content.as_ref()).collect();
+            text.trim_end_matches(['\n', '\r']).to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// How `copy_to_clipboard` handed the text over.
+#[derive(Clone, Copy)]
+enum ClipboardCopy {
+    /// A clipboard tool took it, so it's on the clipboard.
+    Tool,
+    /// It went to the terminal as an OSC 52 escape, which the terminal may ignore without a word.
+    Terminal,
+}
+
+impl ClipboardCopy {
+    /// The status message for having copied `what` this way.
+    fn describe(self, what: &str) -> String {
+        match self {
+            ClipboardCopy::Tool => format!("Copied {}", what),
+            ClipboardCopy::Terminal => format!("Sent {} to the terminal clipboard (OSC 52)", what),
+        }
+    }
+}
+
+/// Put `text` on the system clipboard. Tries the platform's clipboard tool first (pbcopy, clip,
+/// wl-copy, xclip, xsel) and falls back to the OSC 52 terminal escape, which most modern
+/// terminals (and tmux, over SSH too) understand. Whether the terminal did can't be told, so
+/// only a failure to write the escape is an error then.
+fn copy_to_clipboard(text: &str) -> Result<ClipboardCopy, String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let tools: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
+        &[("pbcopy", &[])]
+    } else if cfg!(windows) {
+        &[("clip", &[])]
+    } else {
+        &[
+            ("wl-copy", &[]),
+            ("xclip", &["-selection", "clipboard"]),
+            ("xsel", &["--clipboard", "--input"]),
+        ]
+    };
+    for (program, args) in tools {
+        let Ok(mut child) = Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+        let wrote = child
+            .stdin
+            .take()
+            .is_some_and(|mut stdin| stdin.write_all(text.as_bytes()).is_ok());
+        if wrote && child.wait().is_ok_and(|status| status.success()) {
+            return Ok(ClipboardCopy::Tool);
+        }
+    }
+
+    let mut stderr = io::stderr();
+    write!(stderr, "\x1b]52;c;{}\x07", base64_encode(text.as_bytes()))
+        .and_then(|_| stderr.flush())
+        .map(|()| ClipboardCopy::Terminal)
+        .map_err(|e| describe_io_error(&e))
+}
+
+/// Launch `path` in its default application (`open`, `start` or `xdg-open`) and return at once.
+/// The opener's output is discarded so it can't draw over the TUI, and a thread reaps it when it
+/// exits. Only a failure to start it is reported; whatever it does afterwards is up to the desktop.
+fn open_with_default_app(path: &std::path::Path) -> Result<(), String> {
+    use std::process::{Command, Stdio};
+
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        // `start` is a cmd builtin; its first quoted argument is the window title.
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => format!("{} not found", program),
+            _ => e.to_string(),
+        })?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Standard (RFC 4648) base64 with padding, for the OSC 52 clipboard escape.
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Directory for dirnav's persistent files: `$XDG_CONFIG_HOME/dirnav`, `~/.config/dirnav`,
+/// or `%APPDATA%\dirnav` on Windows. None if no suitable base directory is known.
+fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| std::env::home_dir().map(|h| h.join(".config")))
+    };
+    base.map(|b| b.join("dirnav"))
+}
+
+/// Read bookmarks from the config dir. The file has one `<letter>\t<path>` per line;
+/// malformed lines are skipped and a missing file means no bookmarks.
+fn load_bookmarks() -> HashMap<char, PathBuf> {
+    let Some(path) = config_dir().map(|d| d.join("bookmarks")) else {
+        return HashMap::new();
+    };
+    let Ok(text) = fs::read_to_string(path) else {
+        return HashMap::new();
+    };
+    text.lines()
+        .filter_map(|line| {
+            let (key, dir) = line.split_once('\t')?;
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some((c, PathBuf::from(dir))),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Write all bookmarks to the config dir, creating it if needed.
+fn save_bookmarks(bookmarks: &HashMap<char, PathBuf>) -> io::Result<()> {
+    let dir = config_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    fs::create_dir_all(&dir)?;
+    let mut letters: Vec<&char> = bookmarks.keys().collect();
+    letters.sort();
+    let text: String = letters
+        .into_iter()
+        .map(|c| format!("{}\t{}\n", c, bookmarks[c].display()))
+        .collect();
+    fs::write(dir.join("bookmarks"), text)
+}
+
+/// Read where the previous session ended from the config dir: the directory on the first line of
+/// `last_dir` and the entry selected there, if any, on the second. None if there's no such file or
+/// the directory is gone.
+fn load_last_dir() -> Option<(PathBuf, Option<String>)> {
+    let text = fs::read_to_string(config_dir()?.join("last_dir")).ok()?;
+    let mut lines = text.lines();
+    let dir = PathBuf::from(lines.next()?).canonicalize().ok().filter(|d| d.is_dir())?;
+    let selected = lines.next().filter(|name| !name.is_empty()).map(str::to_string);
+    Some((dir, selected))
+}
+
+/// Record the directory and selected entry this session ended on, for `--resume`.
+fn save_last_dir(dir: &std::path::Path, selected: Option<&str>) -> io::Result<()> {
+    let config_dir = config_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    fs::create_dir_all(&config_dir)?;
+    fs::write(config_dir.join("last_dir"), format!("{}\n{}\n", dir.display(), selected.unwrap_or("")))
+}
+
+/// Read `config.toml` from the config dir. A missing file gives the defaults (picking up the
+/// theme saved by older versions in the `theme` file); a malformed one is an error.
+fn load_config() -> Result<Config, String> {
+    let Some(dir) = config_dir() else {
+        return Ok(Config::default());
+    };
+    match fs::read_to_string(dir.join("config.toml")) {
+        Ok(text) => toml::from_str(&text).map_err(|e| e.message().to_string()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let theme = fs::read_to_string(dir.join("theme"))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_else(|_| DEFAULT_THEME.to_string());
+            Ok(Config { theme, ..Config::default() })
+        }
+        Err(e) => Err(describe_io_error(&e)),
+    }
+}
+
+/// Write `config` to `config.toml` in the config dir, creating the directory if needed.
+fn save_config(config: &Config) -> io::Result<()> {
+    let dir = config_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    fs::create_dir_all(&dir)?;
+    let text = toml::to_string(config).map_err(io::Error::other)?;
+    fs::write(dir.join("config.toml"), text)
+}
+
+/// Replace the file at `path` with `contents` by writing a temporary file beside it and renaming
+/// that over it, so a reader sees either the old contents or the new, never half of them.
+fn write_atomically(path: &std::path::Path, contents: &str) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".tmp-{}", std::process::id()));
+    let temp = PathBuf::from(temp);
+    fs::write(&temp, contents).and_then(|()| fs::rename(&temp, path)).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+/// Replace a leading `~` (alone or followed by `/`) with the user's home directory.
+fn expand_tilde(path: &str) -> PathBuf {
+    if (path == "~" || path.starts_with("~/"))
+        && let Some(home) = std::env::home_dir()
+    {
+        return home.join(path[1..].trim_start_matches('/'));
+    }
+    PathBuf::from(path)
+}
+
+/// Ask git which paths under `dir` are modified, staged, untracked or ignored, keyed by their path
+/// relative to `dir`. Each directory on the way to a changed path gets the most notable status
+/// below it, so a collapsed directory still shows that something inside changed. Empty outside a
+/// repo, or when git isn't installed.
+fn git_status(dir: &std::path::Path) -> HashMap<String, GitStatus> {
+    let mut statuses = HashMap::new();
+    if !dir.ancestors().any(|a| a.join(".git").exists()) {
+        return statuses;
+    }
+    // Porcelain paths are relative to the top of the repo, so strip the part leading to `dir`.
+    let git = |args: &[&str]| {
+        std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .stdin(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| out.stdout)
+    };
+    let Some(prefix) = git(&["rev-parse", "--show-prefix"]) else {
+        return statuses;
+    };
+    let prefix = String::from_utf8_lossy(&prefix).trim_end_matches('\n').to_string();
+    let Some(out) = git(&["status", "--porcelain", "-z", "--ignored", "--", "."]) else {
+        return statuses;
+    };
+    let mut records = out.split(|&b| b == 0);
+    while let Some(record) = records.next() {
+        if record.len() < 4 {
+            continue;
+        }
+        let (code, path) = (&record[..2], String::from_utf8_lossy(&record[3..]));
+        // Renames and copies are followed by the path they came from.
+        if matches!(code[0], b'R' | b'C') {
+            records.next();
+        }
+        let Some(status) = GitStatus::from_porcelain(code) else {
+            continue;
+        };
+        // Untracked and ignored directories come with a trailing slash.
+        let Some(rel) = path.strip_prefix(prefix.as_str()).map(|rel| rel.trim_end_matches('/')) else {
+            continue;
+        };
+        let mut end = rel.len();
+        loop {
+            let key = &rel[..end];
+            if key.is_empty() {
+                break;
+            }
+            let slot = statuses.entry(key.to_string()).or_insert(status);
+            *slot = (*slot).max(status);
+            match key.rfind('/') {
+                Some(i) => end = i,
+                None => break,
+            }
+        }
+    }
+    statuses
+}
+
+/// Names of the immediate children of `dir` that are *not* excluded by gitignore rules
+/// (.gitignore files in `dir` and its ancestors, .git/info/exclude, and the global excludes file).
+/// Returns None when `dir` isn't inside a git repository, meaning "don't filter anything".
+fn gitignore_visible_names(dir: &std::path::Path) -> Option<HashSet<String>> {
+    // The walker only applies git rules inside a repo; outside one it would list everything anyway,
+    // but checking up front lets us skip the walk entirely.
+    if !dir.ancestors().any(|a| a.join(".git").exists()) {
+        return None;
+    }
+    let walker = ignore::WalkBuilder::new(dir)
+        .max_depth(Some(1))
+        // Dotfiles are handled separately by `show_hidden`.
+        .hidden(false)
+        .build();
+    let names = walker
+        .flatten()
+        .filter(|e| e.depth() == 1)
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    Some(names)
+}
+
+/// Unix permission bits from `meta`, or None on platforms without them.
+#[cfg(unix)]
+fn permission_mode(meta: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode())
+}
+
+#[cfg(not(unix))]
+fn permission_mode(_meta: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Set the Unix permission bits of `path`; fails on platforms without them.
+#[cfg(unix)]
+fn set_permission_mode(path: &std::path::Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn set_permission_mode(_path: &std::path::Path, _mode: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "permissions can't be changed on this platform"))
+}
+
+/// The mode `text` turns `mode` into, as chmod(1) would: either an octal mode of up to four digits
+/// ("644", "2755") or comma-separated symbolic clauses like "+x", "go-w" or "u=rw,o=". A clause
+/// without a who (u, g, o, a) applies to everyone, ignoring the umask. `X` only adds execute to
+/// directories and to files someone can already execute. None if `text` is neither.
+fn parse_mode_change(text: &str, mode: u32, is_dir: bool) -> Option<u32> {
+    let text = text.trim();
+    if (1..=4).contains(&text.len()) && text.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return Some((mode & !0o7777) | u32::from_str_radix(text, 8).ok()?);
+    }
+    let mut mode = mode;
+    for clause in text.split(',') {
+        let ops_at = clause.find(['+', '-', '='])?;
+        let mut who = 0;
+        for c in clause[..ops_at].chars() {
+            who |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return None,
+            };
+        }
+        if who == 0 {
+            who = 0o7777;
+        }
+        let mut chars = clause[ops_at..].chars().peekable();
+        while let Some(op) = chars.next() {
+            let mut bits = 0;
+            while let Some(&c) = chars.peek().filter(|c| !matches!(c, '+' | '-' | '=')) {
+                bits |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    'X' if is_dir || mode & 0o111 != 0 => 0o111,
+                    'X' => 0,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    _ => return None,
+                };
+                chars.next();
+            }
+            let bits = bits & who;
+            mode = match op {
+                '+' => mode | bits,
+                '-' => mode & !bits,
+                '=' => (mode & !who) | bits,
+                _ => return None,
+            };
+        }
+    }
+    Some(mode)
+}
+
+/// Whether `meta` carries the Windows hidden attribute. Always false elsewhere.
+#[cfg(windows)]
+fn has_hidden_attribute(meta: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+/// `ls -l` style rendering of `st_mode`: the file type, then read/write/execute for owner, group
+/// and others, with setuid/setgid/sticky shown in the execute slots (e.g. "drwxr-xr-x").
+fn symbolic_permissions(mode: u32) -> String {
+    let kind = match mode & 0o170000 {
+        0o040000 => 'd',
+        0o120000 => 'l',
+        0o020000 => 'c',
+        0o060000 => 'b',
+        0o010000 => 'p',
+        0o140000 => 's',
+        _ => '-',
+    };
+    let mut out = String::with_capacity(10);
+    out.push(kind);
+    // (shift of the rwx triple, special bit, symbol when set with/without execute)
+    for (shift, special, set) in [(6, 0o4000, ('s', 'S')), (3, 0o2000, ('s', 'S')), (0, 0o1000, ('t', 'T'))] {
+        let bits = (mode >> shift) & 0o7;
+        out.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        out.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        let exec = bits & 0o1 != 0;
+        out.push(match (mode & special != 0, exec) {
+            (true, true) => set.0,
+            (true, false) => set.1,
+            (false, true) => 'x',
+            (false, false) => '-',
+        });
+    }
+    out
+}
+
+/// Sort one group of entries. Ties (e.g. directories, which all have size 0) fall back to name order.
+fn sort_entries(entries: &mut [DirEntry], sort: SortOptions) {
+    // Case-insensitive name order so "Apple" comes before "banana", unless asked for plain
+    // character order. Lowercased keys are made once per entry, not once per comparison.
+    match (sort.natural, sort.case_sensitive) {
+        (true, case_sensitive) => entries.sort_by(|a, b| natural_cmp(&a.name, &b.name, case_sensitive)),
+        (false, true) => entries.sort_by(|a, b| a.name.cmp(&b.name)),
+        (false, false) => entries.sort_by_cached_key(|e| e.name.to_lowercase()),
+    }
+    match sort.mode {
+        SortMode::Name => {}
+        // Stable sorts keep the name order among equal keys.
+        SortMode::Size => entries.sort_by_key(|e| std::cmp::Reverse(e.size)),
+        SortMode::Modified => entries.sort_by_key(|e| std::cmp::Reverse(e.modified)),
+    }
+    if sort.reverse {
+        entries.reverse();
+    }
+    // Stable again, so hidden entries keep their order among themselves.
+    let hidden = |e: &DirEntry| e.file_name().starts_with('.');
+    match sort.hidden {
+        HiddenPlacement::Interleave => {}
+        HiddenPlacement::Top => entries.sort_by_key(|e| !hidden(e)),
+        HiddenPlacement::Bottom => entries.sort_by_key(hidden),
+    }
+}
+
+/// Comparison that treats runs of ASCII digits as numbers, so "file2" < "file10", ignoring case
+/// unless `case_sensitive` is set. Equal numbers with more leading zeros sort after ("a1" < "a01"),
+/// and names that only differ in case fall back to a plain comparison so the order is always total.
+fn natural_cmp(a: &str, b: &str, case_sensitive: bool) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+
+    let (mut x, mut y) = (a, b);
+    while let (Some(cx), Some(cy)) = (x.chars().next(), y.chars().next()) {
+        if cx.is_ascii_digit() && cy.is_ascii_digit() {
+            let run_x = x.len() - x.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let run_y = y.len() - y.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let (num_x, num_y) = (x[..run_x].trim_start_matches('0'), y[..run_y].trim_start_matches('0'));
+            let ordering = num_x
+                .len()
+                .cmp(&num_y.len())
+                .then_with(|| num_x.cmp(num_y))
+                .then_with(|| run_x.cmp(&run_y));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            x = &x[run_x..];
+            y = &y[run_y..];
+        } else {
+            let ordering = if case_sensitive { cx.cmp(&cy) } else { cx.to_lowercase().cmp(cy.to_lowercase()) };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            x = &x[cx.len_utf8()..];
+            y = &y[cy.len_utf8()..];
+        }
+    }
+    x.len().cmp(&y.len()).then_with(|| a.cmp(b))
+}
+
+/// Format a byte count compactly: "512B", "1.2K", "3.4M", "5.0G".
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+/// Format a modification time relative to now: "just now", "5m ago", "3h ago", "2d ago", "4mo ago", "1y ago".
+fn format_relative_time(time: SystemTime) -> String {
+    let secs = match SystemTime::now().duration_since(time) {
+        Ok(d) => d.as_secs(),
+        // Timestamp in the future (clock skew, or files from another machine).
+        Err(_) => return "just now".to_string(),
+    };
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3_600 => format!("{}m ago", secs / 60),
+        3_600..86_400 => format!("{}h ago", secs / 3_600),
+        86_400..2_592_000 => format!("{}d ago", secs / 86_400),
+        2_592_000..31_536_000 => format!("{}mo ago", secs / 2_592_000),
+        _ => format!("{}y ago", secs / 31_536_000),
+    }
+}
+
+/// Cut `s` down to at most `max_width` terminal columns, ending in "…" if anything was removed.
+/// Uses display width (not bytes), so wide characters like CJK or emoji are measured correctly.
+fn truncate_with_ellipsis(s: &str, max_width: usize) -> String {
+    if s.width() <= max_width {
+        return s.to_string();
+    }
+    if max_width == 0 {
+        return String::new();
+    }
+    let mut out = String::new();
+    let mut width = 0;
+    for c in s.chars() {
+        let w = c.width().unwrap_or(0);
+        if width + w > max_width - 1 {
+            break;
+        }
+        out.push(c);
+        width += w;
+    }
+    out.push('…');
+    out
+}
+
+/// Cut `s` down to at most `max_width` terminal columns by replacing its middle with "…", keeping
+/// the start and the end, including the whole extension when there's room for it. Measured in
+/// display width like `truncate_with_ellipsis`.
+fn truncate_middle(s: &str, max_width: usize) -> String {
+    if s.width() <= max_width {
+        return s.to_string();
+    }
+    if max_width == 0 {
+        return String::new();
+    }
+    let available = max_width - 1;
+    let extension_width = std::path::Path::new(s)
+        .extension()
+        .map(|ext| ext.to_string_lossy().width() + 1)
+        .unwrap_or(0);
+    let tail_width = extension_width.max(available / 2).min(available);
+    let head_width = available - tail_width;
+
+    let mut head = String::new();
+    let mut width = 0;
+    for c in s.chars() {
+        let w = c.width().unwrap_or(0);
+        if width + w > head_width {
+            break;
+        }
+        head.push(c);
+        width += w;
+    }
+    let mut tail = Vec::new();
+    let mut width = 0;
+    for c in s.chars().rev() {
+        let w = c.width().unwrap_or(0);
+        if width + w > tail_width {
+            break;
+        }
+        tail.push(c);
+        width += w;
+    }
+    head.push('…');
+    head.extend(tail.into_iter().rev());
+    head
+}
+
+/// Build a plain Line from a string (single-style).
+fn plain_line(s: impl Into<String>) -> Line<'static> {
+    Line::from(Span::raw(s.into()))
+}
+
+/// Extensions of the image formats we can decode for previews.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "bmp", "webp"];
+
+fn is_image_path(path: &std::path::Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| IMAGE_EXTENSIONS.iter().any(|i| e.eq_ignore_ascii_case(i)))
+}
+
+/// Text stand-in for a binary file, or an image the terminal can't draw: its type, sniffed from
+/// the first bytes, what a few formats tell cheaply about themselves (image dimensions, audio
+/// duration, the CPU an executable is for), and its size.
+fn binary_summary_lines(path: &std::path::Path, content: &[u8]) -> Vec<Line<'static>> {
+    let kind = infer::get(content);
+    let mut out = vec![plain_line(match kind {
+        Some(kind) => {
+            let what = match kind.matcher_type() {
+                infer::MatcherType::Image => "image",
+                infer::MatcherType::Video => "video",
+                infer::MatcherType::Audio => "audio",
+                infer::MatcherType::Archive => "archive",
+                infer::MatcherType::Book => "book",
+                infer::MatcherType::Doc => "document",
+                infer::MatcherType::Font => "font",
+                infer::MatcherType::App => "program",
+                infer::MatcherType::Text | infer::MatcherType::Custom => "file",
+            };
+            format!("({} {}, {})", kind.extension().to_uppercase(), what, kind.mime_type())
+        }
+        None => "(binary file)".to_string(),
+    })];
+    let image = kind.is_some_and(|k| k.matcher_type() == infer::MatcherType::Image) || is_image_path(path);
+    let dimensions = image
+        .then(|| image::ImageReader::new(io::Cursor::new(content)).with_guessed_format().ok())
+        .flatten()
+        .and_then(|reader| reader.into_dimensions().ok());
+    if let Some((width, height)) = dimensions {
+        out.push(plain_line(format!("{} × {} pixels", width, height)));
+    }
+    if let Some(duration) = wav_duration(content).or_else(|| flac_duration(content)) {
+        let secs = duration.as_secs();
+        out.push(plain_line(match secs / 3600 {
+            0 => format!("{}:{:02}", secs / 60, secs % 60),
+            hours => format!("{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+        }));
+    }
+    if let Some(executable) = describe_executable(content) {
+        out.push(plain_line(executable));
+    }
+    // An archive member has no metadata of its own; what was read of it will do.
+    let size = fs::metadata(path).map_or(content.len() as u64, |meta| meta.len());
+    out.push(plain_line(format_size(size)));
+    out.push(Line::from(Span::styled(
+        "press x for hex view",
+        Style::default().fg(Color::DarkGray),
+    )));
+    out
+}
+
+/// How long a WAV file plays, from the byte rate in its `fmt ` chunk and the size of its `data`.
+fn wav_duration(bytes: &[u8]) -> Option<Duration> {
+    if bytes.get(0..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let u32_at = |i: usize| Some(u32::from_le_bytes(bytes.get(i..i + 4)?.try_into().ok()?));
+    let (mut pos, mut byte_rate) = (12, None);
+    while let (Some(id), Some(len)) = (bytes.get(pos..pos + 4), u32_at(pos + 4)) {
+        match id {
+            b"fmt " => byte_rate = u32_at(pos + 16).filter(|&rate| rate > 0),
+            b"data" => return Some(Duration::from_secs_f64(len as f64 / byte_rate? as f64)),
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        pos += 8 + len as usize + len as usize % 2;
+    }
+    None
+}
+
+/// How long a FLAC file plays, from the sample rate and sample count in its STREAMINFO block.
+fn flac_duration(bytes: &[u8]) -> Option<Duration> {
+    if bytes.get(0..4)? != b"fLaC" || bytes.get(4)? & 0x7f != 0 {
+        return None;
+    }
+    // 20 bits of sample rate, 3 of channels, 5 of sample size, then 36 of total samples.
+    let bits = u64::from_be_bytes(bytes.get(18..26)?.try_into().ok()?);
+    let (rate, samples) = (bits >> 44, bits & 0xf_ffff_ffff);
+    (rate > 0 && samples > 0).then(|| Duration::from_secs_f64(samples as f64 / rate as f64))
+}
+
+/// What an ELF, PE or Mach-O header says the file is and which CPU it's for, e.g.
+/// "ELF 64-bit shared object for x86-64".
+fn describe_executable(bytes: &[u8]) -> Option<String> {
+    let u16_le = |i: usize| Some(u16::from_le_bytes(bytes.get(i..i + 2)?.try_into().ok()?));
+    let u32_le = |i: usize| Some(u32::from_le_bytes(bytes.get(i..i + 4)?.try_into().ok()?));
+    if bytes.starts_with(b"\x7fELF") {
+        let bits = match bytes.get(4)? {
+            1 => "32-bit",
+            2 => "64-bit",
+            _ => return None,
+        };
+        let big_endian = *bytes.get(5)? == 2;
+        let field = |i: usize| u16_le(i).map(|v| if big_endian { v.swap_bytes() } else { v });
+        let kind = match field(16)? {
+            1 => "relocatable object",
+            2 => "executable",
+            3 => "shared object",
+            4 => "core dump",
+            _ => "file",
+        };
+        let arch = match field(18)? {
+            0x03 => "x86".to_string(),
+            0x08 => "MIPS".to_string(),
+            0x14 => "PowerPC".to_string(),
+            0x15 => "PowerPC 64".to_string(),
+            0x16 => "S/390".to_string(),
+            0x28 => "ARM".to_string(),
+            0x2b => "SPARC V9".to_string(),
+            0x3e => "x86-64".to_string(),
+            0xb7 => "AArch64".to_string(),
+            0xf3 => "RISC-V".to_string(),
+            0x102 => "LoongArch".to_string(),
+            other => format!("machine {:#x}", other),
+        };
+        return Some(format!("ELF {} {} for {}", bits, kind, arch));
+    }
+    if bytes.starts_with(b"MZ") {
+        let header = u32_le(0x3c)? as usize;
+        if bytes.get(header..header + 4)? != b"PE\0\0" {
+            return None;
+        }
+        let arch = match u16_le(header + 4)? {
+            0x014c => "x86".to_string(),
+            0x01c0 | 0x01c4 => "ARM".to_string(),
+            0x5064 => "RISC-V 64".to_string(),
+            0x8664 => "x86-64".to_string(),
+            0xaa64 => "AArch64".to_string(),
+            other => format!("machine {:#x}", other),
+        };
+        let kind = if u16_le(header + 22)? & 0x2000 != 0 { "DLL" } else { "executable" };
+        return Some(format!("PE {} for {}", kind, arch));
+    }
+    let bits = match u32_le(0)? {
+        0xfeed_face => "32-bit",
+        0xfeed_facf => "64-bit",
+        _ => return None,
+    };
+    let arch = match u32_le(4)? {
+        0x0000_0007 => "x86".to_string(),
+        0x0100_0007 => "x86-64".to_string(),
+        0x0000_000c => "ARM".to_string(),
+        0x0100_000c => "arm64".to_string(),
+        other => format!("CPU type {:#x}", other),
+    };
+    let kind = match u32_le(12)? {
+        1 => "object",
+        2 => "executable",
+        6 => "dynamic library",
+        8 => "bundle",
+        _ => "file",
+    };
+    Some(format!("Mach-O {} {} for {}", bits, kind, arch))
+}
+
+/// Preview limit used until the config says otherwise.
+const DEFAULT_PREVIEW_LIMIT: usize = 512 * 1024;
+/// Largest preview limit allowed, so a huge file is never read into memory whole.
+const MAX_PREVIEW_LIMIT: usize = 64 * 1024 * 1024;
+/// Largest image drawn in the preview, per side and decoded (at 4 bytes a pixel); bigger ones
+/// get the text summary instead.
+const MAX_IMAGE_SIDE: u32 = 16_384;
+const MAX_IMAGE_BYTES: u64 = 256 * 1024 * 1024;
+/// Shares of the split the preview can take, in percent, so neither side disappears.
+const PREVIEW_RATIO_RANGE: std::ops::RangeInclusive<u16> = 20..=80;
+/// How far one `<` or `>` moves the split, in percent.
+const PREVIEW_RATIO_STEP: i16 = 5;
+/// How many rows of a CSV file the column widths are worked out from, and the widest a column gets.
+const CSV_WIDTH_ROWS: usize = 1000;
+const CSV_MAX_COLUMN_WIDTH: usize = 40;
+/// How many lines of the selected file the peek shows, and how much of the file it reads for them.
+const PEEK_LINES: usize = 2;
+const PEEK_BYTES: usize = 4096;
+
+/// Read at most `limit` bytes of the file at `path`. Returns the bytes and whether the file
+/// had more; the rest is never read.
+fn read_prefix(path: &std::path::Path, limit: usize) -> io::Result<(Vec<u8>, bool)> {
+    use std::io::Read;
+
+    if let Some((archive, inner)) = split_archive_path(path)
+        && !inner.is_empty()
+    {
+        return read_archive_member(&archive, &inner, limit);
+    }
+    let mut content = Vec::new();
+    // One byte past the limit tells us whether the file was cut short.
+    fs::File::open(path)?.take(limit as u64 + 1).read_to_end(&mut content)?;
+    let truncated = content.len() > limit;
+    content.truncate(limit);
+    Ok((content, truncated))
+}
+
+/// Read the last `limit` bytes of the file at `path`, dropping the partial line they start in the
+/// middle of (so they never start mid-character either). Returns (bytes, truncated) like
+/// `read_prefix`, which it defers to for files within the limit and files inside archives.
+fn read_suffix(path: &std::path::Path, limit: usize) -> io::Result<(Vec<u8>, bool)> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let Ok(mut file) = fs::File::open(path) else {
+        return read_prefix(path, limit);
+    };
+    let len = file.metadata()?.len();
+    if len <= limit as u64 {
+        return read_prefix(path, limit);
+    }
+    file.seek(SeekFrom::Start(len - limit as u64))?;
+    let mut content = Vec::new();
+    file.take(limit as u64).read_to_end(&mut content)?;
+    if let Some(newline) = content.iter().position(|&b| b == b'\n') {
+        content.drain(..=newline);
+    }
+    Ok((content, true))
+}
+
+/// Read and render a preview the way `App::reload_preview` asks for, decoding the image too when
+/// `decode_image` is set. Runs on a worker thread.
+fn load_preview(path: &std::path::Path, markdown: bool, opts: PreviewOptions, decode_image: bool) -> LoadedPreview {
+    let mut loaded = if markdown {
+        let (content, truncated) = load_markdown_preview(path, opts.theme, opts.limit);
+        LoadedPreview { truncated, ..LoadedPreview::plain(content) }
+    } else {
+        load_file_preview(path, opts)
+    };
+    loaded.image = decode_image.then(|| load_image(path)).flatten();
+    loaded
+}
+
+/// Decode the image at `path` for drawing, unless it's too big to hold in memory: wider or taller
+/// than `MAX_IMAGE_SIDE`, or more than `MAX_IMAGE_BYTES` decoded. The header is read first so an
+/// oversized image is turned down without decoding any of it.
+fn load_image(path: &std::path::Path) -> Option<image::DynamicImage> {
+    let reader = || image::ImageReader::open(path).and_then(|r| r.with_guessed_format()).ok();
+    let (width, height) = reader()?.into_dimensions().ok()?;
+    if width > MAX_IMAGE_SIDE || height > MAX_IMAGE_SIDE || u64::from(width) * u64::from(height) * 4 > MAX_IMAGE_BYTES {
+        return None;
+    }
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_SIDE);
+    limits.max_image_height = Some(MAX_IMAGE_SIDE);
+    limits.max_alloc = Some(MAX_IMAGE_BYTES);
+    let mut reader = reader()?;
+    reader.limits(limits);
+    reader.decode().ok()
+}
+
+/// Worker side of a directory preview: the entries of `path` as `read_dir_entries` orders them,
+/// one per line with its size, skipping hidden ones unless `show_hidden`.
+fn load_dir_preview(path: &std::path::Path, sort: SortOptions, show_hidden: bool, icons: IconSet) -> LoadedPreview {
+    let content = match read_dir_entries(path, sort) {
+        Ok(entries) => {
+            let lines: Vec<Line<'static>> = entries
+                .iter()
+                .filter(|e| e.name != ".." && (show_hidden || !e.name.starts_with('.')))
+                .map(|e| {
+                    let style = if e.is_symlink {
+                        Style::default().fg(Color::Cyan)
+                    } else if e.is_executable() {
+                        Style::default().fg(Color::Green)
+                    } else {
+                        Style::default()
+                    };
+                    let prefix = icons.prefix(e.is_dir, false, e.is_symlink);
+                    let suffix = if e.is_dir { icons.dir_suffix() } else { "" };
+                    let mut spans = vec![Span::styled(format!("{}{}{}", prefix, e.name, suffix), style)];
+                    if !e.is_dir {
+                        let size = format!("  {}", format_size(e.size));
+                        spans.push(Span::styled(size, Style::default().fg(Color::DarkGray)));
+                    }
+                    Line::from(spans)
+                })
+                .collect();
+            if lines.is_empty() { vec![plain_line("(empty directory)")] } else { lines }
+        }
+        Err(e) => vec![plain_line(format!("Error reading: {}", describe_io_error(&e)))],
+    };
+    LoadedPreview::plain(content)
+}
+
+/// The frame of a busy spinner that has been going since `started`, advancing every 100ms.
+fn spinner_frame(started: Instant) -> char {
+    const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+    FRAMES[(started.elapsed().as_millis() / 100) as usize % FRAMES.len()]
+}
+
+/// A preview limit for messages, e.g. "512 KB" or "2 MB".
+fn format_preview_limit(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 && bytes.is_multiple_of(1024 * 1024) {
+        format!("{} MB", bytes / (1024 * 1024))
+    } else {
+        format!("{} KB", bytes / 1024)
+    }
+}
+
+/// UTF-16 text, going by a byte order mark or, failing that, by every other byte being zero (as
+/// it is for mostly-ASCII text). It would otherwise look binary for all the zeros.
+fn utf16_encoding(bytes: &[u8]) -> Option<&'static encoding_rs::Encoding> {
+    if let Some((encoding, _)) = encoding_rs::Encoding::for_bom(bytes) {
+        return (encoding != encoding_rs::UTF_8).then_some(encoding);
+    }
+    let sample = &bytes[..bytes.len().min(4096) & !1];
+    let pairs = sample.len() / 2;
+    if pairs < 2 {
+        return None;
+    }
+    let zeros_at = |start: usize| sample.iter().skip(start).step_by(2).filter(|&&b| b == 0).count();
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    if odd > pairs * 2 / 5 && even < pairs / 20 {
+        Some(encoding_rs::UTF_16LE)
+    } else if even > pairs * 2 / 5 && odd < pairs / 20 {
+        Some(encoding_rs::UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Whether `bytes` from the start of a file look like binary data rather than text. Bytes above
+/// ASCII are text in some encoding, so only control characters and zeros (which no single-byte
+/// text has, though UTF-16 does) count against it.
+fn looks_binary(bytes: &[u8]) -> bool {
+    let control = bytes
+        .iter()
+        .filter(|&&b| b.is_ascii_control() && !matches!(b, b'\n' | b'\r' | b'\t' | 0x0c | 0x1b))
+        .count();
+    utf16_encoding(bytes).is_none() && (bytes.contains(&0) || control > bytes.len() / 20)
+}
+
+/// The first `PEEK_LINES` lines of the text file at `path` that aren't blank, trimmed, for the
+/// peek under the list. Empty for binary and unreadable files.
+fn peek_lines(path: &std::path::Path) -> Vec<String> {
+    let Ok((content, truncated)) = read_prefix(path, PEEK_BYTES) else {
+        return Vec::new();
+    };
+    if looks_binary(&content) {
+        return Vec::new();
+    }
+    let (text, _) = decode_text(&content, truncated, utf16_encoding(&content));
+    text.trim_start_matches('\u{feff}')
+        .lines()
+        .map(|line| line.trim().chars().map(|c| if c.is_control() { ' ' } else { c }).collect::<String>())
+        .filter(|line| !line.is_empty())
+        .take(PEEK_LINES)
+        .collect()
+}
+
+/// Decode text read for the preview: UTF-8 when it is (a character cut off by the preview limit
+/// is dropped), else `encoding` or the legacy encoding it looks most like. Returns the text and
+/// the name of the encoding when it wasn't UTF-8.
+fn decode_text(
+    bytes: &[u8],
+    truncated: bool,
+    encoding: Option<&'static encoding_rs::Encoding>,
+) -> (String, Option<&'static str>) {
+    let encoding = match (encoding, std::str::from_utf8(bytes)) {
+        (Some(encoding), _) => encoding,
+        (None, Ok(text)) => return (text.to_string(), None),
+        (None, Err(e)) if truncated && e.error_len().is_none() => {
+            return (String::from_utf8_lossy(&bytes[..e.valid_up_to()]).into_owned(), None);
+        }
+        (None, Err(_)) => {
+            let mut detector = chardetng::EncodingDetector::new(chardetng::Iso2022JpDetection::Deny);
+            detector.feed(bytes, !truncated);
+            detector.guess(None, chardetng::Utf8Detection::Allow)
+        }
+    };
+    // Undecodable bytes come out as U+FFFD rather than failing the whole preview.
+    let (text, _, _) = encoding.decode(bytes);
+    (text.into_owned(), Some(encoding.name()))
+}
+
+/// Load a short preview of a file: content only, with syntax highlighting when available.
+/// Binary files get a one-line summary, or a hex dump when `opts.hex_view` is set.
+/// Returns (lines, truncated, encoding) where truncated is true if the file was larger than the
+/// limit, and encoding names the text encoding when it isn't UTF-8.
+fn load_file_preview(path: &std::path::Path, opts: PreviewOptions) -> LoadedPreview {
+    let mut out: Vec<Line<'static>> = Vec::new();
+
+    if let Some(problem) = symlink_problem(path) {
+        out.push(plain_line(problem));
+        return LoadedPreview::plain(out);
+    }
+
+    let read = if opts.tail { read_suffix(path, opts.limit) } else { read_prefix(path, opts.limit) };
+    let (content, truncated) = match read {
+        Ok(read) => read,
+        Err(e) => {
+            out.push(plain_line(format!("Error reading: {}", e)));
+            return LoadedPreview::plain(out);
+        }
+    };
+    if content.is_empty() {
+        out.push(plain_line("(empty file)"));
+        return LoadedPreview::plain(out);
+    }
+    let text = content.as_slice();
+    let utf16 = utf16_encoding(text);
+    if looks_binary(text) {
+        if opts.hex_view {
+            return LoadedPreview { truncated, ..LoadedPreview::plain(hex_dump_lines(text)) };
+        }
+        return LoadedPreview::plain(binary_summary_lines(path, text));
+    }
+    let (mut content_str, encoding) = decode_text(text, truncated, utf16);
+    // A byte order mark isn't part of the text.
+    if let Some(rest) = content_str.strip_prefix('\u{feff}') {
+        content_str = rest.to_string();
+    }
+    // A truncated file can't parse, so the part that was read is reindented as is.
+    if opts.pretty_json && is_json_path(path) {
+        match serde_json::from_str::<serde::de::IgnoredAny>(&content_str) {
+            Err(e) if !truncated => out.push(Line::from(Span::styled(
+                format!("(not valid JSON: {}; showing it as is)", e),
+                Style::default().fg(Color::DarkGray),
+            ))),
+            _ => content_str = pretty_print_json(&content_str),
+        }
+    }
+    // A tail read from partway through the file starts partway through a row; otherwise a
+    // truncated read ends partway through one.
+    if opts.csv_table
+        && let Some(delimiter) = csv_delimiter(path)
+    {
+        let from_offset = opts.tail && truncated;
+        let content = csv_table_lines(&content_str, delimiter, from_offset, truncated && !from_offset);
+        return LoadedPreview { truncated, encoding, ..LoadedPreview::plain(content) };
+    }
+
+    let ps = syntax_set();
+    let syntax = detect_syntax(path, &content_str);
+    let mut highlighter = HighlightLines::new(syntax, opts.theme);
+    // Diffs are coloured by their line prefixes instead, like `git diff`.
+    let diff = is_diff(path, &content_str);
+
+    // Gutter is as wide as the largest line number so the numbers stay right-aligned.
+    let gutter_width = LinesWithEndings::from(&content_str).count().to_string().len();
+
+    // Highlighting and wrapping a minified file's one enormous line would make the preview
+    // crawl, so lines past the limit are cut short (and the title says so). Saying it in the
+    // content would shift every line number by one.
+    let max = opts.max_line_length;
+    let too_long = |line: &str| max > 0 && line.len() > max && line.chars().nth(max).is_some();
+    let lines_cut = content_str.lines().any(too_long);
+    // Copying the preview wants the text as shown but without the decorations drawn over it.
+    let mut plain_lines: Option<Vec<String>> = (opts.line_numbers || opts.show_whitespace)
+        .then(|| out.iter().map(|line| line.spans.iter().map(|s| s.content.as_ref()).collect()).collect());
+
+    for (line_idx, line_with_ending) in LinesWithEndings::from(&content_str).enumerate() {
+        let cut;
+        let (line_with_ending, cut_short) = if too_long(line_with_ending.trim_end_matches(['\r', '\n'])) {
+            let end = line_with_ending.char_indices().nth(max).map_or(line_with_ending.len(), |(i, _)| i);
+            cut = format!("{}\n", &line_with_ending[..end]);
+            (cut.as_str(), true)
+        } else {
+            (line_with_ending, false)
+        };
+        // Tabs are expanded before highlighting so the spans come out with the final columns;
+        // when whitespace is shown, `mark_whitespace` expands them afterwards instead.
+        let expanded;
+        let line_with_ending = if line_with_ending.contains('\t') && !opts.show_whitespace {
+            expanded = expand_tabs(line_with_ending, opts.tab_width);
+            expanded.as_str()
+        } else {
+            line_with_ending
+        };
+        let mut line_spans: Vec<Span> = if diff {
+            vec![Span::styled(line_with_ending.to_string(), diff_line_style(line_with_ending))]
+        } else {
+            match highlighter.highlight_line(line_with_ending, ps) {
+                Ok(segments) => segments
+                    .into_iter()
+                    .map(|(syntect_style, text)| {
+                        let mut style = Style::default();
+                        if let Some(c) = syntect_color_to_ratatui(syntect_style.foreground) {
+                            style = style.fg(c);
+                        }
+                        let mods = syntect_font_style_to_modifier(syntect_style.font_style);
+                        if !mods.is_empty() {
+                            style = style.add_modifier(mods);
+                        }
+                        Span::styled(text.to_string(), style)
+                    })
+                    .collect(),
+                Err(_) => vec![Span::raw(line_with_ending.to_string())],
+            }
+        };
+        if let Some(plain_lines) = plain_lines.as_mut() {
+            let text = line_with_ending.trim_end_matches(['\r', '\n']);
+            plain_lines.push(if cut_short { format!("{}…", text) } else { text.to_string() });
+        }
+        if opts.show_whitespace {
+            line_spans = mark_whitespace(line_spans, opts.tab_width);
+        }
+        if cut_short {
+            line_spans.push(Span::styled("…", Style::default().fg(Color::DarkGray)));
+        }
+        if opts.line_numbers && !(opts.tail && truncated) {
+            line_spans.insert(
+                0,
+                Span::styled(
+                    format!("{:>width$} │ ", line_idx + 1, width = gutter_width),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            );
+        }
+        out.push(Line::from(line_spans));
+    }
+
+    let plain_text = plain_lines.map(|lines| lines.join("\n"));
+    LoadedPreview { content: out, truncated, encoding, image: None, lines_cut, plain_text }
+}
+
+/// Why `path` can't be previewed if it is a symbolic link that leads nowhere: "(broken symlink ->
+/// target)" when the chain of links ends at a missing path, "(symlink loop)" when it comes back
+/// on itself. None for anything else, including links that resolve.
+fn symlink_problem(path: &std::path::Path) -> Option<String> {
+    let target = fs::read_link(path).ok()?;
+    if fs::metadata(path).is_ok() {
+        return None;
+    }
+    // Follow the chain by hand: a missing path ends it, a path seen before means a loop.
+    let mut seen = HashSet::new();
+    let mut current = path.to_path_buf();
+    while let Ok(next) = fs::read_link(&current) {
+        let next = current.parent().map(|dir| dir.join(&next)).unwrap_or(next);
+        if !seen.insert(next.clone()) || seen.len() > 40 {
+            return Some("(symlink loop)".to_string());
+        }
+        current = next;
+    }
+    if fs::symlink_metadata(&current).is_err() {
+        return Some(format!("(broken symlink -> {})", target.display()));
+    }
+    // Something else is wrong (e.g. a directory on the way can't be read); the read reports it.
+    None
+}
+
+/// Whether to show `text` as a diff: a `.diff` or `.patch` file, or anything that starts the way
+/// `git diff` or `diff -u` output does.
+fn is_diff(path: &std::path::Path, text: &str) -> bool {
+    let by_extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("diff") || e.eq_ignore_ascii_case("patch"));
+    by_extension
+        || text.starts_with("diff --git ")
+        || text.starts_with("@@ ")
+        || (text.starts_with("--- ") && text.contains("\n+++ "))
+}
+
+/// How a line of a diff is coloured: file headers bold, hunk headers cyan, added lines green,
+/// removed lines red and context dimmed.
+fn diff_line_style(line: &str) -> Style {
+    const HEADERS: &[&str] = &["diff ", "index ", "--- ", "+++ ", "new file", "deleted file", "similarity", "rename "];
+    if HEADERS.iter().any(|h| line.starts_with(h)) {
+        Style::default().add_modifier(Modifier::BOLD)
+    } else if line.starts_with("@@") {
+        Style::default().fg(Color::Cyan)
+    } else if line.starts_with('+') {
+        Style::default().fg(Color::Green)
+    } else if line.starts_with('-') {
+        Style::default().fg(Color::Red)
+    } else {
+        Style::default().add_modifier(Modifier::DIM)
+    }
+}
+
+/// Replace each tab in `line` with spaces up to the next multiple of `tab_width` columns, counting
+/// wide characters as two columns, the way a terminal with those tab stops would show it.
+fn expand_tabs(line: &str, tab_width: usize) -> String {
+    let mut out = String::with_capacity(line.len() + tab_width);
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = tab_width - column % tab_width;
+            out.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            out.push(c);
+            column += c.width().unwrap_or(0);
+        }
+    }
+    out
+}
+
+/// Make the whitespace in a highlighted line visible: each space becomes a dim "·" and each tab a
+/// dim "→" padded to the next tab stop (as `expand_tabs` would), and whitespace at the end of the
+/// line gets a dark red background. The other characters keep their spans' styles.
+fn mark_whitespace(spans: Vec<Span<'static>>, tab_width: usize) -> Vec<Span<'static>> {
+    let text: String = spans.iter().map(|span| span.content.as_ref()).collect();
+    let trailing_from = text.trim_end_matches(['\r', '\n']).trim_end_matches([' ', '\t']).chars().count();
+    let mut out = Vec::with_capacity(spans.len());
+    let (mut index, mut column) = (0, 0);
+    for span in spans {
+        let mut run = String::new();
+        for c in span.content.chars() {
+            let glyph = match c {
+                ' ' => "·".to_string(),
+                '\t' => format!("→{}", " ".repeat(tab_width - column % tab_width - 1)),
+                _ => {
+                    run.push(c);
+                    column += c.width().unwrap_or(0);
+                    index += 1;
+                    continue;
+                }
+            };
+            if !run.is_empty() {
+                out.push(Span::styled(std::mem::take(&mut run), span.style));
+            }
+            let mut style = span.style.fg(Color::DarkGray);
+            if index >= trailing_from {
+                style = style.bg(Color::Indexed(52));
+            }
+            column += glyph.chars().count();
+            index += 1;
+            out.push(Span::styled(glyph, style));
+        }
+        if !run.is_empty() {
+            out.push(Span::styled(run, span.style));
+        }
+    }
+    out
+}
+
+fn is_json_path(path: &std::path::Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+}
+
+/// The field separator of a `.csv` or `.tsv` file; None for other files.
+fn csv_delimiter(path: &std::path::Path) -> Option<u8> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    match ext.as_str() {
+        "csv" => Some(b','),
+        "tsv" | "tab" => Some(b'\t'),
+        _ => None,
+    }
+}
+
+/// Lay CSV text out as a table. Each column is as wide as its widest cell among the first
+/// `CSV_WIDTH_ROWS` rows, up to `CSV_MAX_COLUMN_WIDTH` (longer cells are cut with "…"), and
+/// columns of numbers are right-aligned. The first row is styled as a header, unless the text is
+/// `cut_start`: read from partway through the file, so that row is likely partial and is left out
+/// instead. Likewise `cut_end` text ends partway through a row, which is left out. Short rows are
+/// padded out and long ones get extra columns.
+fn csv_table_lines(text: &str, delimiter: u8, cut_start: bool, cut_end: bool) -> Vec<Line<'static>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut error = None;
+    for record in reader.records() {
+        match record {
+            // Line breaks inside quoted fields would break the row apart on screen.
+            Ok(record) => {
+                let clean = |field: &str| field.chars().map(|c| if c.is_control() { ' ' } else { c }).collect::<String>();
+                rows.push(record.iter().map(clean).collect());
+            }
+            Err(e) => {
+                error = Some(format!("(could not read past row {}: {})", rows.len(), e));
+                break;
+            }
+        }
+    }
+    if cut_end && error.is_none() {
+        rows.pop();
+    }
+    if cut_start && !rows.is_empty() {
+        rows.remove(0);
+    }
+    let header = !cut_start;
+
+    let sample = &rows[..rows.len().min(CSV_WIDTH_ROWS)];
+    let columns = sample.iter().map(Vec::len).max().unwrap_or(0);
+    let mut widths = vec![0; columns];
+    let mut numeric = vec![true; columns];
+    for (i, row) in sample.iter().enumerate() {
+        let is_header = header && i == 0;
+        for (col, cell) in row.iter().enumerate() {
+            widths[col] = widths[col].max(cell.width().min(CSV_MAX_COLUMN_WIDTH));
+            let value = cell.trim();
+            if !is_header && !value.is_empty() && value.parse::<f64>().is_err() {
+                numeric[col] = false;
+            }
+        }
+    }
 
-    const MAX_PREVIEW_BYTES: usize = 512 * 1024;
-    let content = match fs::read(path) {
-        Ok(b) => b,
-        Err(e) => {
-            out.push(plain_line(format!("Error reading: {}", e)));
-            return (out, false);
+    let dim = Style::default().fg(Color::DarkGray);
+    let header_style = Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD);
+    let mut out = Vec::with_capacity(rows.len() + 1);
+    for (i, row) in rows.iter().enumerate() {
+        let is_header = header && i == 0;
+        let style = if is_header { header_style } else { Style::default() };
+        let mut spans = Vec::with_capacity(row.len().max(columns) * 2);
+        for col in 0..row.len().max(columns) {
+            if col > 0 {
+                spans.push(Span::styled(" │ ", dim));
+            }
+            let cell = row.get(col).map_or("", String::as_str);
+            // Columns first seen after the sampled rows go unpadded.
+            let width = widths.get(col).copied().unwrap_or(0);
+            let cell = truncate_with_ellipsis(cell, if col < columns { width } else { CSV_MAX_COLUMN_WIDTH });
+            let pad = " ".repeat(width.saturating_sub(cell.width()));
+            let text = if numeric.get(col).copied().unwrap_or(false) && !is_header {
+                format!("{}{}", pad, cell)
+            } else {
+                format!("{}{}", cell, pad)
+            };
+            spans.push(Span::styled(text, style));
         }
-    };
-    if content.is_empty() {
-        out.push(plain_line("(empty file)"));
-        return (out, false);
+        out.push(Line::from(spans));
     }
-    let truncated = content.len() > MAX_PREVIEW_BYTES;
-    let text = if truncated {
-        &content[..MAX_PREVIEW_BYTES]
-    } else {
-        content.as_slice()
-    };
-    let non_print = text
-        .iter()
-        .filter(|&&b| !b.is_ascii_graphic() && !matches!(b, b' ' | b'\n' | b'\r' | b'\t'))
-        .count();
-    if non_print > text.len() / 4 {
-        out.push(plain_line("(binary file)"));
-        return (out, false);
+    if let Some(error) = error {
+        out.push(Line::from(Span::styled(error, dim)));
     }
-    let content_str = match String::from_utf8(text.to_vec()) {
-        Ok(s) => s,
-        Err(_) => {
-            out.push(plain_line("(not valid UTF-8)"));
-            return (out, false);
+    out
+}
+
+/// Reindent JSON text with 2-space indentation, one member or element per line. Works token by
+/// token rather than parsing, so key order and number formatting are kept and a truncated
+/// document still comes out readable up to where it was cut off.
+fn pretty_print_json(text: &str) -> String {
+    fn newline(out: &mut String, depth: usize) {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    }
+    let mut out = String::with_capacity(text.len() * 2);
+    let mut depth = 0usize;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                out.push(c);
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => out.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '{' | '[' => {
+                out.push(c);
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                // Keep empty objects and arrays on one line.
+                if let Some(close) = chars.next_if(|&next| matches!((c, next), ('{', '}') | ('[', ']'))) {
+                    out.push(close);
+                } else {
+                    depth += 1;
+                    newline(&mut out, depth);
+                }
+            }
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                newline(&mut out, depth);
+                out.push(c);
+            }
+            ',' => {
+                out.push(c);
+                newline(&mut out, depth);
+            }
+            ':' => out.push_str(": "),
+            c if c.is_whitespace() => {}
+            c => out.push(c),
         }
-    };
+    }
+    out.push('\n');
+    out
+}
 
+/// Well-known extensionless file names (and dotfiles) that syntect's defaults don't claim,
+/// mapped to the syntax token to highlight them with.
+const KNOWN_FILE_NAMES: &[(&str, &str)] = &[
+    ("Dockerfile", "bash"),
+    ("Containerfile", "bash"),
+    ("PKGBUILD", "bash"),
+    ("APKBUILD", "bash"),
+    (".envrc", "bash"),
+    (".zshrc", "bash"),
+    (".zprofile", "bash"),
+    (".zshenv", "bash"),
+    ("Justfile", "make"),
+    ("justfile", "make"),
+    ("Jenkinsfile", "groovy"),
+    ("BUILD", "py"),
+    ("WORKSPACE", "py"),
+];
+
+/// Pick a syntax for highlighting `path`: by extension first, then by the whole file name
+/// (syntect knows e.g. `Makefile`, `Gemfile`, `.bashrc`), then by the first line (shebangs,
+/// `<?xml`, modelines), then by a table of known names. Plain text is the fallback.
+fn detect_syntax(path: &std::path::Path, content: &str) -> &'static SyntaxReference {
     let ps = syntax_set();
-    let ts = theme_set();
-    let syntax = path
-        .extension()
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    path.extension()
         .and_then(|e| e.to_str())
         .and_then(|ext| ps.find_syntax_by_token(ext))
-        .unwrap_or_else(|| ps.find_syntax_plain_text());
-    let theme = ts
-        .themes
-        .get("base16-eighties.dark")
-        .or_else(|| ts.themes.values().next())
-        .expect("theme set has at least one theme");
-    let mut highlighter = HighlightLines::new(syntax, theme);
+        .or_else(|| ps.find_syntax_by_extension(file_name))
+        .or_else(|| ps.find_syntax_by_first_line(content.lines().next().unwrap_or("")))
+        .or_else(|| {
+            KNOWN_FILE_NAMES
+                .iter()
+                .find(|(name, _)| *name == file_name)
+                .and_then(|(_, token)| ps.find_syntax_by_token(token))
+        })
+        .unwrap_or_else(|| ps.find_syntax_plain_text())
+}
 
-    for line_with_ending in LinesWithEndings::from(&content_str) {
-        let line_spans: Vec<Span> = match highlighter.highlight_line(line_with_ending, ps) {
-            Ok(segments) => segments
-                .into_iter()
-                .map(|(syntect_style, text)| {
-                    let mut style = Style::default();
-                    if let Some(c) = syntect_color_to_ratatui(syntect_style.foreground) {
-                        style = style.fg(c);
-                    }
-                    let mods = syntect_font_style_to_modifier(syntect_style.font_style);
-                    if !mods.is_empty() {
-                        style = style.add_modifier(mods);
-                    }
-                    Span::styled(text.to_string(), style)
-                })
-                .collect(),
-            Err(_) => vec![Span::raw(line_with_ending.to_string())],
-        };
-        out.push(Line::from(line_spans));
-    }
+/// The furthest the preview can scroll: far enough that the last of `total_rows` sits on the
+/// bottom row of a panel `inner_height` rows tall, so the last page is always a full one.
+fn preview_scroll_limit(total_rows: usize, inner_height: usize) -> usize {
+    total_rows.saturating_sub(inner_height)
+}
 
-    (out, truncated)
+/// Render bytes as classic `xxd`-style rows: offset, 16 hex bytes, and an ASCII gutter
+/// where non-printable bytes show as a dim dot.
+fn hex_dump_lines(bytes: &[u8]) -> Vec<Line<'static>> {
+    const BYTES_PER_ROW: usize = 16;
+    let dim = Style::default().fg(Color::DarkGray);
+    bytes
+        .chunks(BYTES_PER_ROW)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let mut spans = vec![Span::styled(format!("{:08x}: ", row * BYTES_PER_ROW), dim)];
+            let mut hex = String::with_capacity(BYTES_PER_ROW * 3 + 1);
+            for i in 0..BYTES_PER_ROW {
+                match chunk.get(i) {
+                    Some(b) => hex.push_str(&format!("{:02x} ", b)),
+                    None => hex.push_str("   "),
+                }
+                if i == BYTES_PER_ROW / 2 - 1 {
+                    hex.push(' ');
+                }
+            }
+            spans.push(Span::styled(hex, Style::default().fg(Color::Cyan)));
+            spans.push(Span::raw(" "));
+            for &b in chunk {
+                if b.is_ascii_graphic() || b == b' ' {
+                    spans.push(Span::raw((b as char).to_string()));
+                } else {
+                    spans.push(Span::styled(".", dim));
+                }
+            }
+            Line::from(spans)
+        })
+        .collect()
 }
 
 /// Load and render markdown file as styled lines.
 /// Returns (lines, truncated) where truncated is true if the file was larger than the limit.
-fn load_markdown_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
+fn load_markdown_preview(path: &std::path::Path, theme: &Theme, limit: usize) -> (Vec<Line<'static>>, bool) {
     let mut out: Vec<Line<'static>> = Vec::new();
 
-    const MAX_PREVIEW_BYTES: usize = 512 * 1024;
-    let content = match fs::read(path) {
-        Ok(b) => b,
+    if let Some(problem) = symlink_problem(path) {
+        out.push(plain_line(problem));
+        return (out, false);
+    }
+
+    let (content, truncated) = match read_prefix(path, limit) {
+        Ok(read) => read,
         Err(e) => {
             out.push(plain_line(format!("Error reading: {}", e)));
             return (out, false);
@@ -386,12 +6358,7 @@ fn load_markdown_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
         return (out, false);
     }
     
-    let truncated = content.len() > MAX_PREVIEW_BYTES;
-    let text = if truncated {
-        &content[..MAX_PREVIEW_BYTES]
-    } else {
-        content.as_slice()
-    };
+    let text = content.as_slice();
     
     let content_str = match String::from_utf8(text.to_vec()) {
         Ok(s) => s,
@@ -408,11 +6375,14 @@ fn load_markdown_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
     let parser = Parser::new_ext(&content_str, options);
 
     let mut current_line_spans: Vec<Span<'static>> = Vec::new();
-    let mut list_depth: usize = 0;
+    // One entry per open list: the next item number for ordered lists, None for bullet lists.
+    let mut list_stack: Vec<Option<u64>> = Vec::new();
     let mut in_code_block = false;
     let mut code_block_lang: Option<String> = None;
     let mut code_block_content = String::new();
-    let mut current_style = Style::default();
+    // Inline styles nest (e.g. bold inside a heading, italic inside bold), so each opening tag
+    // pushes its style on top of the enclosing one and the closing tag pops it again.
+    let mut style_stack: Vec<Style> = vec![Style::default()];
     let mut in_blockquote = false;
 
     for event in parser {
@@ -429,19 +6399,17 @@ fn load_markdown_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
                         out.push(plain_line(""));
                     }
                     // Style based on heading level
-                    current_style = match level {
+                    style_stack.push(match level {
                         pulldown_cmark::HeadingLevel::H1 => Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
                         pulldown_cmark::HeadingLevel::H2 => Style::default().fg(Color::Blue).add_modifier(Modifier::BOLD),
                         pulldown_cmark::HeadingLevel::H3 => Style::default().fg(Color::Green).add_modifier(Modifier::BOLD),
                         _ => Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
-                    };
+                    });
                 }
-                Tag::Paragraph => {
-                    // Add spacing before paragraph (unless it's the first element)
-                    if !out.is_empty() && !current_line_spans.is_empty() {
-                        out.push(Line::from(current_line_spans.clone()));
-                        current_line_spans.clear();
-                    }
+                // Add spacing before paragraph (unless it's the first element)
+                Tag::Paragraph if !out.is_empty() && !current_line_spans.is_empty() => {
+                    out.push(Line::from(current_line_spans.clone()));
+                    current_line_spans.clear();
                 }
                 Tag::CodeBlock(kind) => {
                     in_code_block = true;
@@ -459,8 +6427,8 @@ fn load_markdown_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
                         out.push(plain_line(""));
                     }
                 }
-                Tag::List(_) => {
-                    list_depth += 1;
+                Tag::List(start) => {
+                    list_stack.push(start);
                     if !current_line_spans.is_empty() {
                         out.push(Line::from(current_line_spans.clone()));
                         current_line_spans.clear();
@@ -471,21 +6439,28 @@ fn load_markdown_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
                         out.push(Line::from(current_line_spans.clone()));
                         current_line_spans.clear();
                     }
-                    // Add indentation and bullet
-                    let indent = "  ".repeat(list_depth.saturating_sub(1));
+                    // Add indentation and bullet (or the item number for ordered lists)
+                    let indent = "  ".repeat(list_stack.len().saturating_sub(1));
+                    let marker = match list_stack.last_mut() {
+                        Some(Some(n)) => {
+                            *n += 1;
+                            format!("{}. ", *n - 1)
+                        }
+                        _ => "• ".to_string(),
+                    };
                     current_line_spans.push(Span::styled(
-                        format!("{}• ", indent),
+                        format!("{}{}", indent, marker),
                         Style::default().fg(Color::Yellow),
                     ));
                 }
-                Tag::Emphasis => {
-                    current_style = current_style.add_modifier(Modifier::ITALIC);
-                }
-                Tag::Strong => {
-                    current_style = current_style.add_modifier(Modifier::BOLD);
-                }
-                Tag::Strikethrough => {
-                    current_style = current_style.add_modifier(Modifier::CROSSED_OUT);
+                Tag::Emphasis | Tag::Strong | Tag::Strikethrough => {
+                    let modifier = match tag {
+                        Tag::Emphasis => Modifier::ITALIC,
+                        Tag::Strong => Modifier::BOLD,
+                        _ => Modifier::CROSSED_OUT,
+                    };
+                    let top = style_stack.last().copied().unwrap_or_default();
+                    style_stack.push(top.add_modifier(modifier));
                 }
                 Tag::BlockQuote(_) => {
                     if !current_line_spans.is_empty() {
@@ -504,7 +6479,10 @@ fn load_markdown_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
                         current_line_spans.clear();
                     }
                     out.push(plain_line(""));
-                    current_style = Style::default();
+                    // Never pop the base style, even if the event stream is unbalanced.
+                    if style_stack.len() > 1 {
+                        style_stack.pop();
+                    }
                 }
                 TagEnd::Paragraph => {
                     if !current_line_spans.is_empty() {
@@ -518,15 +6496,9 @@ fn load_markdown_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
                     // Try syntax highlighting if language is known
                     if let Some(lang) = code_block_lang.as_ref() {
                         let ps = syntax_set();
-                        let ts = theme_set();
                         let syntax = ps
                             .find_syntax_by_token(lang)
                             .unwrap_or_else(|| ps.find_syntax_plain_text());
-                        let theme = ts
-                            .themes
-                            .get("base16-eighties.dark")
-                            .or_else(|| ts.themes.values().next())
-                            .expect("theme set has at least one theme");
                         let mut highlighter = HighlightLines::new(syntax, theme);
                         
                         for line in LinesWithEndings::from(&code_block_content) {
@@ -563,20 +6535,22 @@ fn load_markdown_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
                     out.push(plain_line(""));
                 }
                 TagEnd::List(_) => {
-                    list_depth = list_depth.saturating_sub(1);
+                    list_stack.pop();
                     if !current_line_spans.is_empty() {
                         out.push(Line::from(current_line_spans.clone()));
                         current_line_spans.clear();
                     }
-                }
-                TagEnd::Item => {
-                    if !current_line_spans.is_empty() {
-                        out.push(Line::from(current_line_spans.clone()));
-                        current_line_spans.clear();
+                    // Separate a finished top-level list from what follows.
+                    if list_stack.is_empty() {
+                        out.push(plain_line(""));
                     }
                 }
-                TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => {
-                    current_style = Style::default();
+                TagEnd::Item if !current_line_spans.is_empty() => {
+                    out.push(Line::from(current_line_spans.clone()));
+                    current_line_spans.clear();
+                }
+                TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough if style_stack.len() > 1 => {
+                    style_stack.pop();
                 }
                 TagEnd::BlockQuote(_) => {
                     if !current_line_spans.is_empty() {
@@ -598,7 +6572,8 @@ fn load_markdown_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
                             Style::default().fg(Color::DarkGray),
                         ));
                     }
-                    current_line_spans.push(Span::styled(text.to_string(), current_style));
+                    let style = style_stack.last().copied().unwrap_or_default();
+                    current_line_spans.push(Span::styled(text.to_string(), style));
                 }
             }
             MdEvent::Code(code) => {
@@ -643,128 +6618,878 @@ fn load_markdown_preview(path: &std::path::Path) -> (Vec<Line<'static>>, bool) {
         }
     }
 
-    // Flush any remaining spans
-    if !current_line_spans.is_empty() {
-        out.push(Line::from(current_line_spans));
+    // Flush any remaining spans
+    if !current_line_spans.is_empty() {
+        out.push(Line::from(current_line_spans));
+    }
+
+    (out, truncated)
+}
+
+/// Draw the full UI into the given frame. This is called every frame after handling input.
+fn ui(frame: &mut Frame, app: &mut App) {
+    let area = frame.area();
+
+    // Vertical layout: [path bar] [list] [details of selected entry] [peek, when on] [hints]
+    let chunks = Layout::default()
+        .direction(ratatui::layout::Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Length(1),
+            Constraint::Length(if app.show_peek { PEEK_LINES as u16 } else { 0 }),
+            Constraint::Length(3),
+        ])
+        .split(area);
+
+    // ---- Path bar ----
+    let mut path_tags: Vec<String> = Vec::new();
+    if app.show_hidden {
+        path_tags.push("hidden".to_string());
+    }
+    if app.respect_gitignore {
+        path_tags.push("gitignore".to_string());
+    }
+    if app.pane.archive.is_some() {
+        path_tags.push("archive (read-only)".to_string());
+    }
+    if app.recent_first {
+        path_tags.push("recent".to_string());
+    } else if app.sort_mode != SortMode::Name || app.sort_reverse {
+        // Arrow shows the direction relative to the mode's natural order.
+        let arrow = if app.sort_reverse { "↑" } else { "↓" };
+        path_tags.push(format!("{} {}", app.sort_mode.label(), arrow));
+    }
+    if !app.group_dirs_first && !app.recent_first {
+        path_tags.push("dirs mixed".to_string());
+    }
+    if app.case_sensitive_sort {
+        path_tags.push("case-sensitive".to_string());
+    }
+    // A reminder that there's more here when dotfiles aren't shown.
+    let path_label = match app.pane.hidden_count {
+        0 => " Path".to_string(),
+        count => format!(" Path ({} hidden)", count),
+    };
+    let path_title: String = std::iter::once(path_label)
+        .chain(path_tags.iter().map(|t| format!(" • {}", t)))
+        .chain(std::iter::once(" ".to_string()))
+        .collect();
+    let mut path_block = Block::default().borders(Borders::ALL).title(path_title);
+    if let Some((available, total)) = app.pane.disk_space {
+        path_block = path_block.title(
+            Line::from(format!(" {} free / {} ", format_size(available), format_size(total))).right_aligned(),
+        );
+    }
+    let path_line = breadcrumb_line(&app.pane.cwd, chunks[0].width.saturating_sub(2) as usize);
+    let fullscreen = app.preview_fullscreen && app.preview_path.is_some();
+    if !fullscreen {
+        frame.render_widget(Paragraph::new(path_line).block(path_block), chunks[0]);
+    }
+
+    // ---- Middle: list only, or list | preview, or both panes with the preview (if open) in
+    // place of the unfocused one. A full-screen preview covers everything above the key hints. ----
+    let preview_chunk = if fullscreen {
+        app.other_list_area = None;
+        app.list_area = Rect::default();
+        Some(Rect { height: area.height.saturating_sub(chunks[4].height), ..area })
+    } else {
+        let (list_chunk, side_chunk) = if app.preview_path.is_some() || app.other_pane.is_some() {
+            // Two panes split evenly; the preview takes its configured share.
+            let side = if app.preview_path.is_some() { app.preview_ratio } else { 50 };
+            let shares = if app.pane_is_right { [side, 100 - side] } else { [100 - side, side] };
+            let horz = Layout::default()
+                .direction(ratatui::layout::Direction::Horizontal)
+                .constraints(shares.map(Constraint::Percentage))
+                .split(chunks[1]);
+            if app.pane_is_right { (horz[1], Some(horz[0])) } else { (horz[0], Some(horz[1])) }
+        } else {
+            (chunks[1], None)
+        };
+        let preview_chunk = side_chunk.filter(|_| app.preview_path.is_some());
+
+        let list_height = render_entry_list(frame, app, list_chunk, true);
+        if let (Some(rect), None) = (side_chunk, preview_chunk) {
+            app.with_other_pane(|app| render_entry_list(frame, app, rect, false));
+        }
+        app.other_list_area = side_chunk.filter(|_| preview_chunk.is_none() && app.other_pane.is_some());
+        app.list_area = list_chunk;
+        app.list_viewport_height = list_height;
+        preview_chunk
+    };
+    app.preview_area = preview_chunk;
+
+    if let (Some(rect), Some(load)) = (preview_chunk, app.preview_load.as_ref()) {
+        let name = app.preview_path.as_deref().map(display_name).unwrap_or_default();
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Yellow))
+            .title(format!(" {} ", name));
+        let line = Line::from(Span::styled(
+            format!("{} Loading preview…", spinner_frame(load.started)),
+            Style::default().fg(Color::DarkGray),
+        ));
+        app.preview_viewport_height = rect.height.saturating_sub(2) as usize;
+        frame.render_widget(Paragraph::new(line).block(block), rect);
+    } else if let (Some(rect), Some(content)) = (preview_chunk, app.preview_content.as_ref()) {
+        let base_title = app
+            .preview_path
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "Preview".to_string());
+        let base_title = match app.preview_link.as_ref() {
+            Some(target) => format!("{} -> {}", base_title, target.display()),
+            None => base_title,
+        };
+        
+        let mode_indicator = match app.preview_mode {
+            PreviewMode::Rendered if app.preview_path.as_deref().is_some_and(is_json_path) => " [pretty]",
+            PreviewMode::Rendered if app.preview_path.as_deref().and_then(csv_delimiter).is_some() => " [table]",
+            PreviewMode::Rendered => " [rendered]",
+            PreviewMode::Raw => "",
+        };
+        let wrap_indicator = if app.preview_wrap { "" } else { " [nowrap]" };
+        let wrap_indicator = if app.preview_follow.is_some() { format!("{} [follow]", wrap_indicator) } else { wrap_indicator.to_string() };
+        
+        let base_title = match app.preview_encoding {
+            Some(encoding) => format!("{} [{}]", base_title, encoding),
+            None => base_title,
+        };
+        let base_title = if app.preview_lines_cut {
+            format!("{} [long lines cut at {}]", base_title, app.max_line_length)
+        } else {
+            base_title
+        };
+        let title = if app.preview_truncated {
+            format!(
+                " {}{}{} ({} {}) ",
+                base_title,
+                mode_indicator,
+                wrap_indicator,
+                if app.preview_tail { "last" } else { "first" },
+                format_preview_limit(app.preview_limit)
+            )
+        } else {
+            format!(" {}{}{} ", base_title, mode_indicator, wrap_indicator)
+        };
+        // Account for borders (top + bottom = 2 lines) when calculating scroll range
+        let inner_height = rect.height.saturating_sub(2) as usize;
+        app.preview_viewport_height = inner_height;
+        let inner_width = rect.width.saturating_sub(2);
+        // When wrapping, long lines take several rows, so the scroll range is based on the
+        // number of visual rows rather than the number of lines.
+        let total_rows = if app.preview_wrap {
+            match app.preview_wrapped_rows {
+                Some((width, rows)) if width == inner_width => rows,
+                _ => {
+                    let rows = Paragraph::new(Text::from(content.clone()))
+                        .wrap(Wrap { trim: false })
+                        .line_count(inner_width);
+                    app.preview_wrapped_rows = Some((inner_width, rows));
+                    rows
+                }
+            }
+        } else {
+            content.len()
+        };
+        let scroll_max = preview_scroll_limit(total_rows, inner_height);
+        app.preview_scroll_max = scroll_max;
+        // Write the clamp back so a resize that grows the viewport doesn't leave a stale offset
+        // that the next few k presses would have to work off before anything moves.
+        app.preview_scroll = app.preview_scroll.min(scroll_max);
+        let scroll = app.preview_scroll;
+        // Horizontal scroll stops once the longest line's end is in view.
+        let longest = content.iter().map(Line::width).max().unwrap_or(0);
+        app.preview_hscroll_max = longest.saturating_sub(inner_width as usize);
+        let hscroll = if app.preview_wrap { 0 } else { app.preview_hscroll.min(app.preview_hscroll_max) };
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Yellow))
+            .title(title)
+            .title_bottom(Line::from(format!(" {} ", app.theme_name)).right_aligned());
+        if let Some(image) = app.preview_image.as_mut() {
+            // The image is fitted to the panel, so there is nothing to scroll.
+            app.preview_scroll = 0;
+            app.preview_scroll_max = 0;
+            app.preview_hscroll_max = 0;
+            let inner = block.inner(rect);
+            frame.render_widget(block, rect);
+            frame.render_stateful_widget(StatefulImage::default(), inner, image);
+        } else {
+            let mut lines: Vec<Line<'static>> = content.to_vec();
+            // The highlight from `:` goes away as soon as the preview scrolls.
+            app.preview_line_highlight = app.preview_line_highlight.filter(|&(line, at)| at == scroll && line < lines.len());
+            if let Some((line, _)) = app.preview_line_highlight {
+                lines[line] = std::mem::take(&mut lines[line]).patch_style(Style::default().bg(Color::DarkGray));
+            }
+            if let Some(search) = app.preview_search.as_ref() {
+                let len = search.query.chars().count();
+                for (i, &(line, col)) in search.matches.iter().enumerate() {
+                    let bg = if i == search.current { Color::LightYellow } else { Color::DarkGray };
+                    highlight_range(&mut lines[line], col, col + len, bg);
+                }
+            }
+            // Paragraph scrolls by a u16, so past that many rows (the end of a long file) whole
+            // lines are dropped from the front instead.
+            let mut rows_left = scroll;
+            let mut skip = 0;
+            while rows_left > u16::MAX as usize && skip < lines.len() {
+                let rows = if app.preview_wrap {
+                    Paragraph::new(lines[skip].clone()).wrap(Wrap { trim: false }).line_count(inner_width)
+                } else {
+                    1
+                };
+                rows_left = rows_left.saturating_sub(rows);
+                skip += 1;
+            }
+            lines.drain(..skip);
+            let mut para = Paragraph::new(Text::from(lines))
+                .block(block)
+                .scroll((rows_left as u16, hscroll as u16));
+            if app.preview_wrap {
+                para = para.wrap(Wrap { trim: false });
+            }
+            frame.render_widget(para, rect);
+            render_scrollbar(frame, rect, scroll, scroll_max, inner_height);
+        }
+    }
+
+    // ---- Details of the selected entry ----
+    if !fullscreen {
+        frame.render_widget(Paragraph::new(entry_details_line(app)), chunks[2]);
+        if let Some((_, _, lines)) = app.peek.as_ref() {
+            let dim = Style::default().fg(Color::DarkGray);
+            let lines: Vec<Line> = lines.iter().map(|line| Line::styled(format!(" {}", line), dim)).collect();
+            frame.render_widget(Paragraph::new(lines), chunks[3]);
+        }
+    }
+
+    // ---- Key hints (or an open prompt, or the last status message) ----
+    let (bottom_line, bottom_title) = if let Mode::Input(kind) = app.mode {
+        let mut line = input_prompt_line(&app.input);
+        if kind == InputKind::PreviewSearch {
+            let case = if app.preview_search_case_sensitive { "on" } else { "off" };
+            line.push_span(Span::raw("  "));
+            line.push_span(Span::styled(" Tab ", Style::default().fg(Color::DarkGray)));
+            line.push_span(Span::raw(format!("match case: {}", case)));
+        }
+        if kind == InputKind::Rename
+            && let Some((_, to, true)) = app.rename_target(&app.input)
+        {
+            line.push_span(Span::styled(
+                format!("  {} already exists and would be replaced", display_name(&to)),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ));
+        }
+        (line, kind.title())
+    } else if let Some(query) = app.search_query.as_ref() {
+        (search_prompt_line(app, query), " Search ")
+    } else if app.pending_key == Some('m') {
+        (
+            Line::from(vec![
+                Span::raw(" Press a letter to bookmark this directory  "),
+                Span::styled(" Esc ", Style::default().fg(Color::DarkGray)),
+                Span::raw("cancel"),
+            ]),
+            " Bookmark ",
+        )
+    } else if let Some(status) = app.status.as_ref() {
+        (
+            Line::from(Span::styled(format!(" {}", status.text), Style::default().fg(status.color()))),
+            " Status ",
+        )
+    } else {
+        (key_hints_line(app), " Keys ")
+    };
+    let mut hint_block = Block::default().borders(Borders::ALL).title(bottom_title);
+    if let Some(count) = app.count {
+        hint_block = hint_block.title(
+            Line::from(Span::styled(format!(" {} ", count), Style::default().fg(Color::Yellow))).right_aligned(),
+        );
+    }
+    if app.show_clock {
+        hint_block = hint_block.title(Line::from(chrono::Local::now().format(" %H:%M ").to_string()).right_aligned());
+    }
+    let hint_para = Paragraph::new(bottom_line).block(hint_block);
+    frame.render_widget(hint_para, chunks[4]);
+
+    // ---- Modal overlays ----
+    if app.pending_key == Some('\'') {
+        let mut letters: Vec<&char> = app.bookmarks.keys().collect();
+        letters.sort();
+        let mut lines: Vec<Line<'static>> = letters
+            .into_iter()
+            .map(|c| {
+                Line::from(vec![
+                    Span::styled(format!(" {}  ", c), Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+                    Span::raw(app.bookmarks[c].to_string_lossy().into_owned()),
+                ])
+            })
+            .collect();
+        if lines.is_empty() {
+            lines.push(Line::from(Span::styled(
+                " No bookmarks yet — press m and a letter to add one",
+                Style::default().fg(Color::DarkGray),
+            )));
+        }
+        let popup = centered_rect(area, 60, lines.len() as u16 + 2);
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Yellow))
+            .title(" Bookmarks — press a letter to jump ");
+        frame.render_widget(Clear, popup);
+        frame.render_widget(Paragraph::new(lines).block(block), popup);
+    }
+    if let Some(output) = app.command_output.as_mut() {
+        let rect = chunks[1];
+        output.scroll_max = output.lines.len().saturating_sub(rect.height.saturating_sub(2) as usize);
+        output.scroll = output.scroll.min(output.scroll_max);
+        let (status, status_color) = match output.exit_code {
+            Some(0) => ("exit 0".to_string(), Color::Green),
+            Some(code) => (format!("exit {}", code), Color::Red),
+            None => ("terminated".to_string(), Color::Red),
+        };
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(status_color))
+            .title(format!(" $ {} ", output.command))
+            .title(Line::from(format!(" {} ", status)).right_aligned())
+            .title_bottom(Line::from(" j/k scroll • any other key closes ").right_aligned());
+        let body = if output.lines.is_empty() {
+            vec![Line::from(Span::styled("(no output)", Style::default().fg(Color::DarkGray)))]
+        } else {
+            output.lines.clone()
+        };
+        frame.render_widget(Clear, rect);
+        frame.render_widget(Paragraph::new(body).block(block).scroll((output.scroll as u16, 0)), rect);
+        let viewport = rect.height.saturating_sub(2) as usize;
+        render_scrollbar(frame, rect, output.scroll, output.scroll_max, viewport);
+    }
+    if let Some(finder) = app.finder.as_mut() {
+        render_finder(frame, area, finder);
+    }
+    if let Some(scroll) = app.help_scroll {
+        let lines = help_lines(&app.keymap);
+        let popup = centered_rect(area, 80, (lines.len() as u16 + 2).min(area.height));
+        app.help_scroll_max = lines.len().saturating_sub(popup.height.saturating_sub(2) as usize);
+        let scroll = scroll.min(app.help_scroll_max);
+        app.help_scroll = Some(scroll);
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Yellow))
+            .title(" Help ")
+            .title_bottom(Line::from(" j/k scroll • Esc or ? closes ").right_aligned());
+        frame.render_widget(Clear, popup);
+        frame.render_widget(Paragraph::new(lines).block(block).scroll((scroll as u16, 0)), popup);
+        render_scrollbar(frame, popup, scroll, app.help_scroll_max, popup.height.saturating_sub(2) as usize);
+    }
+    if let Mode::ConfirmDelete(paths, mode) = &app.mode {
+        let (what, name) = match paths.as_slice() {
+            [path] => {
+                let kind = if path.is_dir() { "directory" } else { "file" };
+                (kind.to_string(), display_name(path))
+            }
+            _ => ("marked".to_string(), format!("{} entries", paths.len())),
+        };
+        let name = Span::styled(name, Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD));
+        // Permanent deletion is spelled out in red, and says why when the trash was wanted.
+        if *mode == DeleteMode::Trash {
+            let question = Line::from(vec![Span::raw(format!(" Move {} ", what)), name, Span::raw(" to the trash?")]);
+            render_confirm_popup(frame, area, " Confirm delete ", question, &[("y", "move to trash")]);
+        } else {
+            let mut question = vec![
+                Span::raw(" "),
+                Span::styled("Permanently", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+                Span::raw(format!(" delete {} ", what)),
+                name,
+                Span::raw("?"),
+            ];
+            if !TRASH_SUPPORTED {
+                question.push(Span::styled(" (no trash here)", Style::default().fg(Color::DarkGray)));
+            }
+            let choices = [("y", "delete forever")];
+            render_confirm_popup(frame, area, " Delete permanently ", Line::from(question), &choices);
+        }
+    }
+    if let Mode::ConfirmOverwrite(existing) = &app.mode {
+        let (name, verb) = match existing.as_slice() {
+            [path] => (display_name(path), "exists"),
+            _ => (format!("{} entries", existing.len()), "exist"),
+        };
+        let question = Line::from(vec![
+            Span::raw(" "),
+            Span::styled(name, Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+            Span::raw(format!(" already {} here.", verb)),
+        ]);
+        render_confirm_popup(frame, area, " Paste ", question, &[("y", "overwrite"), ("k", "keep both")]);
+    }
+    if let Mode::ConfirmRename(from, to) = &app.mode {
+        let bold = Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+        let question = Line::from(vec![
+            Span::raw(" Rename "),
+            Span::styled(display_name(from), bold),
+            Span::raw(" and replace the existing "),
+            Span::styled(display_name(to), bold),
+            Span::raw("?"),
+        ]);
+        render_confirm_popup(frame, area, " Confirm rename ", question, &[("y", "replace")]);
+    }
+    if let Mode::ConfirmBatchRename(pairs) = &app.mode {
+        render_batch_rename_popup(frame, area, pairs);
+    }
+    if app.mode == Mode::ConfirmQuit {
+        render_confirm_popup(frame, area, " Confirm quit ", plain_line(" Quit dirnav?"), &[("y", "quit")]);
+    }
+}
+
+/// `path` as a breadcrumb: dimmed ancestors, then the current directory highlighted. When it is
+/// wider than `width`, directories after the root are collapsed into "…" until it fits, always
+/// keeping the root and the last two components.
+fn breadcrumb_line(path: &std::path::Path, width: usize) -> Line<'static> {
+    use std::path::Component;
+
+    let mut root = String::new();
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => root.push_str(&prefix.as_os_str().to_string_lossy()),
+            Component::RootDir => root.push_str(std::path::MAIN_SEPARATOR_STR),
+            other => names.push(other.as_os_str().to_string_lossy().into_owned()),
+        }
+    }
+    let ancestor = Style::default().fg(Color::Cyan).add_modifier(Modifier::DIM);
+    let current = Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD);
+    let separator = Style::default().fg(Color::DarkGray);
+    let build = |skipped: usize| {
+        let root_style = if names.is_empty() { current } else { ancestor };
+        let mut spans = vec![Span::raw(" "), Span::styled(root.clone(), root_style)];
+        let mut parts: Vec<Span<'static>> = Vec::new();
+        if skipped > 0 {
+            parts.push(Span::styled("…", separator));
+        }
+        for (i, name) in names.iter().enumerate().skip(skipped) {
+            let style = if i + 1 == names.len() { current } else { ancestor };
+            parts.push(Span::styled(name.clone(), style));
+        }
+        for (i, part) in parts.into_iter().enumerate() {
+            if i > 0 {
+                spans.push(Span::styled(std::path::MAIN_SEPARATOR_STR, separator));
+            }
+            spans.push(part);
+        }
+        Line::from(spans)
+    };
+    let mut skipped = 0;
+    let mut line = build(skipped);
+    while line.width() > width && names.len() - skipped > 2 {
+        skipped += 1;
+        line = build(skipped);
+    }
+    line
+}
+
+/// A small yes/no popup, as used for the delete and quit confirmations.
+fn render_confirm_popup(frame: &mut Frame, area: Rect, title: &str, question: Line<'static>, choices: &[(&str, &str)]) {
+    let mut options = Vec::new();
+    for (key, label) in choices.iter().chain(&[("n/Esc", "cancel")]) {
+        options.push(Span::styled(format!(" {} ", key), Style::default().fg(Color::DarkGray)));
+        options.push(Span::raw(format!("{}  ", label)));
+    }
+    let lines = vec![plain_line(""), question, plain_line(""), Line::from(options)];
+    let popup = centered_rect(area, 50, 6);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Red))
+        .title(title.to_string());
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(lines).block(block), popup);
+}
+
+/// The batch rename confirmation: every old name beside its new one, as many as fit, then y/n.
+fn render_batch_rename_popup(frame: &mut Frame, area: Rect, pairs: &[(PathBuf, PathBuf)]) {
+    let room = area.height.saturating_sub(8) as usize;
+    let shown = if pairs.len() > room { room.saturating_sub(1) } else { pairs.len() };
+    let old_width = pairs.iter().take(shown).map(|(from, _)| display_name(from).width()).max().unwrap_or(0);
+    let new_style = Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+    let mut lines = vec![plain_line("")];
+    for (from, to) in &pairs[..shown] {
+        lines.push(Line::from(vec![
+            Span::raw(format!(" {:<old_width$}", display_name(from))),
+            Span::styled(" → ", Style::default().fg(Color::DarkGray)),
+            Span::styled(display_name(to), new_style),
+        ]));
+    }
+    if shown < pairs.len() {
+        lines.push(Line::from(Span::styled(
+            format!(" … and {} more", pairs.len() - shown),
+            Style::default().fg(Color::DarkGray),
+        )));
+    }
+    lines.push(plain_line(""));
+    lines.push(Line::from(vec![
+        Span::styled(" y ", Style::default().fg(Color::DarkGray)),
+        Span::raw("rename  "),
+        Span::styled(" n/Esc ", Style::default().fg(Color::DarkGray)),
+        Span::raw("cancel  "),
+    ]));
+    let popup = centered_rect(area, 60, lines.len() as u16 + 2);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Red))
+        .title(format!(" Rename {} entries ", pairs.len()));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(lines).block(block), popup);
+}
+
+/// Draw the focused pane's entry list into `list_chunk`; `focused` is false when this is the
+/// unfocused pane of dual-pane mode, swapped in to be drawn. Returns the number of entry rows shown.
+fn render_entry_list(frame: &mut Frame, app: &mut App, list_chunk: Rect, focused: bool) -> usize {
+    // Row layout: [prefix][name ...][git][gap][size][gap][modified]. The size and modified columns
+    // have fixed widths so they line up; the name gets whatever is left (minus the borders). The
+    // git status column only appears when something in the listing has a status.
+    const SIZE_WIDTH: usize = 7;
+    const TIME_WIDTH: usize = 8;
+    const MIN_NAME_WIDTH: usize = 8;
+    let prefix_width = app.icons.prefix(false, false, false).width();
+    let inner_width = list_chunk.width.saturating_sub(2) as usize;
+    let columns_width = 1 + SIZE_WIDTH + 2 + TIME_WIDTH;
+    let show_columns = inner_width >= prefix_width + MIN_NAME_WIDTH + columns_width;
+    let git_width = if app.pane.git_status.is_empty() { 0 } else { 2 };
+    let name_width = if show_columns {
+        inner_width - prefix_width - columns_width
+    } else {
+        inner_width.saturating_sub(prefix_width)
+    }
+    .saturating_sub(git_width);
+
+    // Scroll just enough to keep `scrolloff` entries in view above and below the selection (after
+    // moving towards an edge, or when a resize shrank the list), without leaving blank rows at
+    // the bottom. The margin shrinks at the ends of the list, and in lists too short for it.
+    let list_height = list_chunk.height.saturating_sub(2) as usize;
+    let margin = app.scrolloff.min(list_height.saturating_sub(1) / 2);
+    if app.pane.selected < app.pane.list_offset + margin {
+        app.pane.list_offset = app.pane.selected.saturating_sub(margin);
+    } else if list_height > 0 && app.pane.selected + margin >= app.pane.list_offset + list_height {
+        app.pane.list_offset = app.pane.selected + margin + 1 - list_height;
+    }
+    app.pane.list_offset = app.pane.list_offset.min(app.pane.entries.len().saturating_sub(list_height));
+
+    // Only the visible rows are built; a directory can hold hundreds of thousands of entries.
+    let mut items: Vec<ListItem> = app
+        .pane
+        .entries
+        .iter()
+        .enumerate()
+        .skip(app.pane.list_offset)
+        .take(list_height)
+        .map(|(i, e)| {
+            let expanded = e.is_dir && app.pane.expanded.contains(&e.name);
+            let prefix = app.icons.prefix(e.is_dir, expanded, e.is_symlink);
+            let marked = app.pane.marked.contains(&e.name);
+            let style = if i == app.pane.selected && !focused {
+                Style::default().fg(Color::Yellow)
+            } else if i == app.pane.selected {
+                Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+            } else if marked {
+                Style::default().fg(Color::Magenta)
+            } else if e.is_symlink {
+                Style::default().fg(Color::Cyan)
+            } else if e.is_executable() {
+                Style::default().fg(Color::Green)
+            } else if let Some(&color) = std::path::Path::new(e.file_name())
+                .extension()
+                .filter(|_| !e.is_dir)
+                .and_then(|ext| app.file_colors.get(&ext.to_string_lossy().to_lowercase()))
+            {
+                Style::default().fg(color)
+            } else {
+                Style::default()
+            };
+            let mark = if marked { "*" } else { "" };
+            let kind = if e.is_executable() {
+                "*".to_string()
+            } else if e.is_dir {
+                app.icons.dir_suffix().to_string()
+            } else {
+                String::new()
+            };
+            let kind = match app.dir_counts.as_ref() {
+                Some(counts) if e.is_dir && e.name != ".." && app.pane.archive.is_none() => {
+                    format!("{} ({})", kind, counts.label(&app.pane.cwd.join(&e.name)))
+                }
+                _ => kind,
+            };
+            let label = format!("{}{}{}", mark, e.file_name(), kind);
+            let target = e.link_target.as_ref().map(|t| format!(" -> {}", t.display())).unwrap_or_default();
+            // Tree children are indented under their directory, narrowing the name column.
+            let indent = "  ".repeat(e.depth);
+            let name_width = name_width.saturating_sub(indent.len());
+            let git = match app.pane.git_status.get(&e.name).map(|status| status.glyph()) {
+                Some((glyph, color)) => Span::styled(format!(" {}", glyph), Style::default().fg(color)),
+                None => Span::raw(" ".repeat(git_width)),
+            };
+            // Long names lose their middle so the extension stays visible; a link target is
+            // shortened (from the end) before the name is.
+            let name = if label.width() + target.width() <= name_width {
+                format!("{}{}", label, target)
+            } else if !target.is_empty() && label.width() + 5 <= name_width {
+                format!("{}{}", label, truncate_with_ellipsis(&target, name_width - label.width()))
+            } else {
+                truncate_middle(&label, name_width)
+            };
+            let padding = " ".repeat(name_width - name.width());
+            if !show_columns {
+                return ListItem::new(Line::from(vec![
+                    Span::styled(format!("{}{}{}{}", indent, prefix, name, padding), style),
+                    git,
+                ]));
+            }
+            let size = if e.is_dir { "-".to_string() } else { format_size(e.size) };
+            let modified = e.modified.map(format_relative_time).unwrap_or_default();
+            let meta_style = if i == app.pane.selected { style } else { Style::default().fg(Color::DarkGray) };
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{}{}{}{}", indent, prefix, name, padding), style),
+                git,
+                Span::styled(
+                    format!(" {:>size_w$}  {:>time_w$}", size, modified, size_w = SIZE_WIDTH, time_w = TIME_WIDTH),
+                    meta_style,
+                ),
+            ]))
+        })
+        .collect();
+
+    // Explain an unreadable or empty directory below the (non-selectable) entries.
+    if let Some(error) = app.pane.list_error.as_ref() {
+        let hint = if app.pane.cwd.parent().is_some() { " (h or .. goes up)" } else { "" };
+        items.push(ListItem::new(Span::styled(format!("   {}{}", error, hint), Style::default().fg(Color::Red))));
+    } else if app.pane.dir_load.is_none() && app.pane.entries.iter().all(|e| e.name == "..") {
+        let note = if app.filter.is_some() { "   (nothing matches the filter)" } else { "   (empty directory)" };
+        items.push(ListItem::new(Span::styled(note, Style::default().fg(Color::DarkGray))));
+    }
+
+    let list_title = if app.pane.entries.is_empty() {
+        " Entries (0) ".to_string()
+    } else {
+        let marked = if app.pane.marked.is_empty() { String::new() } else { format!(" • {} marked", app.pane.marked.len()) };
+        format!(" Entries ({}/{}){} ", app.pane.selected + 1, app.pane.entries.len(), marked)
+    };
+    let list_title = match app.filter.as_ref() {
+        Some(pattern) => format!("{}• filter: {} ", list_title, pattern),
+        None => list_title,
+    };
+    let list_title = if app.pane.dir_load.is_some() { format!("{}• Loading… ", list_title) } else { list_title };
+    let mut block = Block::default().borders(Borders::ALL).title(list_title);
+    if app.other_pane.is_some() || !focused {
+        // Dual panes: say where each one is, and dim the one keys don't go to.
+        let width = list_chunk.width.saturating_sub(4) as usize;
+        block = block.title_bottom(format!(" {} ", truncate_middle(&app.pane.cwd.display().to_string(), width)));
+        if !focused {
+            block = block.border_style(Style::default().fg(Color::DarkGray));
+        }
+    }
+    frame.render_widget(List::new(items).block(block), list_chunk);
+    let list_scroll_max = app.pane.entries.len().saturating_sub(list_height);
+    render_scrollbar(frame, list_chunk, app.pane.list_offset, list_scroll_max, list_height);
+    list_height
+}
+
+/// Draw a scrollbar over the right border of the bordered panel `area`, whose content is scrolled
+/// `offset` rows down out of `scroll_max`, with `viewport` rows visible at a time. Nothing is
+/// drawn when the content fits.
+fn render_scrollbar(frame: &mut Frame, area: Rect, offset: usize, scroll_max: usize, viewport: usize) {
+    if scroll_max == 0 {
+        return;
     }
-
-    (out, truncated)
+    // Positions run over the scroll offsets, so the thumb reaches the bottom with the last row.
+    let mut state = ScrollbarState::new(scroll_max + 1).position(offset).viewport_content_length(viewport);
+    let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
+        .begin_symbol(None)
+        .end_symbol(None)
+        .thumb_style(Style::default().fg(Color::Gray))
+        .track_style(Style::default().fg(Color::DarkGray));
+    frame.render_stateful_widget(scrollbar, area.inner(Margin { vertical: 1, horizontal: 0 }), &mut state);
 }
 
-/// Draw the full UI into the given frame. This is called every frame after handling input.
-fn ui(frame: &mut Frame, app: &mut App) {
-    let area = frame.area();
-
-    // Vertical layout: [path bar] [list] [hints]
-    let chunks = Layout::default()
-        .direction(ratatui::layout::Direction::Vertical)
-        .constraints([
-            Constraint::Length(3),
-            Constraint::Min(5),
-            Constraint::Length(3),
-        ])
-        .split(area);
-
-    // ---- Path bar ----
-    let path_text = app.cwd.to_string_lossy();
-    let path_title = if app.show_hidden {
-        " Path • hidden "
-    } else {
-        " Path "
-    };
-    let path_para = Paragraph::new(path_text.as_ref())
-        .block(Block::default().borders(Borders::ALL).title(path_title))
-        .style(Style::default().fg(Color::Cyan))
-        .wrap(Wrap { trim: true });
-    frame.render_widget(path_para, chunks[0]);
-
-    // ---- Middle: list only, or list | preview ----
-    let (list_chunk, preview_chunk) = if app.preview_path.is_some() {
-        let horz = Layout::default()
-            .direction(ratatui::layout::Direction::Horizontal)
-            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-            .split(chunks[1]);
-        (horz[0], Some(horz[1]))
+/// Draw the fuzzy finder over the whole screen: the query on top, then the results with the
+/// matched characters picked out, best match first.
+fn render_finder(frame: &mut Frame, area: Rect, finder: &mut FuzzyFinder) {
+    let counts = if finder.rx.is_some() {
+        format!(" {}/{} (indexing…) ", finder.matches.len(), finder.paths.len())
+    } else if finder.paths.len() >= FINDER_MAX_FILES {
+        format!(" {}/{} (stopped at {} files) ", finder.matches.len(), finder.paths.len(), FINDER_MAX_FILES)
     } else {
-        (chunks[1], None)
+        format!(" {}/{} ", finder.matches.len(), finder.paths.len())
     };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Yellow))
+        .title(format!(" Find files in {} ", finder.root.display()))
+        .title(Line::from(counts).right_aligned())
+        .title_bottom(Line::from(" Enter go to • Tab preview • ↑/↓ move • Esc cancel ").right_aligned());
+    let inner = block.inner(area);
+    frame.render_widget(Clear, area);
+    frame.render_widget(block, area);
+    let [prompt_area, results_area] = Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(inner);
+    let prompt = Line::from(vec![
+        Span::styled(" > ", Style::default().fg(Color::DarkGray)),
+        Span::styled(finder.query.clone(), Style::default().fg(Color::Yellow)),
+        Span::styled("▏", Style::default().fg(Color::Yellow)),
+    ]);
+    frame.render_widget(Paragraph::new(prompt), prompt_area);
 
-    let items: Vec<ListItem> = app
-        .entries
+    let height = results_area.height as usize;
+    if finder.selected < finder.offset {
+        finder.offset = finder.selected;
+    } else if height > 0 && finder.selected >= finder.offset + height {
+        finder.offset = finder.selected + 1 - height;
+    }
+    // Only the visible results get their matched positions worked out.
+    let matcher = SkimMatcherV2::default().smart_case();
+    let lines: Vec<Line> = finder
+        .matches
         .iter()
         .enumerate()
-        .map(|(i, e)| {
-            let prefix = if e.is_dir { "📁 " } else { "   " };
-            let style = if i == app.selected {
+        .skip(finder.offset)
+        .take(height)
+        .map(|(row, &i)| {
+            let path = &finder.paths[i];
+            let hits: HashSet<usize> = matcher
+                .fuzzy_indices(path, &finder.query)
+                .map(|(_, indices)| indices.into_iter().collect())
+                .unwrap_or_default();
+            let base = if row == finder.selected {
                 Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
             } else {
                 Style::default()
             };
-            ListItem::new(Line::from(Span::styled(
-                format!("{}{}", prefix, e.name),
-                style,
-            )))
+            let mut spans = vec![Span::styled(if row == finder.selected { " > " } else { "   " }, base)];
+            spans.extend(path.chars().enumerate().map(|(c, ch)| {
+                let style = if hits.contains(&c) { base.fg(Color::Green).add_modifier(Modifier::BOLD) } else { base };
+                Span::styled(ch.to_string(), style)
+            }));
+            Line::from(spans)
         })
         .collect();
+    frame.render_widget(Paragraph::new(lines), results_area);
+    // On the border beside the results, not the prompt.
+    let bar_area = Rect { y: prompt_area.y, height: results_area.height + 2, ..area };
+    render_scrollbar(frame, bar_area, finder.offset, finder.matches.len().saturating_sub(height), height);
+}
 
-    let list = List::new(items).block(
-        Block::default()
-            .borders(Borders::ALL)
-            .title(" Entries "),
-    );
-    frame.render_widget(list, list_chunk);
+/// A rect of `percent_x`% of `area`'s width and `height` rows, centered in `area` (for popups).
+fn centered_rect(area: Rect, percent_x: u16, height: u16) -> Rect {
+    let [row] = Layout::vertical([Constraint::Length(height)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [rect] = Layout::horizontal([Constraint::Percentage(percent_x)])
+        .flex(Flex::Center)
+        .areas(row);
+    rect
+}
 
-    if let (Some(rect), Some(ref content)) = (preview_chunk, app.preview_content.as_ref()) {
-        let base_title = app
-            .preview_path
-            .as_ref()
-            .and_then(|p| p.file_name())
-            .map(|n| n.to_string_lossy().into_owned())
-            .unwrap_or_else(|| "Preview".to_string());
-        
-        let mode_indicator = match app.preview_mode {
-            PreviewMode::Rendered => " [rendered]",
-            PreviewMode::Raw => "",
-        };
-        
-        let title = if app.preview_truncated {
-            format!(" {}{} (first 512 KB) ", base_title, mode_indicator)
-        } else {
-            format!(" {}{} ", base_title, mode_indicator)
-        };
-        // Account for borders (top + bottom = 2 lines) when calculating scroll range
-        let inner_height = rect.height.saturating_sub(2) as usize;
-        let scroll_max = content.len().saturating_sub(inner_height);
-        app.preview_scroll_max = scroll_max;
-        let scroll = app.preview_scroll.min(scroll_max);
-        let block = Block::default()
-            .borders(Borders::ALL)
-            .border_style(Style::default().fg(Color::Yellow))
-            .title(title);
-        let lines: Vec<Line<'static>> = content.to_vec();
-        let para = Paragraph::new(Text::from(lines))
-            .block(block)
-            .wrap(Wrap { trim: false })
-            .scroll((scroll as u16, 0));
-        frame.render_widget(para, rect);
+/// One-line summary of the selected entry: full path, size, permissions, modified time, link target.
+/// Everything comes from the metadata cached in `DirEntry`, so nothing is stat'ed per frame.
+fn entry_details_line(app: &App) -> Line<'static> {
+    let dim = Style::default().fg(Color::DarkGray);
+    let Some(entry) = app.pane.entries.get(app.pane.selected) else {
+        return Line::from(Span::styled(" (empty directory)", dim));
+    };
+    let full_path = if entry.name == ".." {
+        app.pane.cwd.parent().map(|p| p.to_path_buf()).unwrap_or_else(|| app.pane.cwd.clone())
+    } else {
+        app.pane.cwd.join(&entry.name)
+    };
+    let sep = || Span::styled("  │  ", dim);
+    let mut spans = vec![Span::raw(format!(" {}", full_path.display()))];
+    if entry.name == ".." {
+        return Line::from(spans);
+    }
+    if !entry.is_dir {
+        spans.push(sep());
+        spans.push(Span::raw(format_size(entry.size)));
+    }
+    spans.push(sep());
+    let hidden = if entry.hidden_attribute { ", hidden" } else { "" };
+    spans.push(Span::raw(match entry.mode {
+        Some(mode) if app.octal_permissions => format!("{:04o}", mode & 0o7777),
+        Some(mode) => symbolic_permissions(mode),
+        None if entry.readonly => format!("read-only{}", hidden),
+        None => format!("read-write{}", hidden),
+    }));
+    if let Some(modified) = entry.modified {
+        let local: chrono::DateTime<chrono::Local> = modified.into();
+        spans.push(sep());
+        spans.push(Span::raw(local.format("%Y-%m-%d %H:%M").to_string()));
+    }
+    if let Some(target) = entry.link_target.as_ref() {
+        spans.push(sep());
+        spans.push(Span::styled(format!("-> {}", target.display()), Style::default().fg(Color::Cyan)));
+    }
+    Line::from(spans)
+}
+
+/// A text prompt shown in place of the key hints while `Mode::Input` is active.
+fn input_prompt_line(input: &str) -> Line<'static> {
+    Line::from(vec![
+        Span::styled(" : ", Style::default().fg(Color::DarkGray)),
+        Span::styled(input.to_string(), Style::default().fg(Color::Yellow)),
+        Span::styled("▏", Style::default().fg(Color::Yellow)),
+        Span::raw("   "),
+        Span::styled(" Enter ", Style::default().fg(Color::DarkGray)),
+        Span::raw("confirm  "),
+        Span::styled(" Esc ", Style::default().fg(Color::DarkGray)),
+        Span::raw("cancel"),
+    ])
+}
+
+/// The incremental search prompt shown in place of the key hints while `/` search is active.
+fn search_prompt_line(app: &App, query: &str) -> Line<'static> {
+    let no_match = !query.is_empty()
+        && !app
+            .pane
+            .entries
+            .iter()
+            .any(|e| e.file_name().to_lowercase().contains(&query.to_lowercase()));
+    let mut spans = vec![
+        Span::styled(" / ", Style::default().fg(Color::DarkGray)),
+        Span::styled(query.to_string(), Style::default().fg(Color::Yellow)),
+        Span::styled("▏", Style::default().fg(Color::Yellow)),
+    ];
+    if no_match {
+        spans.push(Span::styled("  (no match)", Style::default().fg(Color::Red)));
     }
+    spans.push(Span::raw("   "));
+    spans.push(Span::styled(" Enter ", Style::default().fg(Color::DarkGray)));
+    spans.push(Span::raw("keep  "));
+    spans.push(Span::styled(" Esc ", Style::default().fg(Color::DarkGray)));
+    spans.push(Span::raw("cancel"));
+    Line::from(spans)
+}
 
-    // ---- Key hints ----
+/// The key hints line; the preview-specific keys only appear while a preview is open.
+fn key_hints_line(app: &App) -> Line<'static> {
     let mut hint_spans = vec![
+        Span::styled(" ? ", Style::default().fg(Color::DarkGray)),
+        Span::raw("help  "),
         Span::styled(" ↑/↓ ", Style::default().fg(Color::DarkGray)),
         Span::raw("or "),
         Span::styled(" k/j ", Style::default().fg(Color::DarkGray)),
         Span::raw("move  "),
+        Span::styled(" gg/G ", Style::default().fg(Color::DarkGray)),
+        Span::raw("top/bottom  "),
         Span::styled(" Enter/l ", Style::default().fg(Color::DarkGray)),
         Span::raw("open  "),
         Span::styled(" h ", Style::default().fg(Color::DarkGray)),
         Span::raw("up  "),
         Span::styled(" H ", Style::default().fg(Color::DarkGray)),
         Span::raw("toggle hidden  "),
+        Span::styled(" / ", Style::default().fg(Color::DarkGray)),
+        Span::raw("search  "),
     ];
     
-    // Add toggle hint only for markdown files
-    if app.preview_path.is_some() {
+    // Add toggle hint only for markdown and JSON files
+    if app.preview_path.is_some() && !app.preview_auto {
         let is_markdown = app.preview_path
             .as_ref()
             .and_then(|p| p.extension())
@@ -773,10 +7498,24 @@ fn ui(frame: &mut Frame, app: &mut App) {
         if is_markdown {
             hint_spans.push(Span::styled(" t ", Style::default().fg(Color::DarkGray)));
             hint_spans.push(Span::raw("toggle render  "));
+        } else if app.preview_path.as_deref().is_some_and(is_json_path) {
+            hint_spans.push(Span::styled(" t ", Style::default().fg(Color::DarkGray)));
+            hint_spans.push(Span::raw("toggle pretty  "));
+        } else if app.preview_path.as_deref().and_then(csv_delimiter).is_some() {
+            hint_spans.push(Span::styled(" t ", Style::default().fg(Color::DarkGray)));
+            hint_spans.push(Span::raw("toggle table  "));
         }
         
         hint_spans.push(Span::styled(" j/k ", Style::default().fg(Color::DarkGray)));
         hint_spans.push(Span::raw("scroll  "));
+        hint_spans.push(Span::styled(" n ", Style::default().fg(Color::DarkGray)));
+        hint_spans.push(Span::raw("line numbers  "));
+        hint_spans.push(Span::styled(" w ", Style::default().fg(Color::DarkGray)));
+        hint_spans.push(Span::raw(if app.preview_wrap { "no wrap  " } else { "wrap  " }));
+        if !app.preview_wrap {
+            hint_spans.push(Span::styled(" h/l ", Style::default().fg(Color::DarkGray)));
+            hint_spans.push(Span::raw("pan  "));
+        }
     }
     
     hint_spans.push(Span::styled(" Esc ", Style::default().fg(Color::DarkGray)));
@@ -784,107 +7523,853 @@ fn ui(frame: &mut Frame, app: &mut App) {
     hint_spans.push(Span::styled(" q ", Style::default().fg(Color::DarkGray)));
     hint_spans.push(Span::raw("quit"));
     
-    let hints = Line::from(hint_spans);
-    let hint_para = Paragraph::new(hints).block(
-        Block::default()
-            .borders(Borders::ALL)
-            .title(" Keys "),
-    );
-    frame.render_widget(hint_para, chunks[2]);
+    Line::from(hint_spans)
 }
 
-fn run_app(terminal: &mut ratatui::Terminal<CrosstermBackend<Stdout>>, mut app: App) -> io::Result<()> {
+fn run_app(terminal: &mut ratatui::Terminal<CrosstermBackend<BufWriter<Stderr>>>, app: &mut App) -> io::Result<()> {
     loop {
+        if let Some(template) = app.pending_command.take() {
+            let command = expand_command(&template, app.selected_path().as_deref());
+            app.command_output = Some(run_shell_command(terminal, &app.pane.cwd, &command)?);
+            // The command may have created, changed or removed files here.
+            app.refresh_entries_keep_selection();
+        }
+        if std::mem::take(&mut app.shell_requested) {
+            let result = open_shell(terminal, app.disk_dir())?;
+            if let Err(e) = result {
+                app.set_error(e);
+            }
+            app.refresh_entries_keep_selection();
+        }
+
+        // Pick up filesystem changes in cwd before drawing so the listing never goes stale.
+        app.poll_fs_events();
+        app.poll_dir_load(Duration::ZERO);
+        app.with_other_pane(|app| {
+            app.poll_fs_events();
+            app.poll_dir_load(Duration::ZERO);
+        });
+        app.poll_auto_preview();
+        app.poll_preview_load(Duration::ZERO);
+        app.poll_prefetch();
+        app.poll_preview_follow();
+        app.poll_dir_size();
+        app.poll_dir_counts();
+        app.with_other_pane(App::poll_dir_counts);
+        app.poll_peek();
+        app.poll_status();
+        if let Some(finder) = app.finder.as_mut() {
+            finder.poll();
+        }
+
         // Draw current state. Ratatui uses double buffering: we draw to an internal buffer,
         // then on draw() it's swapped to the terminal in one go to avoid flicker.
-        terminal.draw(|f| ui(f, &mut app))?;
+        terminal.draw(|f| ui(f, app))?;
 
         // Block until we get an event. This is why we don't need a "sleep" in the loop —
         // the thread blocks on key press.
-        if !event::poll(std::time::Duration::from_millis(100))? {
+        if !event::poll(Duration::from_millis(100))? {
             continue;
         }
-        let Event::Key(key) = event::read()? else {
-            continue;
+        let key = match event::read()? {
+            Event::Key(key) => key,
+            Event::Mouse(mouse) => {
+                // Mouse input only applies to plain browsing, not to prompts.
+                if app.mode == Mode::Browse && app.search_query.is_none() && app.finder.is_none() {
+                    app.handle_mouse(mouse);
+                }
+                continue;
+            }
+            // Resizes need nothing here: the next draw adopts the new size.
+            _ => continue,
         };
         // Only act on key *press*, not repeat (avoid moving 10 steps when you hold arrow).
         if key.kind != KeyEventKind::Press {
             continue;
         }
 
-        match key.code {
-            KeyCode::Char('q') => break,
-            KeyCode::Esc => {
-                if app.preview_path.is_some() {
-                    app.close_preview();
-                } else {
-                    break;
+        // The command output pager scrolls with j/k and closes on any other key.
+        if app.command_output.is_some() {
+            match key.code {
+                KeyCode::Char('j') | KeyCode::Down => app.scroll_command_output(1),
+                KeyCode::Char('k') | KeyCode::Up => app.scroll_command_output(-1),
+                KeyCode::PageDown | KeyCode::Char(' ') => app.scroll_command_output(app.list_viewport_height as isize),
+                KeyCode::PageUp => app.scroll_command_output(-(app.list_viewport_height as isize)),
+                _ => app.command_output = None,
+            }
+            continue;
+        }
+
+        // The help overlay scrolls with j/k and closes on Esc or the help key itself.
+        if app.help_scroll.is_some() {
+            let page = app.list_viewport_height as isize;
+            match key.code {
+                KeyCode::Char('j') | KeyCode::Down => app.scroll_help(1),
+                KeyCode::Char('k') | KeyCode::Up => app.scroll_help(-1),
+                KeyCode::PageDown | KeyCode::Char(' ') => app.scroll_help(page),
+                KeyCode::PageUp => app.scroll_help(-page),
+                KeyCode::Esc | KeyCode::Char('q') => app.help_scroll = None,
+                _ if app.keymap.get(&key_binding(key.code, key.modifiers)) == Some(&Action::Help) => {
+                    app.help_scroll = None
                 }
+                _ => {}
             }
-            KeyCode::Up => app.selection_up(),
-            KeyCode::Down => app.selection_down(),
-            KeyCode::Char('k') => {
-                if app.preview_path.is_some() {
-                    app.preview_scroll_up();
-                } else {
-                    app.selection_up();
+            continue;
+        }
+
+        // The fuzzy finder takes all keys: typing edits the query, arrows (or Ctrl-n/Ctrl-p) move
+        // through the results, Enter goes to the one highlighted and Tab also previews it.
+        if let Some(finder) = app.finder.as_mut() {
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            let page = app.list_viewport_height as isize;
+            match key.code {
+                KeyCode::Esc => app.finder = None,
+                KeyCode::Enter => app.accept_finder(false),
+                KeyCode::Tab => app.accept_finder(true),
+                KeyCode::Up => finder.move_selection(-1),
+                KeyCode::Down => finder.move_selection(1),
+                KeyCode::Char('p') if ctrl => finder.move_selection(-1),
+                KeyCode::Char('n') if ctrl => finder.move_selection(1),
+                KeyCode::PageUp => finder.move_selection(-page),
+                KeyCode::PageDown => finder.move_selection(page),
+                KeyCode::Backspace => finder.edit(|query| {
+                    query.pop();
+                }),
+                KeyCode::Char(c) if !ctrl => finder.edit(|query| query.push(c)),
+                _ => {}
+            }
+            continue;
+        }
+
+        // A confirmation prompt only listens for yes/no.
+        if let Mode::ConfirmDelete(..) = app.mode {
+            match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => app.confirm_delete(),
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => app.mode = Mode::Browse,
+                _ => {}
+            }
+            continue;
+        }
+
+        if let Mode::ConfirmOverwrite(_) = app.mode {
+            match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => app.resolve_overwrite(true),
+                KeyCode::Char('k') | KeyCode::Char('K') => app.resolve_overwrite(false),
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => app.mode = Mode::Browse,
+                _ => {}
+            }
+            continue;
+        }
+
+        if let Mode::ConfirmRename(..) = app.mode {
+            match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => {
+                    if let Mode::ConfirmRename(from, to) = std::mem::replace(&mut app.mode, Mode::Browse) {
+                        app.rename_path(from, to);
+                    }
                 }
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => app.mode = Mode::Browse,
+                _ => {}
             }
-            KeyCode::Char('j') => {
-                if app.preview_path.is_some() {
-                    app.preview_scroll_down();
-                } else {
-                    app.selection_down();
+            continue;
+        }
+
+        if let Mode::ConfirmBatchRename(_) = app.mode {
+            match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => app.confirm_batch_rename(),
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => app.mode = Mode::Browse,
+                _ => {}
+            }
+            continue;
+        }
+
+        if app.mode == Mode::ConfirmQuit {
+            match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => break,
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => app.mode = Mode::Browse,
+                _ => {}
+            }
+            continue;
+        }
+
+        // A text prompt takes all keys until submitted or cancelled.
+        if let Mode::Input(kind) = app.mode {
+            match key.code {
+                KeyCode::Esc => app.cancel_input(),
+                KeyCode::Enter => app.submit_input(),
+                KeyCode::Backspace => {
+                    app.input.pop();
                 }
+                KeyCode::Tab if kind == InputKind::GotoPath => app.complete_path_input(),
+                KeyCode::Tab if kind == InputKind::PreviewSearch => {
+                    app.preview_search_case_sensitive = !app.preview_search_case_sensitive;
+                }
+                KeyCode::Char(c) => app.input.push(c),
+                _ => {}
             }
-            KeyCode::Enter | KeyCode::Char('l') => app.enter_selected(),
-            KeyCode::Char('h') => {
-                if let Some(parent) = app.cwd.parent() {
-                    app.cwd = parent.to_path_buf();
-                    app.selected = 0;
-                    app.refresh_entries();
+            continue;
+        }
+
+        // While searching, keys edit the query instead of acting as commands.
+        if app.search_query.is_some() {
+            match key.code {
+                KeyCode::Esc => app.cancel_search(),
+                KeyCode::Enter => app.commit_search(),
+                KeyCode::Backspace => app.pop_search_char(),
+                KeyCode::Char(c) => app.push_search_char(c),
+                _ => {}
+            }
+            continue;
+        }
+
+        // With matches highlighted in the preview, n/N step through them and Esc clears them.
+        if app.preview_search.is_some() && app.preview_path.is_some() {
+            match key.code {
+                KeyCode::Char('n') => {
+                    app.cycle_preview_match(true);
+                    continue;
                 }
+                KeyCode::Char('N') => {
+                    app.cycle_preview_match(false);
+                    continue;
+                }
+                KeyCode::Esc => {
+                    app.preview_search = None;
+                    continue;
+                }
+                _ => {}
             }
-            KeyCode::Char('H') => {
-                app.show_hidden = !app.show_hidden;
-                app.refresh_entries();
+        }
+
+        // Multi-key commands: the first key is remembered in `pending_key`, and the next key
+        // either completes the command or cancels it.
+        let pending = app.pending_key.take();
+        match (pending, key.code) {
+            (Some('m'), KeyCode::Char(c)) if c.is_ascii_alphabetic() => {
+                app.set_bookmark(c);
+                continue;
             }
-            KeyCode::Char('t') => {
-                app.toggle_preview_mode();
+            (Some('\''), KeyCode::Char(c)) if c.is_ascii_alphabetic() => {
+                app.jump_to_bookmark(c);
+                continue;
             }
+            // Anything else (e.g. Esc) just cancels a pending bookmark command.
+            (Some('m' | '\''), _) => continue,
             _ => {}
         }
+
+        // Digits build up a count for the next command (`5j`); a leading 0, and any digit the
+        // config binds to an action, is left to the keymap.
+        if let KeyCode::Char(c @ '0'..='9') = key.code
+            && key.modifiers.difference(KeyModifiers::SHIFT).is_empty()
+            && (c != '0' || app.count.is_some())
+            && !app.keymap.contains_key(&key_binding(key.code, key.modifiers))
+        {
+            let digit = c as usize - '0' as usize;
+            app.count = Some((app.count.unwrap_or(0) * 10 + digit).min(999_999));
+            continue;
+        }
+        let count = app.count.take();
+        if count.is_some() && key.code == KeyCode::Esc {
+            continue;
+        }
+
+        let action = app.keymap.get(&key_binding(key.code, key.modifiers)).copied();
+        if let Some(action) = action
+            && !app.perform(action, pending, count)
+        {
+            break;
+        }
     }
     Ok(())
 }
 
+/// Command-line usage, printed by `--help`.
+const USAGE: &str = "\
+Usage: dirnav [OPTIONS] [PATH]
+
+Browse PATH (a directory), or the directory holding PATH (a file) with the file's preview open.
+
+Options:
+      --resume   Start where the last session ended
+  -a, --hidden   Show hidden files
+  -h, --help     Print this help
+  -V, --version  Print the version";
+
+/// What the command line asked for.
+enum Cli {
+    /// Browse as the arguments say.
+    Run(CliArgs),
+    /// Print `USAGE` and exit.
+    Help,
+    /// Print the version and exit.
+    Version,
+}
+
+/// The arguments of a run.
+#[derive(Default)]
+struct CliArgs {
+    /// Where to start: a directory, or a file to select and preview.
+    path: Option<PathBuf>,
+    resume: bool,
+    hidden: bool,
+}
+
+/// Parse the arguments after the program name, or say what's wrong with them. `--help` and
+/// `--version` win over anything after them, as they end the run.
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    let mut cli = CliArgs::default();
+    let mut only_paths = false;
+    for arg in args {
+        match arg.as_str() {
+            "--" if !only_paths => only_paths = true,
+            "--resume" if !only_paths => cli.resume = true,
+            "-a" | "--hidden" if !only_paths => cli.hidden = true,
+            "-h" | "--help" if !only_paths => return Ok(Cli::Help),
+            "-V" | "--version" if !only_paths => return Ok(Cli::Version),
+            flag if flag.starts_with('-') && flag.len() > 1 && !only_paths => {
+                return Err(format!("unknown option {} (see --help)", flag));
+            }
+            _ if cli.path.is_some() => return Err("only one path can be given (see --help)".to_string()),
+            path => cli.path = Some(expand_tilde(path)),
+        }
+    }
+    Ok(Cli::Run(cli))
+}
+
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
 
-    // Start in current directory (or fallback to "/" on Unix, "C:\" on Windows if needed).
-    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-    let app = App::new(cwd);
+    // Bad arguments are reported before the terminal is taken over.
+    let cli = match parse_args(std::env::args().skip(1)) {
+        Ok(Cli::Run(cli)) => cli,
+        Ok(Cli::Help) => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        Ok(Cli::Version) => {
+            println!("dirnav {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("dirnav: {}", e);
+            std::process::exit(2);
+        }
+    };
+    // A file opens in its directory, selected and previewed.
+    let start = match cli.path.as_ref().map(fs::canonicalize) {
+        None => None,
+        Some(Ok(path)) if path.is_dir() => Some((path, None)),
+        Some(Ok(path)) => {
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+            path.parent().map(|dir| (dir.to_path_buf(), name))
+        }
+        Some(Err(e)) => {
+            let path = cli.path.as_deref().unwrap_or(std::path::Path::new(""));
+            eprintln!("dirnav: {}: {}", path.display(), describe_io_error(&e));
+            std::process::exit(1);
+        }
+    };
+
+    // A broken config file is reported and left alone: we run with defaults and don't overwrite it.
+    let (mut config, config_error) = match load_config() {
+        Ok(config) => (config, None),
+        Err(e) => (Config::default(), Some(e)),
+    };
+
+    // Start at the path given, else where the last session ended when asked to (and it still
+    // exists), else in the configured directory if it exists, else the current directory.
+    let resume = start.is_none() && (config.resume_last_dir || cli.resume);
+    let last_dir = if resume { load_last_dir() } else { None };
+    let cwd = start
+        .as_ref()
+        .map(|(dir, _)| dir.clone())
+        .or_else(|| last_dir.as_ref().map(|(dir, _)| dir.clone()))
+        .or_else(|| {
+            config
+                .start_dir
+                .as_ref()
+                .map(|dir| expand_tilde(&dir.to_string_lossy()))
+                .filter(|dir| dir.is_dir())
+        })
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_else(|| PathBuf::from("."));
+    // `--hidden` is for this run only; the config keeps what it had unless `H` changes it.
+    let configured_hidden = config.show_hidden;
+    config.show_hidden |= cli.hidden;
+    let mut app = App::new(cwd, &config, load_bookmarks());
+    config.show_hidden = configured_hidden;
+    if let Some((_, Some(name))) = start {
+        app.select_when_loaded(name, true);
+    } else if let Some((_, Some(name))) = last_dir {
+        app.select_when_loaded(name, false);
+    }
+    if let Some(e) = config_error.as_ref() {
+        app.set_error(format!("Ignoring config.toml: {}", e));
+    }
+
+    // Ask the terminal which graphics protocol it speaks. The query switches raw mode on and off
+    // by itself, so it has to happen before we enable it. Half blocks are too coarse to be worth
+    // it, so those terminals get the text summary for images instead. tmux drops the query unless
+    // passthrough is enabled, and an unanswered query leaves a thread reading stdin that would
+    // eat our key presses, so we don't ask there. The query goes over stdout, so neither do we
+    // when that is being captured by a shell wrapper.
+    if std::env::var_os("TMUX").is_none() && io::stdout().is_terminal() {
+        app.image_picker = Picker::from_query_stdio()
+            .ok()
+            .filter(|picker| picker.protocol_type() != ProtocolType::Halfblocks);
+    }
 
     // 1) Set up terminal: raw mode + alternate screen.
     //    Raw mode = we get key events instead of line-buffered input.
     //    Alternate screen = we draw on a separate buffer; when we exit, the previous
     //    terminal content is restored (no "leftover" UI).
+    //    Mouse capture = clicks and wheel scrolls arrive as events instead of terminal selection.
     enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
+    //    The UI goes to stderr so `cd "$(dirnav)"` captures only the emitted path.
+    let mut stderr = BufWriter::new(io::stderr());
+    execute!(stderr, EnterAlternateScreen, EnableMouseCapture)?;
 
     // 2) CrosstermBackend lets ratatui draw using crossterm's terminal API.
-    let backend = CrosstermBackend::new(stdout);
+    let backend = CrosstermBackend::new(stderr);
     let mut terminal = ratatui::Terminal::new(backend)?;
 
     // 3) Run the app loop. When it returns, restore terminal state.
-    let result = run_app(&mut terminal, app);
+    let result = run_app(&mut terminal, &mut app);
 
     // 4) Restore terminal so the shell looks normal again.
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
     terminal.show_cursor()?;
 
     result?;
+
+    // Quitting with Q hands the path to whoever ran us, e.g. a shell function that cds there. It
+    // goes out first, so failing to save the state below can't keep the shell from getting it.
+    if let Some(path) = app.selected_output.as_ref() {
+        println!("{}", path.display());
+    }
+
+    // Remember where we were. Inside an archive, that is the archive itself.
+    let (last_dir, selected) = match app.pane.archive.as_ref() {
+        Some(archive) => (archive.parent().unwrap_or(archive), archive.file_name().and_then(|n| n.to_str())),
+        None => (
+            app.pane.cwd.as_path(),
+            app.pane.entries.get(app.pane.selected).map(|e| e.name.as_str()).filter(|&n| n != ".."),
+        ),
+    };
+    if let Err(e) = save_last_dir(last_dir, selected) {
+        eprintln!("dirnav: couldn't save the last directory: {}", describe_io_error(&e));
+    }
+
+    // Remember this session's choices for next time.
+    if config_error.is_none() {
+        let loaded = config.clone();
+        config.theme = app.theme_name.clone();
+        if app.show_hidden != (configured_hidden || cli.hidden) {
+            config.show_hidden = app.show_hidden;
+        }
+        config.sort_mode = app.sort_mode;
+        config.case_sensitive_sort = app.case_sensitive_sort;
+        config.group_dirs_first = app.group_dirs_first;
+        config.preview_limit_kb = app.preview_limit / 1024;
+        config.preview_ratio = app.preview_ratio;
+        // Rewriting an unchanged file would only lose the comments and layout of a hand-edited one.
+        // The session is over either way; a config that can't be written isn't worth failing for.
+        if config != loaded
+            && let Err(e) = save_config(&config)
+        {
+            eprintln!("dirnav: couldn't save config.toml: {}", describe_io_error(&e));
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A plain entry named `name`; a directory if `is_dir`.
+    fn entry(name: &str, is_dir: bool) -> DirEntry {
+        DirEntry { name: name.to_string(), is_dir, ..parent_entry() }
+    }
+
+    /// An app whose focused pane lists `count` files, with `wrap_selection` as given.
+    fn app_with_entries(count: usize, wrap: bool) -> App {
+        let mut app = App::new(std::env::temp_dir(), &Config::default(), HashMap::new());
+        app.pane.dir_load = None;
+        app.pane.entries = (0..count).map(|i| entry(&format!("file{}", i), false)).collect();
+        app.pane.selected = 0;
+        app.wrap_selection = wrap;
+        app
+    }
+
+    #[test]
+    fn selection_up_stops_at_top() {
+        let mut app = app_with_entries(3, false);
+        app.selection_up();
+        assert_eq!(app.pane.selected, 0);
+    }
+
+    #[test]
+    fn selection_up_wraps_to_bottom() {
+        let mut app = app_with_entries(3, true);
+        app.selection_up();
+        assert_eq!(app.pane.selected, 2);
+    }
+
+    #[test]
+    fn selection_down_stops_at_bottom() {
+        let mut app = app_with_entries(3, false);
+        app.pane.selected = 2;
+        app.selection_down();
+        assert_eq!(app.pane.selected, 2);
+    }
+
+    #[test]
+    fn selection_down_wraps_to_top() {
+        let mut app = app_with_entries(3, true);
+        app.pane.selected = 2;
+        app.selection_down();
+        assert_eq!(app.pane.selected, 0);
+    }
+
+    #[test]
+    fn selection_moves_on_empty_list() {
+        for wrap in [false, true] {
+            let mut app = app_with_entries(0, wrap);
+            app.selection_up();
+            assert_eq!(app.pane.selected, 0);
+            app.selection_down();
+            assert_eq!(app.pane.selected, 0);
+        }
+    }
+
+    #[test]
+    fn reset_view_goes_back_to_the_configured_settings() {
+        let config = Config { sort_mode: SortMode::Size, group_dirs_first: false, show_hidden: true, ..Config::default() };
+        let mut app = App::new(std::env::temp_dir(), &config, HashMap::new());
+        app.sort_mode = SortMode::Modified;
+        app.sort_reverse = true;
+        app.group_dirs_first = true;
+        app.show_hidden = false;
+        app.natural_sort = false;
+        app.hidden_placement = HiddenPlacement::Bottom;
+        app.respect_gitignore = true;
+        app.filter = Some("*.rs".to_string());
+        app.reset_view();
+        assert!(app.sort_mode == SortMode::Size);
+        assert!(!app.sort_reverse && !app.group_dirs_first && app.show_hidden);
+        assert!(app.natural_sort && app.hidden_placement == HiddenPlacement::Interleave && !app.respect_gitignore);
+        assert_eq!(app.filter, None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn expand_command_quotes_the_path_and_keeps_escaped_percents() {
+        let path = std::path::Path::new("/tmp/it's here");
+        assert_eq!(expand_command("wc -l %", Some(path)), "wc -l '/tmp/it'\\''s here'");
+        assert_eq!(expand_command("date +%%s > %.txt", Some(path)), "date +%s > '/tmp/it'\\''s here'.txt");
+        assert_eq!(expand_command("printf '%%d%%%' 5", None), "printf '%d%%' 5");
+        assert_eq!(expand_command("echo 100%", None), "echo 100%");
+    }
+
+    /// `parse_args` over `args`, with the arguments of a run unpacked as (path, resume, hidden).
+    fn parse(args: &[&str]) -> Result<Option<(Option<PathBuf>, bool, bool)>, String> {
+        parse_args(args.iter().map(|a| a.to_string())).map(|cli| match cli {
+            Cli::Run(cli) => Some((cli.path, cli.resume, cli.hidden)),
+            Cli::Help | Cli::Version => None,
+        })
+    }
+
+    #[test]
+    fn parse_args_reads_flags_and_a_path() {
+        assert_eq!(parse(&[]), Ok(Some((None, false, false))));
+        assert_eq!(parse(&["--resume", "-a"]), Ok(Some((None, true, true))));
+        assert_eq!(parse(&["src", "--hidden"]), Ok(Some((Some(PathBuf::from("src")), false, true))));
+        assert!(matches!(parse_args(["-a", "--help", "--bogus"].map(String::from).into_iter()), Ok(Cli::Help)));
+        assert!(matches!(parse_args(["-V"].map(String::from).into_iter()), Ok(Cli::Version)));
+    }
+
+    #[test]
+    fn parse_args_rejects_bad_input() {
+        assert!(parse(&["--bogus"]).unwrap_err().contains("--bogus"));
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["one", "two"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_takes_everything_after_double_dash_as_a_path() {
+        assert_eq!(parse(&["--", "--help"]), Ok(Some((Some(PathBuf::from("--help")), false, false))));
+        assert_eq!(parse(&["-a", "--", "-"]), Ok(Some((Some(PathBuf::from("-")), false, true))));
+        assert!(parse(&["--", "-a", "b"]).is_err());
+        // A lone `-` is a path even before `--`.
+        assert_eq!(parse(&["-"]), Ok(Some((Some(PathBuf::from("-")), false, false))));
+    }
+
+    /// A WAV header with a `fmt ` chunk giving `byte_rate` and a `data` chunk of `data_len` bytes,
+    /// after a padded odd-length chunk of something else.
+    fn wav_header(byte_rate: u32, data_len: u32) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        bytes.extend(b"LIST\x03\0\0\0abc\0");
+        bytes.extend(b"fmt \x10\0\0\0\x01\0\x02\0\x44\xac\0\0");
+        bytes.extend(byte_rate.to_le_bytes());
+        bytes.extend(b"\x04\0\x10\0data");
+        bytes.extend(data_len.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn wav_duration_reads_the_chunks() {
+        assert_eq!(wav_duration(&wav_header(176_400, 176_400 * 3)), Some(Duration::from_secs(3)));
+        assert_eq!(wav_duration(&wav_header(0, 1000)), None);
+        // Huge lengths are just long durations, not overflows.
+        assert!(wav_duration(&wav_header(1, u32::MAX)).is_some());
+        let header = wav_header(176_400, 100);
+        for len in 0..header.len() {
+            assert_eq!(wav_duration(&header[..len]), None, "cut at {len}");
+        }
+        // A chunk claiming to run past the end stops the walk.
+        let mut runaway = b"RIFF\0\0\0\0WAVEjunk\xff\xff\xff\xff".to_vec();
+        runaway.extend(&wav_header(176_400, 100)[12..]);
+        assert_eq!(wav_duration(&runaway), None);
+        assert_eq!(wav_duration(b"RIFX\0\0\0\0WAVE"), None);
+    }
+
+    #[test]
+    fn flac_duration_reads_streaminfo() {
+        // 44.1 kHz, stereo, 16-bit, 441000 samples.
+        let bits: u64 = (44_100 << 44) | (1 << 41) | (15 << 36) | 441_000;
+        let mut bytes = b"fLaC\0\0\0\x22".to_vec();
+        bytes.extend([0; 10]);
+        bytes.extend(bits.to_be_bytes());
+        assert_eq!(flac_duration(&bytes), Some(Duration::from_secs(10)));
+        assert_eq!(flac_duration(&bytes[..25]), None);
+        let mut no_rate = bytes.clone();
+        no_rate[18..26].copy_from_slice(&441_000u64.to_be_bytes());
+        assert_eq!(flac_duration(&no_rate), None);
+        // The first block has to be STREAMINFO.
+        bytes[4] = 0x04;
+        assert_eq!(flac_duration(&bytes), None);
+        assert_eq!(flac_duration(b"OggS"), None);
+    }
+
+    #[test]
+    fn describe_executable_reads_headers() {
+        let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+        elf.resize(16, 0);
+        elf.extend([3, 0, 0x3e, 0]);
+        assert_eq!(describe_executable(&elf).as_deref(), Some("ELF 64-bit shared object for x86-64"));
+        let mut big_endian = b"\x7fELF\x01\x02\x01".to_vec();
+        big_endian.resize(16, 0);
+        big_endian.extend([0, 2, 0, 0x08]);
+        assert_eq!(describe_executable(&big_endian).as_deref(), Some("ELF 32-bit executable for MIPS"));
+        assert_eq!(describe_executable(&elf[..18]), None);
+
+        let mut pe = b"MZ".to_vec();
+        pe.resize(0x3c, 0);
+        pe.extend(0x40u32.to_le_bytes());
+        pe.extend(b"PE\0\0\x64\x86");
+        pe.resize(0x40 + 22, 0);
+        pe.extend(0x2000u16.to_le_bytes());
+        assert_eq!(describe_executable(&pe).as_deref(), Some("PE DLL for x86-64"));
+        let mut stray = pe.clone();
+        stray[0x3c..0x40].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(describe_executable(&stray), None);
+
+        let mut macho = 0xfeed_facfu32.to_le_bytes().to_vec();
+        macho.extend(0x0100_000cu32.to_le_bytes());
+        macho.extend([0; 4]);
+        macho.extend(2u32.to_le_bytes());
+        assert_eq!(describe_executable(&macho).as_deref(), Some("Mach-O 64-bit executable for arm64"));
+
+        assert_eq!(describe_executable(b""), None);
+        assert_eq!(describe_executable(b"not an executable at all"), None);
+    }
+
+    #[test]
+    fn format_size_picks_the_unit() {
+        assert_eq!(format_size(0), "0B");
+        assert_eq!(format_size(1023), "1023B");
+        assert_eq!(format_size(1024), "1.0K");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0M");
+        assert_eq!(format_size(u64::MAX), "16384.0P");
+    }
+
+    #[test]
+    fn csv_table_lines_leaves_out_cut_rows() {
+        let cell_text = |line: &Line| line.spans[0].content.trim().to_string();
+        let tail = csv_table_lines("a-very-long-partial-cell,1\nb,2\ncc,3\n", b',', true, false);
+        assert_eq!(tail.iter().map(cell_text).collect::<Vec<_>>(), ["b", "cc"]);
+        // The partial row doesn't widen the column either.
+        assert_eq!(tail[0].spans[0].content, "b ");
+
+        let head = csv_table_lines("name,n\nb,2\ncc,3\ndd", b',', false, true);
+        assert_eq!(head.iter().map(cell_text).collect::<Vec<_>>(), ["name", "b", "cc"]);
+        assert!(head[0].spans[0].style.add_modifier.contains(Modifier::BOLD));
+    }
+
+    #[test]
+    fn natural_cmp_orders_mixed_names() {
+        use std::cmp::Ordering::{Greater, Less};
+        let cases = [
+            ("file1", "file2", false, Less),
+            ("file2", "file10", false, Less),
+            ("file10", "file9", false, Greater),
+            ("a1", "a01", false, Less),
+            ("file007", "file7", false, Greater),
+            ("file010", "file9", false, Greater),
+            ("v2.9", "v2.10", false, Less),
+            ("x99999999999999999999999", "x100000000000000000000000", false, Less),
+            ("x100000000000000000000001", "x100000000000000000000000", false, Greater),
+            ("Apple", "banana", false, Less),
+            ("Apple", "banana", true, Less),
+            ("apple", "Banana", false, Less),
+            ("apple", "Banana", true, Greater),
+            ("README", "readme", false, Less),
+            ("readme", "README", false, Greater),
+            ("äa", "Äb", false, Less),
+            ("Äb", "äa", true, Less),
+            ("file", "file1", false, Less),
+        ];
+        for (a, b, case_sensitive, expected) in cases {
+            assert_eq!(natural_cmp(a, b, case_sensitive), expected, "{a:?} vs {b:?}, case_sensitive: {case_sensitive}");
+        }
+    }
+
+    #[test]
+    fn natural_cmp_is_a_total_order() {
+        let names = [
+            "a", "A", "a1", "a01", "a001", "A1", "a2", "a10", "a1b", "a01b", "b", "B", "ä", "Ä", "10", "9", "010",
+            "", "x18446744073709551616", "x18446744073709551615",
+        ];
+        for case_sensitive in [false, true] {
+            for a in names {
+                for b in names {
+                    let ordering = natural_cmp(a, b, case_sensitive);
+                    assert_eq!(ordering == std::cmp::Ordering::Equal, a == b, "{a:?} vs {b:?}");
+                    assert_eq!(ordering.reverse(), natural_cmp(b, a, case_sensitive), "{a:?} vs {b:?}");
+                }
+            }
+            let mut sorted = names.to_vec();
+            sorted.sort_by(|a, b| natural_cmp(a, b, case_sensitive));
+            for (i, a) in sorted.iter().enumerate() {
+                for b in &sorted[i + 1..] {
+                    assert_eq!(natural_cmp(a, b, case_sensitive), std::cmp::Ordering::Less, "{a:?} vs {b:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn detect_syntax_reads_shebang_without_extension() {
+        let syntax = detect_syntax(std::path::Path::new("scripts/deploy"), "#!/bin/sh\necho hi\n");
+        assert_eq!(syntax.name, "Bourne Again Shell (bash)");
+    }
+
+    #[test]
+    fn detect_syntax_falls_back_to_file_name() {
+        let cases = [
+            ("Makefile", "all:\n\tcargo build\n", "Makefile"),
+            // The file name wins over the first line.
+            ("Makefile", "#!/usr/bin/env python3\n", "Makefile"),
+            ("Dockerfile", "FROM alpine\nRUN apk add git\n", "Bourne Again Shell (bash)"),
+            ("Justfile", "build:\n    cargo build\n", "Makefile"),
+            ("notes", "just some words\n", "Plain Text"),
+        ];
+        for (name, content, expected) in cases {
+            assert_eq!(detect_syntax(std::path::Path::new(name), content).name, expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn fullscreen_on_a_directory_stays_put() {
+        let mut app = app_with_entries(0, false);
+        let name = format!("dirnav-test-fullscreen-{}", std::process::id());
+        fs::create_dir_all(app.pane.cwd.join(&name)).unwrap();
+        app.pane.entries = vec![entry(&name, true)];
+        let cwd = app.pane.cwd.clone();
+        app.toggle_preview_fullscreen();
+        let _ = fs::remove_dir(cwd.join(&name));
+        assert_eq!(app.pane.cwd, cwd);
+        assert!(app.preview_path.is_none() && !app.preview_fullscreen);
+    }
+
+    #[test]
+    fn preview_scroll_limit_keeps_last_page_full() {
+        assert_eq!(preview_scroll_limit(100, 20), 80);
+        assert_eq!(preview_scroll_limit(21, 20), 1);
+        assert_eq!(preview_scroll_limit(20, 20), 0);
+        assert_eq!(preview_scroll_limit(5, 20), 0);
+        assert_eq!(preview_scroll_limit(0, 20), 0);
+        assert_eq!(preview_scroll_limit(10, 0), 10);
+    }
+
+    #[test]
+    fn preview_scroll_down_pins_to_last_page() {
+        let mut app = app_with_entries(0, false);
+        app.preview_content = Some((0..100).map(|i| Line::from(i.to_string())).collect());
+        app.preview_scroll_max = preview_scroll_limit(100, 20);
+        for _ in 0..75 {
+            app.preview_scroll_down(1);
+        }
+        assert_eq!(app.preview_scroll, 75);
+        for _ in 0..75 {
+            app.preview_scroll_down(1);
+        }
+        assert_eq!(app.preview_scroll, 80);
+
+        // Content shorter than the panel doesn't scroll at all.
+        app.preview_scroll = 0;
+        app.preview_scroll_max = preview_scroll_limit(100, 150);
+        app.preview_scroll_down(1);
+        assert_eq!(app.preview_scroll, 0);
+    }
+
+    #[test]
+    fn preview_scroll_down_by_a_count_stops_at_last_page() {
+        let mut app = app_with_entries(0, false);
+        app.preview_content = Some((0..100).map(|i| Line::from(i.to_string())).collect());
+        app.preview_scroll_max = preview_scroll_limit(100, 20);
+        app.preview_scroll_down(30);
+        assert_eq!(app.preview_scroll, 30);
+        app.preview_scroll_down(999_999);
+        assert_eq!(app.preview_scroll, 80);
+    }
+
+    #[test]
+    fn goto_preview_line_counts_lines_of_the_file_when_some_are_cut() {
+        let path = std::env::temp_dir().join(format!("dirnav-test-long-lines-{}.txt", std::process::id()));
+        let text: String = (1..=40).map(|i| format!("line{} {}\n", i, "x".repeat(if i % 3 == 0 { 50 } else { 5 }))).collect();
+        fs::write(&path, text).unwrap();
+        let opts = PreviewOptions { max_line_length: 20, ..app_with_entries(0, false).preview_options() };
+        let loaded = load_file_preview(&path, opts);
+        let _ = fs::remove_file(&path);
+        assert!(loaded.lines_cut);
+        assert_eq!(loaded.content.len(), 40);
+
+        let mut app = app_with_entries(0, false);
+        app.preview_content = Some(loaded.content);
+        app.preview_scroll_max = preview_scroll_limit(40, 10);
+        app.preview_goto_line(12, false);
+        let line = &app.preview_content.as_ref().unwrap()[app.preview_scroll];
+        assert!(lines_to_plain_text(std::slice::from_ref(line)).starts_with("line12 "));
+        app.goto_preview_line("21");
+        let (highlighted, _) = app.preview_line_highlight.unwrap();
+        let line = &app.preview_content.as_ref().unwrap()[highlighted];
+        assert!(lines_to_plain_text(std::slice::from_ref(line)).starts_with("line21 "));
+    }
+
+    #[test]
+    fn decorated_preview_keeps_its_text_for_copying() {
+        let path = std::env::temp_dir().join(format!("dirnav-test-plain-text-{}.rs", std::process::id()));
+        fs::write(&path, "fn main() {\n\tlet x = 1;  \n}\n").unwrap();
+        let opts =
+            PreviewOptions { line_numbers: true, show_whitespace: true, ..app_with_entries(0, false).preview_options() };
+        let loaded = load_file_preview(&path, opts);
+        let _ = fs::remove_file(&path);
+        assert!(lines_to_plain_text(&loaded.content).starts_with("1 │ fn·main()"));
+        assert_eq!(loaded.plain_text.as_deref(), Some("fn main() {\n\tlet x = 1;  \n}"));
+    }
+}